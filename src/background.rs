@@ -0,0 +1,49 @@
+//! Running blocking work off an async executor, see [`crate::FileConvertBuilder::convert_async`].
+
+use std::{future::Future, panic::AssertUnwindSafe, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll, Waker}};
+
+/// The outcome of the work, and the task waiting for it.
+struct Shared<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Resolves once the work spawned by [`spawn`] is done, with the panic if it panicked.
+pub(crate) struct Background<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// Runs `work` on a thread of its own, so the executor's threads are never blocked. Works with
+/// any executor, it only needs the waker. Dropping the future doesn't stop the work.
+pub(crate) fn spawn<T, F>(work: F) -> anyhow::Result<Background<T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+    let thread_shared = shared.clone();
+    std::thread::Builder::new().name("phase-change-convert".to_string()).spawn(move || {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(work));
+        let mut shared = thread_shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    })?;
+    Ok(Background { shared })
+}
+
+impl<T> Future for Background<T> {
+    type Output = std::thread::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
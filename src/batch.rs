@@ -0,0 +1,582 @@
+//! Converting many files with the same settings.
+
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}};
+
+use crate::{converters::image::ImageFileType, error::PathContext, get_extension_for_type, glob, json::JsonValue, progress::Progress, sha256, ConversionReport, FileConvertBuilder, FileType};
+
+/// Qualities tried in turn when a file doesn't fit in [`BatchConvertBuilder::total_size_budget`].
+const BUDGET_QUALITIES: [u8; 3] = [60, 40, 20];
+
+/// Converts every file matched by its sources using the settings of a [`FileConvertBuilder`].
+///
+/// The source type of the settings is used for every file if it was set, otherwise each file's
+/// type is inferred from its extension. The output path of the settings is ignored: outputs go
+/// next to their inputs, or below [`BatchConvertBuilder::output_dir`] keeping the directory
+/// structure relative to each source, unless [`BatchConvertBuilder::batch_name_fn`] names them.
+pub struct BatchConvertBuilder {
+    settings: FileConvertBuilder,
+    files: Vec<PathBuf>,
+    globs: Vec<String>,
+    dirs: Vec<(PathBuf, bool)>,
+    excludes: Vec<String>,
+    output_dir: Option<PathBuf>,
+    name_fn: Option<Box<NameFn>>,
+    size_budget: Option<u64>,
+    skip_if_output_newer: bool,
+    source_manifest: Option<PathBuf>,
+    progress: Arc<Progress>,
+    on_error: ErrorPolicy,
+}
+
+/// What a batch does when a file fails to convert, see [`BatchConvertBuilder::on_error`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stops the batch and returns the error.
+    #[default]
+    Abort,
+    /// Goes on with the next file, listing the failure in [`BatchReport::failed`].
+    Skip,
+    /// Converts the file again up to this many more times before skipping it.
+    Retry(u32),
+}
+
+type NameFn = dyn Fn(&Path) -> PathBuf + Send + Sync;
+
+/// The results of a batch conversion, in the order the files were converted.
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    pub converted: Vec<ConversionReport>,
+    /// Files left out because they didn't fit in the [`BatchConvertBuilder::total_size_budget`].
+    pub skipped: Vec<PathBuf>,
+    /// Files whose output was newer or whose source didn't change, see
+    /// [`BatchConvertBuilder::skip_if_output_newer`] and
+    /// [`BatchConvertBuilder::skip_if_source_unchanged`].
+    pub up_to_date: Vec<PathBuf>,
+    /// Files that failed to convert with their error, see [`BatchConvertBuilder::on_error`].
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl BatchReport {
+    /// Total size of every output.
+    pub fn total_output_size(&self) -> u64 {
+        self.converted.iter().map(|report| report.output_size).sum()
+    }
+
+    /// Prints a summary of the failed files, if there are any.
+    pub(crate) fn print_failures(&self, total: usize) {
+        if !self.failed.is_empty() {
+            println!("{} of {} files failed to convert:", self.failed.len(), total);
+            for (path, error) in &self.failed {
+                println!("  {}: {}", path.display(), error);
+            }
+        }
+    }
+}
+
+/// A file picked up by one of the batch sources.
+struct BatchInput {
+    path: PathBuf,
+    /// Path of the file relative to the source that matched it.
+    relative: PathBuf,
+}
+
+impl BatchConvertBuilder {
+    pub fn new(settings: FileConvertBuilder) -> Self {
+        Self {
+            settings,
+            files: Vec::new(),
+            globs: Vec::new(),
+            dirs: Vec::new(),
+            excludes: Vec::new(),
+            output_dir: None,
+            name_fn: None,
+            size_budget: None,
+            skip_if_output_newer: false,
+            source_manifest: None,
+            progress: Arc::default(),
+            on_error: ErrorPolicy::Abort,
+        }
+    }
+
+    pub fn add_file(&mut self, path: PathBuf) -> &mut Self {
+        self.files.push(path);
+        self
+    }
+
+    /// Adds every file matching a wildcard pattern like `assets/**/*.png`. `*` matches within
+    /// a path component, `?` a single character and `**` any number of directories.
+    pub fn add_glob(&mut self, pattern: &str) -> &mut Self {
+        self.globs.push(pattern.to_string());
+        self
+    }
+
+    /// Adds every file in `dir` of the source type, or of any known type if none was set.
+    pub fn add_dir(&mut self, dir: PathBuf, recursive: bool) -> &mut Self {
+        self.dirs.push((dir, recursive));
+        self
+    }
+
+    /// Skips files matching `pattern`. Patterns without a path separator are matched against
+    /// the file name only, so `*_thumb.png` skips thumbnails in every directory.
+    pub fn exclude(&mut self, pattern: &str) -> &mut Self {
+        self.excludes.push(pattern.to_string());
+        self
+    }
+
+    pub fn output_dir(&mut self, dir: PathBuf) -> &mut Self {
+        self.output_dir = Some(dir);
+        self
+    }
+
+    /// Picks the output path of every input instead of swapping its extension. Relative paths
+    /// returned by `name_fn` are taken relative to the output directory if one was set.
+    pub fn batch_name_fn<F>(&mut self, name_fn: F) -> &mut Self
+    where
+        F: Fn(&Path) -> PathBuf + Send + Sync + 'static,
+    {
+        self.name_fn = Some(Box::new(name_fn));
+        self
+    }
+
+    /// Caps the total size of the outputs. A file that would go over the budget is converted
+    /// again at lower qualities if the target format has a quality setting, and if it still
+    /// doesn't fit the batch stops there. Files that were left out are listed in
+    /// [`BatchReport::skipped`].
+    pub fn total_size_budget(&mut self, bytes: u64) -> &mut Self {
+        self.size_budget = Some(bytes);
+        self
+    }
+
+    /// Leaves out files whose output was modified after them, like an incremental build would.
+    /// They are listed in [`BatchReport::up_to_date`] and their outputs count towards the
+    /// [`Self::total_size_budget`].
+    pub fn skip_if_output_newer(&mut self, enabled: bool) -> &mut Self {
+        self.skip_if_output_newer = enabled;
+        self
+    }
+
+    /// Leaves out files whose contents are the same as when they were last converted, even if
+    /// they were touched since. The SHA-256 of every source converted is recorded in the JSON
+    /// file at `manifest_path`, created if it doesn't exist, along with its output and the
+    /// settings used, so a file is converted again if any of those changed or its output is
+    /// gone. Sources are looked up by their path as the batch found them. Skipped files are
+    /// listed in [`BatchReport::up_to_date`] like with [`Self::skip_if_output_newer`].
+    pub fn skip_if_source_unchanged(&mut self, manifest_path: PathBuf) -> &mut Self {
+        self.source_manifest = Some(manifest_path);
+        self
+    }
+
+    /// Calls `callback` with the fraction of the whole batch done, from 0.0 to 1.0: the files
+    /// done plus the progress of the current one, see [`FileConvertBuilder::on_progress`],
+    /// over the number of files. It never goes down and ends at 1.0, also when the
+    /// [`Self::total_size_budget`] stops the batch early. Any per-file callback of the settings
+    /// is still called.
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(f32) + Send + Sync + 'static,
+    {
+        self.progress = Arc::new(Progress::new(Arc::new(callback)));
+        self
+    }
+
+    /// Decides what happens when a file fails to convert, e.g. because it is corrupt. By
+    /// default the batch stops with the error. With [`ErrorPolicy::Skip`] or once the retries of
+    /// [`ErrorPolicy::Retry`] are used up the file is listed in [`BatchReport::failed`] and the
+    /// batch goes on, printing a summary of the failures at the end.
+    pub fn on_error(&mut self, policy: ErrorPolicy) -> &mut Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// What [`Self::skip_if_source_unchanged`] records of the settings: the target type and the
+    /// options, see [`crate::ConvertOptions::to_json_value`].
+    fn manifest_settings(&self) -> JsonValue {
+        JsonValue::object([
+            ("to", get_extension_for_type(&self.settings.to.0).into()),
+            ("options", self.settings.options.to_json_value()),
+        ])
+    }
+
+    fn source_type(&self, path: &Path) -> FileType {
+        match self.settings.from.0 {
+            FileType::Unknown => FileType::from_path(path).unwrap_or_default(),
+            file_type => file_type,
+        }
+    }
+
+    fn collect_inputs(&self) -> anyhow::Result<Vec<BatchInput>> {
+        let mut inputs = Vec::new();
+
+        for path in &self.files {
+            inputs.push(BatchInput {
+                path: path.clone(),
+                relative: PathBuf::from(path.file_name().unwrap_or_default()),
+            });
+        }
+
+        for pattern in &self.globs {
+            for (path, relative) in glob::expand(pattern)? {
+                inputs.push(BatchInput { path, relative });
+            }
+        }
+
+        for (dir, recursive) in &self.dirs {
+            for relative in glob::walk(dir, if *recursive { usize::MAX } else { 1 })? {
+                let path = dir.join(&relative);
+                let matches_type = match self.settings.from.0 {
+                    FileType::Unknown => FileType::from_path(&path).is_some(),
+                    file_type => FileType::from_path(&path) == Some(file_type),
+                };
+                if matches_type {
+                    inputs.push(BatchInput { path, relative });
+                }
+            }
+        }
+
+        inputs.retain(|input| !self.excludes.iter().any(|pattern| glob::matches(pattern, &input.path)));
+
+        let mut seen = std::collections::HashSet::new();
+        inputs.retain(|input| seen.insert(input.path.clone()));
+        Ok(inputs)
+    }
+
+    fn output_path(&self, input: &BatchInput) -> PathBuf {
+        if let Some(name_fn) = &self.name_fn {
+            let output = name_fn(&input.path);
+            return match &self.output_dir {
+                Some(dir) if output.is_relative() => dir.join(output),
+                _ => output,
+            };
+        }
+
+        let to = self.settings.to.0;
+        match &self.output_dir {
+            Some(dir) => dir.join(&input.relative).with_extension(get_extension_for_type(&to)),
+            None => input.path.with_extension(get_extension_for_type(&to)),
+        }
+    }
+
+    /// Converts the file again at lower qualities until it fits in `remaining` bytes. Returns
+    /// `None` if it can't be made small enough.
+    fn fit_budget(&mut self, registry: &crate::ConverterRegistry, from: FileType, input: &Path, output: &Path, mut converted: ConversionReport, remaining: u64) -> anyhow::Result<Option<ConversionReport>> {
+        let has_quality = matches!(self.settings.to.0, FileType::Image(ImageFileType::JPEG | ImageFileType::AVIF));
+        let quality = self.settings.options.quality;
+
+        for lower in BUDGET_QUALITIES.into_iter().filter(|lower| quality.is_none_or(|quality| *lower < quality)) {
+            if converted.output_size <= remaining || !has_quality {
+                break;
+            }
+            self.settings.options.quality = Some(lower);
+            let result = self.settings.convert_file(registry, from, input, output);
+            self.settings.options.quality = quality;
+            converted = result?;
+        }
+
+        Ok((converted.output_size <= remaining).then_some(converted))
+    }
+
+    /// Converts a single file, returning `None` if it doesn't fit in the `remaining` budget.
+    fn convert_input(&mut self, registry: &crate::ConverterRegistry, input: &Path, output: &Path, remaining: Option<u64>) -> anyhow::Result<Option<ConversionReport>> {
+        let converted = self.convert_single(registry, input, output)?;
+        match remaining {
+            Some(remaining) => self.fit_budget(registry, self.source_type(input), input, output, converted, remaining),
+            None => Ok(Some(converted)),
+        }
+    }
+
+    /// Converts a single file with the settings as they are.
+    fn convert_single(&self, registry: &crate::ConverterRegistry, input: &Path, output: &Path) -> anyhow::Result<ConversionReport> {
+        let from = self.source_type(input);
+        if from == FileType::Unknown {
+            return Err(anyhow::anyhow!("Cannot tell the file type of {}", input.display()));
+        }
+
+        if (self.output_dir.is_some() || self.name_fn.is_some()) && let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent).with_path("Cannot create directory", parent)?;
+        }
+
+        self.settings.convert_file(registry, from, input, output)
+    }
+
+    /// Collects the inputs with their output paths, checking every output up front rather than
+    /// overwriting an earlier result halfway through.
+    fn plan(&self) -> anyhow::Result<(Vec<BatchInput>, Vec<PathBuf>)> {
+        if self.settings.to.0 == FileType::Unknown {
+            return Err(anyhow::anyhow!("Target file type not specified"));
+        }
+
+        let inputs = self.collect_inputs()?;
+        let outputs: Vec<PathBuf> = inputs.iter().map(|input| self.output_path(input)).collect();
+
+        let mut claimed: HashMap<&Path, &Path> = HashMap::new();
+        for (input, output) in inputs.iter().zip(&outputs) {
+            if let Some(other) = claimed.insert(output, &input.path) {
+                return Err(anyhow::anyhow!(
+                    "Both {} and {} would be converted to {}", other.display(), input.path.display(), output.display()
+                ));
+            }
+        }
+
+        Ok((inputs, outputs))
+    }
+
+    pub fn convert(mut self) -> anyhow::Result<BatchReport> {
+        let (inputs, outputs) = self.plan()?;
+        let registry = self.settings.prepare_registry()?;
+        let mut manifest = self.source_manifest.as_deref().map(SourceManifest::load).transpose()?;
+        let settings = self.manifest_settings();
+        let mut report = BatchReport::default();
+
+        let file_progress = self.settings.progress.callback.clone();
+        let total = inputs.len() as f32;
+        let retries = match self.on_error {
+            ErrorPolicy::Retry(retries) => retries,
+            ErrorPolicy::Abort | ErrorPolicy::Skip => 0,
+        };
+
+        // Size of the outputs that were up to date, for the budget.
+        let mut kept_size = 0;
+        for (index, (input, output)) in inputs.iter().zip(&outputs).enumerate() {
+            let hash = manifest.as_ref().and_then(|_| sha256::hash_file(&input.path).ok());
+            let unchanged = manifest.as_ref().zip(hash.as_deref())
+                .is_some_and(|(manifest, hash)| manifest.is_unchanged(&input.path, hash, output, &settings));
+            if unchanged || self.skip_if_output_newer && is_up_to_date(&input.path, output) {
+                kept_size += std::fs::metadata(output).with_path("Cannot access", output)?.len();
+                report.up_to_date.push(input.path.clone());
+                self.progress.report((index + 1) as f32 / total);
+                continue;
+            }
+
+            if self.progress.callback.is_some() {
+                let (batch_progress, file_progress) = (self.progress.clone(), file_progress.clone());
+                self.settings.progress.callback = Some(Arc::new(move |fraction| {
+                    if let Some(file_progress) = &file_progress {
+                        file_progress(fraction);
+                    }
+                    batch_progress.report((index as f32 + fraction) / total);
+                }));
+            }
+
+            let remaining = self.size_budget.map(|budget| budget.saturating_sub(report.total_output_size() + kept_size));
+            let mut attempt = 0;
+            let result = loop {
+                match self.convert_input(&registry, &input.path, output, remaining) {
+                    Err(error) if attempt < retries => {
+                        attempt += 1;
+                        println!("Converting {} failed, retrying ({}/{}): {}", input.path.display(), attempt, retries, error);
+                    }
+                    result => break result,
+                }
+            };
+
+            match result {
+                Ok(Some(converted)) => {
+                    if let Some((manifest, hash)) = manifest.as_mut().zip(hash) {
+                        manifest.record(&input.path, hash, output, &settings);
+                    }
+                    report.converted.push(converted);
+                }
+                Ok(None) => {
+                    let _ = std::fs::remove_file(output);
+                    println!("Size budget of {} bytes reached, skipping the remaining files", self.size_budget.unwrap_or_default());
+                    report.skipped = inputs[index..].iter().map(|input| input.path.clone()).collect();
+                    break;
+                }
+                Err(error) if self.on_error == ErrorPolicy::Abort => {
+                    if let Some(manifest) = &manifest {
+                        manifest.save()?;
+                    }
+                    return Err(error);
+                }
+                Err(error) => {
+                    println!("Skipping {}: {}", input.path.display(), error);
+                    report.failed.push((input.path.clone(), error.to_string()));
+                }
+            }
+        }
+
+        if let Some(manifest) = &manifest {
+            manifest.save()?;
+        }
+        report.print_failures(inputs.len());
+        self.progress.report(1.0);
+        Ok(report)
+    }
+
+    /// Like [`Self::convert`], with up to `max_concurrency` files converted at the same time,
+    /// each on a thread of its own. Every file being converted is held in memory decoded, so
+    /// keep it low for huge images on machines short of memory. The report lists the files in
+    /// the order they were added. Progress is reported as files finish, the per-file callback
+    /// of the settings isn't called. With [`ErrorPolicy::Abort`] no new files are started once
+    /// one fails, and its error is returned after the running ones are done.
+    ///
+    /// How a [`Self::total_size_budget`] is spent depends on the order the files are converted
+    /// in, batches with one are converted one file after the other.
+    pub fn convert_parallel(mut self, max_concurrency: usize) -> anyhow::Result<BatchReport> {
+        if self.size_budget.is_some() {
+            println!("Batches with a size budget are converted one file at a time");
+            return self.convert();
+        }
+
+        let (inputs, outputs) = self.plan()?;
+        let registry = self.settings.prepare_registry()?;
+        let mut manifest = self.source_manifest.as_deref().map(SourceManifest::load).transpose()?;
+        let settings = self.manifest_settings();
+        self.settings.progress.callback = None;
+
+        let retries = match self.on_error {
+            ErrorPolicy::Retry(retries) => retries,
+            ErrorPolicy::Abort | ErrorPolicy::Skip => 0,
+        };
+        let next = AtomicUsize::new(0);
+        let finished = AtomicUsize::new(0);
+        let abort = AtomicBool::new(false);
+
+        // The hash of the source is returned with the result, to record it once converted.
+        let convert = |input: &BatchInput, output: &Path| -> Option<(Option<String>, anyhow::Result<ConversionReport>)> {
+            let hash = manifest.as_ref().and_then(|_| sha256::hash_file(&input.path).ok());
+            let unchanged = manifest.as_ref().zip(hash.as_deref())
+                .is_some_and(|(manifest, hash)| manifest.is_unchanged(&input.path, hash, output, &settings));
+            if unchanged || self.skip_if_output_newer && is_up_to_date(&input.path, output) {
+                return None;
+            }
+
+            let mut attempt = 0;
+            Some((hash, loop {
+                match self.convert_single(&registry, &input.path, output) {
+                    Err(error) if attempt < retries => {
+                        attempt += 1;
+                        println!("Converting {} failed, retrying ({}/{}): {}", input.path.display(), attempt, retries, error);
+                    }
+                    result => break result,
+                }
+            }))
+        };
+
+        let mut results: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..max_concurrency.clamp(1, inputs.len().max(1)))
+                .map(|_| scope.spawn(|| {
+                    let mut results = Vec::new();
+                    while !abort.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((input, output)) = inputs.get(index).zip(outputs.get(index)) else {
+                            break;
+                        };
+
+                        let result = convert(input, output);
+                        if matches!(result, Some((_, Err(_)))) && self.on_error == ErrorPolicy::Abort {
+                            abort.store(true, Ordering::Relaxed);
+                        }
+                        results.push((index, result));
+                        self.progress.report((finished.fetch_add(1, Ordering::Relaxed) + 1) as f32 / inputs.len() as f32);
+                    }
+                    results
+                }))
+                .collect();
+
+            workers.into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        });
+        results.sort_by_key(|(index, _)| *index);
+
+        let mut report = BatchReport::default();
+        let mut abort_error = None;
+        for (index, result) in results {
+            let input = &inputs[index];
+            match result {
+                None => report.up_to_date.push(input.path.clone()),
+                Some((hash, Ok(converted))) => {
+                    if let Some((manifest, hash)) = manifest.as_mut().zip(hash) {
+                        manifest.record(&input.path, hash, &outputs[index], &settings);
+                    }
+                    report.converted.push(converted);
+                }
+                Some((_, Err(error))) if self.on_error == ErrorPolicy::Abort => {
+                    abort_error.get_or_insert(error);
+                }
+                Some((_, Err(error))) => {
+                    println!("Skipping {}: {}", input.path.display(), error);
+                    report.failed.push((input.path.clone(), error.to_string()));
+                }
+            }
+        }
+
+        if let Some(manifest) = &manifest {
+            manifest.save()?;
+        }
+        if let Some(error) = abort_error {
+            return Err(error);
+        }
+        report.print_failures(inputs.len());
+        self.progress.report(1.0);
+        Ok(report)
+    }
+}
+
+/// Whether `output` exists and was modified after `input`.
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(input), modified(output)) {
+        (Ok(input), Ok(output)) => output > input,
+        _ => false,
+    }
+}
+
+/// The sources converted by earlier runs, see [`BatchConvertBuilder::skip_if_source_unchanged`].
+/// It is an object keyed by source path, with the `sha256` of the source, its `output` and the
+/// `settings` it was converted with.
+struct SourceManifest {
+    path: PathBuf,
+    entries: Vec<(String, JsonValue)>,
+}
+
+impl SourceManifest {
+    /// Reads the manifest at `path`, or starts an empty one if there is no file yet.
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(text) => match JsonValue::parse(&text).map_err(|err| anyhow::anyhow!("Invalid source manifest {}: {}", path.display(), err))? {
+                JsonValue::Object(entries) => entries,
+                _ => return Err(anyhow::anyhow!("The source manifest {} is not a JSON object", path.display())),
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error).with_path("Cannot read", path),
+        };
+        Ok(Self { path: path.to_path_buf(), entries })
+    }
+
+    fn key(input: &Path) -> String {
+        input.to_string_lossy().into_owned()
+    }
+
+    /// Whether `input` was last converted from the same contents to `output`, with the same
+    /// settings, and `output` is still there.
+    fn is_unchanged(&self, input: &Path, hash: &str, output: &Path, settings: &JsonValue) -> bool {
+        let key = Self::key(input);
+        let Some((_, entry)) = self.entries.iter().find(|(name, _)| *name == key) else {
+            return false;
+        };
+        entry.get("sha256").and_then(JsonValue::as_str) == Some(hash)
+            && entry.get("output").and_then(JsonValue::as_str) == Some(&*output.to_string_lossy())
+            && entry.get("settings") == Some(settings)
+            && output.exists()
+    }
+
+    fn record(&mut self, input: &Path, hash: String, output: &Path, settings: &JsonValue) {
+        let key = Self::key(input);
+        let entry = JsonValue::object([
+            ("sha256", hash.into()),
+            ("output", output.to_string_lossy().into_owned().into()),
+            ("settings", settings.clone()),
+        ]);
+        match self.entries.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, existing)) => *existing = entry,
+            None => self.entries.push((key, entry)),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let text = JsonValue::Object(self.entries.clone()).to_string();
+        std::fs::write(&self.path, text).with_path("Cannot write", &self.path)
+    }
+}
@@ -0,0 +1,296 @@
+use std::{fs::File, io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::Path, time::Duration};
+
+use crate::{converters::audio::{apply_transforms, check_layout, wav::{self, SampleFormat}, AudioFileType, AudioInfo, MAX_FORMAT_CHUNK_LEN}, error::PathContext, ConvertOptions, Converter, FileType};
+
+/// The contents of an AIFF file's `COMM` chunk and the location of its sample data.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AiffHeader {
+    pub channels: u16,
+    pub frames: u32,
+    pub bits_per_sample: u16,
+    pub sample_rate: u32,
+    pub data_len: u64,
+}
+
+impl AiffHeader {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    fn sample_format(&self) -> anyhow::Result<SampleFormat> {
+        match self.bits_per_sample {
+            8 => Ok(SampleFormat::U8),
+            16 => Ok(SampleFormat::I16),
+            24 => Ok(SampleFormat::I24),
+            32 => Ok(SampleFormat::I32),
+            bits => Err(anyhow::anyhow!("Unsupported AIFF sample size of {} bits", bits)),
+        }
+    }
+}
+
+/// Reads the 80-bit extended precision float AIFF stores the sample rate in.
+fn read_extended(bytes: &[u8]) -> f64 {
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7FFF) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+fn write_extended(value: u32) -> [u8; 10] {
+    let mut bytes = [0u8; 10];
+    if value == 0 {
+        return bytes;
+    }
+
+    let shift = (value as u64).leading_zeros();
+    let exponent = (16383 + 63 - shift) as u16;
+    bytes[0..2].copy_from_slice(&exponent.to_be_bytes());
+    bytes[2..10].copy_from_slice(&((value as u64) << shift).to_be_bytes());
+    bytes
+}
+
+/// Walks the IFF chunks up to the `SSND` chunk, leaving `reader` positioned at the first sample.
+pub(crate) fn read_header<R: Read + Seek>(reader: &mut R) -> anyhow::Result<AiffHeader> {
+    let mut form = [0u8; 12];
+    reader.read_exact(&mut form)?;
+    if &form[0..4] != b"FORM" || &form[8..12] != b"AIFF" {
+        return Err(anyhow::anyhow!("Not an AIFF file"));
+    }
+
+    let mut common = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk)
+            .map_err(|_| anyhow::anyhow!("AIFF file has no SSND chunk"))?;
+        let len = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+
+        match &chunk[0..4] {
+            b"COMM" => {
+                if len > MAX_FORMAT_CHUNK_LEN {
+                    return Err(anyhow::anyhow!("AIFF COMM chunk of {} bytes is too long", len));
+                }
+                let mut comm = vec![0u8; len as usize];
+                reader.read_exact(&mut comm)?;
+                if comm.len() < 18 {
+                    return Err(anyhow::anyhow!("AIFF COMM chunk is too short"));
+                }
+
+                common = Some((
+                    u16::from_be_bytes([comm[0], comm[1]]),
+                    u32::from_be_bytes([comm[2], comm[3], comm[4], comm[5]]),
+                    u16::from_be_bytes([comm[6], comm[7]]),
+                    read_extended(&comm[8..18]).round() as u32,
+                ));
+            }
+            b"SSND" => {
+                let (channels, frames, bits_per_sample, sample_rate) = common
+                    .ok_or_else(|| anyhow::anyhow!("AIFF SSND chunk comes before the COMM chunk"))?;
+                check_layout(channels, sample_rate)?;
+
+                let mut offsets = [0u8; 8];
+                reader.read_exact(&mut offsets)?;
+                let offset = u32::from_be_bytes([offsets[0], offsets[1], offsets[2], offsets[3]]) as u64;
+                reader.seek(SeekFrom::Current(offset as i64))?;
+
+                return Ok(AiffHeader {
+                    channels,
+                    frames,
+                    bits_per_sample,
+                    sample_rate,
+                    data_len: len.saturating_sub(8 + offset),
+                });
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+
+        // Chunks are padded to an even length.
+        if len & 1 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+}
+
+pub(crate) fn probe(path: &Path) -> anyhow::Result<AudioInfo> {
+    let header = read_header(&mut BufReader::new(File::open(path).with_path("Cannot read", path)?))?;
+
+    Ok(AudioInfo {
+        sample_rate: header.sample_rate,
+        channels: header.channels,
+        bits_per_sample: Some(header.bits_per_sample),
+        duration: header.duration(),
+    })
+}
+
+/// Turns big endian signed samples into the little endian layout of a WAV `data` chunk and
+/// back. 8-bit samples are signed in AIFF but unsigned in WAV, which flipping the top bit takes
+/// care of.
+fn swap_layout(data: &mut [u8], format: SampleFormat) {
+    match format {
+        SampleFormat::U8 => data.iter_mut().for_each(|sample| *sample ^= 0x80),
+        _ => data.chunks_exact_mut(format.bytes_per_sample()).for_each(|sample| sample.reverse()),
+    }
+}
+
+/// Reads the samples of an AIFF file, laid out as in a WAV `data` chunk.
+pub(crate) fn read_data(path: &Path) -> anyhow::Result<(AiffHeader, SampleFormat, Vec<u8>)> {
+    let mut reader = BufReader::new(File::open(path).with_path("Cannot read", path)?);
+    let header = read_header(&mut reader)?;
+    let format = header.sample_format()?;
+
+    let mut data = Vec::new();
+    reader.take(header.data_len).read_to_end(&mut data)?;
+    let frame_len = header.channels as usize * format.bytes_per_sample();
+    data.truncate(header.frames as usize * frame_len);
+    swap_layout(&mut data, format);
+    Ok((header, format, data))
+}
+
+/// Writes an AIFF file around samples laid out as in a WAV `data` chunk.
+pub(crate) fn write_data(path: &Path, format: SampleFormat, channels: u16, sample_rate: u32, data: &[u8]) -> anyhow::Result<()> {
+    if format == SampleFormat::F32 {
+        return Err(anyhow::anyhow!("AIFF cannot store floating point samples"));
+    }
+
+    let mut data = data.to_vec();
+    swap_layout(&mut data, format);
+    // The FORM chunk holds the 46 bytes of header after its length and a padding byte.
+    let data_len = u32::try_from(data.len()).ok().filter(|len| len.checked_add(47).is_some())
+        .ok_or_else(|| anyhow::anyhow!("{} bytes of samples are too many for an AIFF file", data.len()))?;
+    let frames = data_len / (channels as u32 * format.bytes_per_sample() as u32).max(1);
+
+    let mut writer = BufWriter::new(File::create(path).with_path("Cannot write", path)?);
+    writer.write_all(b"FORM")?;
+    writer.write_all(&(4 + 26 + 16 + data_len + (data_len & 1)).to_be_bytes())?;
+    writer.write_all(b"AIFFCOMM")?;
+    writer.write_all(&18u32.to_be_bytes())?;
+    writer.write_all(&channels.to_be_bytes())?;
+    writer.write_all(&frames.to_be_bytes())?;
+    writer.write_all(&format.bits_per_sample().to_be_bytes())?;
+    writer.write_all(&write_extended(sample_rate))?;
+    writer.write_all(b"SSND")?;
+    writer.write_all(&(8 + data_len).to_be_bytes())?;
+    // No offset and no block alignment.
+    writer.write_all(&[0; 8])?;
+    writer.write_all(&data)?;
+    if data_len & 1 == 1 {
+        writer.write_all(&[0])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decodes an AIFF file into normalised floating point samples, also returning the format the
+/// samples were stored in.
+pub(crate) fn decode_with_format(path: &Path) -> anyhow::Result<(crate::AudioBuffer, SampleFormat)> {
+    let (header, format, data) = read_data(path)?;
+    Ok((wav::samples_to_buffer(&data, format, header.channels, header.sample_rate), format))
+}
+
+/// Converts WAV files to AIFF, keeping the samples bit for bit unless audio transformations
+/// were requested. Floating point WAV files are rejected since AIFF can't hold them.
+pub struct WavToAiff;
+
+impl Converter for WavToAiff {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let (header, format, data) = wav::read_data(input_path)?;
+        if !options.has_audio_transforms() {
+            return write_data(output_path, format, header.channels, header.sample_rate, &data);
+        }
+
+        let mut buffer = wav::samples_to_buffer(&data, format, header.channels, header.sample_rate);
+        apply_transforms(&mut buffer, options)?;
+        write_data(output_path, format, buffer.channels, buffer.sample_rate, &wav::buffer_to_samples(&buffer, format))
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::WAV)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::AIFF)
+    }
+}
+
+/// Converts AIFF files to WAV, keeping the samples bit for bit unless audio transformations
+/// or another WAV sample format were requested.
+pub struct AiffToWav;
+
+impl Converter for AiffToWav {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let (header, format, data) = read_data(input_path)?;
+        let output_format = wav::output_format(format, options);
+        if !options.has_audio_transforms() && output_format == format {
+            return wav::write_data(output_path, format, options.wav_endian, header.channels, header.sample_rate, &data);
+        }
+
+        let mut buffer = wav::samples_to_buffer(&data, format, header.channels, header.sample_rate);
+        apply_transforms(&mut buffer, options)?;
+        wav::encode(&buffer, output_format, options.wav_endian, output_path)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::AIFF)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::WAV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn form(comm: &[u8], comm_len: u32) -> Vec<u8> {
+        let mut bytes = b"FORM\0\0\0\0AIFFCOMM".to_vec();
+        bytes.extend_from_slice(&comm_len.to_be_bytes());
+        bytes.extend_from_slice(comm);
+        bytes.extend_from_slice(b"SSND");
+        bytes.extend_from_slice(&8u32.to_be_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes
+    }
+
+    fn comm(channels: u16, sample_rate: u32) -> Vec<u8> {
+        let mut comm = Vec::new();
+        comm.extend_from_slice(&channels.to_be_bytes());
+        comm.extend_from_slice(&0u32.to_be_bytes());
+        comm.extend_from_slice(&16u16.to_be_bytes());
+        comm.extend_from_slice(&write_extended(sample_rate));
+        comm
+    }
+
+    #[test]
+    fn reads_the_header() {
+        let header = read_header(&mut Cursor::new(form(&comm(2, 48_000), 18))).unwrap();
+        assert_eq!((header.channels, header.sample_rate, header.bits_per_sample), (2, 48_000, 16));
+    }
+
+    #[test]
+    fn rejects_oversized_comm_chunks() {
+        let error = read_header(&mut Cursor::new(form(&comm(2, 48_000), u32::MAX))).unwrap_err();
+        assert!(error.to_string().contains("too long"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_truncated_comm_chunks() {
+        let mut bytes = form(&comm(2, 48_000), 18);
+        bytes.truncate(30);
+        assert!(read_header(&mut Cursor::new(bytes)).is_err());
+        assert!(read_header(&mut Cursor::new(form(&comm(2, 48_000)[..10], 10))).is_err());
+    }
+
+    #[test]
+    fn rejects_impossible_layouts() {
+        for (channels, sample_rate) in [(0, 48_000), (2, 0), (u16::MAX, 48_000)] {
+            assert!(read_header(&mut Cursor::new(form(&comm(channels, sample_rate), 18))).is_err(), "{} channels at {} Hz", channels, sample_rate);
+        }
+    }
+}
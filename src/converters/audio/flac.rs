@@ -0,0 +1,173 @@
+use std::{fs::File, io::Read, path::Path, process::Command, sync::OnceLock, time::Duration};
+
+use crate::{converters::audio::{check_layout, mp3::BITRATES_V1_L3, AudioFileType, AudioInfo}, error::PathContext, ConvertOptions, Converter, FileType};
+
+/// Bitrate of MP3 outputs when [`ConvertOptions::mp3_bitrate`] isn't set, in kbit/s.
+pub const DEFAULT_MP3_BITRATE: u32 = 192;
+
+/// Reads the STREAMINFO block every FLAC file starts with.
+pub(crate) fn probe(path: &Path) -> anyhow::Result<AudioInfo> {
+    // The magic, the metadata block header and the 34 bytes of STREAMINFO.
+    let mut header = [0u8; 42];
+    File::open(path).with_path("Cannot read", path)?.read_exact(&mut header)
+        .map_err(|_| anyhow::anyhow!("FLAC header of {} is truncated", path.display()))?;
+    parse_stream_info(&header)
+}
+
+fn parse_stream_info(header: &[u8; 42]) -> anyhow::Result<AudioInfo> {
+    if &header[0..4] != b"fLaC" {
+        return Err(anyhow::anyhow!("Not a FLAC file"));
+    }
+    let block_len = u32::from_be_bytes([0, header[5], header[6], header[7]]);
+    if header[4] & 0x7F != 0 || block_len < 34 {
+        return Err(anyhow::anyhow!("FLAC file does not start with a STREAMINFO block"));
+    }
+
+    // Sample rate (20 bits), channels - 1 (3), bits per sample - 1 (5) and total samples (36).
+    let fields = u64::from_be_bytes(header[18..26].try_into()?);
+    let sample_rate = (fields >> 44) as u32;
+    let channels = ((fields >> 41) & 0x7) as u16 + 1;
+    let bits_per_sample = ((fields >> 36) & 0x1F) as u16 + 1;
+    let frames = fields & 0xF_FFFF_FFFF;
+    check_layout(channels, sample_rate)?;
+
+    Ok(AudioInfo {
+        sample_rate,
+        channels,
+        bits_per_sample: Some(bits_per_sample),
+        // Zero when the encoder didn't know the length, like one reading from a pipe.
+        duration: Duration::from_secs_f64(frames as f64 / sample_rate as f64),
+    })
+}
+
+/// Whether an `ffmpeg` with an MP3 encoder can be run, checked once per process.
+fn has_mp3_encoder() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ffmpeg").args(["-hide_banner", "-encoders"]).output()
+            .is_ok_and(|output| output.status.success() && String::from_utf8_lossy(&output.stdout).contains("libmp3lame"))
+    })
+}
+
+/// Encodes FLAC files to MP3 in one pass with ffmpeg and LAME, at
+/// [`ConvertOptions::mp3_bitrate`]. Unavailable unless an `ffmpeg` built with libmp3lame is on
+/// the `PATH`. Audio transformations can't be applied, ffmpeg would remix differently from
+/// the other audio converters.
+pub struct FlacToMp3;
+
+impl Converter for FlacToMp3 {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        if options.has_audio_transforms() {
+            return Err(anyhow::anyhow!("Remixing channels is not supported when encoding FLAC to MP3"));
+        }
+        let bitrate = options.mp3_bitrate.unwrap_or(DEFAULT_MP3_BITRATE);
+        if !BITRATES_V1_L3[1..].contains(&bitrate) {
+            return Err(anyhow::anyhow!("Invalid MP3 bitrate {} kbit/s, expected one of {:?}", bitrate, &BITRATES_V1_L3[1..]));
+        }
+        probe(input_path)?;
+
+        let output = Command::new("ffmpeg")
+            .args(["-nostdin", "-hide_banner", "-v", "error", "-y", "-i"])
+            .arg(input_path)
+            // The output path may be a temporary file without an .mp3 extension.
+            .args(["-vn", "-codec:a", "libmp3lame", "-b:a", &format!("{}k", bitrate), "-f", "mp3"])
+            .arg(output_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "ffmpeg failed to encode {} to MP3: {}",
+                input_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let mut magic = [0u8; 12];
+        let read = File::open(output_path).with_path("Cannot read", output_path)?.read(&mut magic)?;
+        if AudioFileType::detect(&magic[..read]) != Some(AudioFileType::MP3) {
+            return Err(anyhow::anyhow!("ffmpeg did not write an MP3 file to {}", output_path.display()));
+        }
+        Ok(())
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::FLAC)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::MP3)
+    }
+
+    fn is_available(&self) -> bool {
+        has_mp3_encoder()
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{converters::audio::{mp3, wav, AudioBuffer}, ConverterRegistry, Endian};
+
+    fn stream_info(sample_rate: u32, channels: u64, bits_per_sample: u64, frames: u64) -> [u8; 42] {
+        let mut header = [0u8; 42];
+        header[0..4].copy_from_slice(b"fLaC");
+        header[4] = 0x80;
+        header[7] = 34;
+        let fields = (sample_rate as u64) << 44 | (channels - 1) << 41 | (bits_per_sample - 1) << 36 | frames;
+        header[18..26].copy_from_slice(&fields.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn parses_stream_info() {
+        let info = parse_stream_info(&stream_info(44_100, 2, 16, 88_200)).unwrap();
+        assert_eq!(info, AudioInfo { sample_rate: 44_100, channels: 2, bits_per_sample: Some(16), duration: Duration::from_secs(2) });
+    }
+
+    #[test]
+    fn rejects_bad_stream_info() {
+        assert!(parse_stream_info(&stream_info(0, 2, 16, 0)).is_err());
+
+        let mut header = stream_info(44_100, 2, 16, 0);
+        header[4] = 0x84;
+        assert!(parse_stream_info(&header).is_err());
+        header[4] = 0x80;
+        header[7] = 10;
+        assert!(parse_stream_info(&header).is_err());
+    }
+
+    #[test]
+    fn prefers_the_direct_path() {
+        let registry = ConverterRegistry::new();
+        let (flac, mp3) = (FileType::Audio(AudioFileType::FLAC), FileType::Audio(AudioFileType::MP3));
+        assert_eq!(registry.find_path_where(flac, mp3, |_, _| true), Some(vec![flac, mp3]));
+    }
+
+    #[test]
+    fn encodes_a_valid_mp3() {
+        if !has_mp3_encoder() {
+            println!("Skipping, no ffmpeg with libmp3lame");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("phase_change_flac_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (wav_path, flac_path, mp3_path) = (dir.join("in.wav"), dir.join("in.flac"), dir.join("out.mp3"));
+        let samples = (0..44_100 * 2).map(|i| ((i / 2) as f32 * 0.05).sin() * 0.5).collect();
+        wav::encode(&AudioBuffer { sample_rate: 44_100, channels: 2, samples }, wav::SampleFormat::I16, Endian::Little, &wav_path).unwrap();
+        let status = Command::new("ffmpeg").args(["-nostdin", "-v", "error", "-y", "-i"]).arg(&wav_path).arg(&flac_path).status().unwrap();
+        assert!(status.success());
+
+        let options = ConvertOptions { mp3_bitrate: Some(128), ..ConvertOptions::default() };
+        FlacToMp3.convert(&flac_path, &mp3_path, &options).unwrap();
+        let info = mp3::probe(&mp3_path).unwrap();
+        assert_eq!((info.sample_rate, info.channels), (44_100, 2));
+
+        let bad = ConvertOptions { mp3_bitrate: Some(100), ..ConvertOptions::default() };
+        assert!(FlacToMp3.convert(&flac_path, &mp3_path, &bad).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
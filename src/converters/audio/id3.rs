@@ -0,0 +1,185 @@
+//! Reading ID3v2 tags, versions 2.2 to 2.4, and writing versions 2.3 and 2.4.
+
+/// A frame of an ID3v2 tag, with any unsynchronisation undone.
+pub(crate) struct Id3Frame {
+    /// Four character ID, three for ID3v2.2 frames.
+    pub id: String,
+    pub data: Vec<u8>,
+}
+
+/// A picture embedded in an `APIC` frame, `PIC` in ID3v2.2.
+pub(crate) struct Picture {
+    /// The MIME type it was tagged with, like `image/jpeg`. ID3v2.2 stores a three letter
+    /// format instead, which is mapped to its MIME type.
+    pub mime: String,
+    /// What the picture shows, 3 for the front cover.
+    pub picture_type: u8,
+    pub data: Vec<u8>,
+}
+
+/// Picture type of the front cover.
+const FRONT_COVER: u8 = 3;
+
+/// Removes the zero bytes unsynchronisation inserts after every 0xFF.
+fn resynchronise(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    for (index, byte) in bytes.iter().enumerate() {
+        if !(*byte == 0 && index > 0 && bytes[index - 1] == 0xFF) {
+            result.push(*byte);
+        }
+    }
+    result
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |size, byte| (size << 7) | (*byte & 0x7F) as usize)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |size, byte| (size << 8) | *byte as usize)
+}
+
+/// Reads every frame of the ID3v2 tag at the start of `bytes`. Returns an empty list if there
+/// is no tag, and the frames read so far if it is truncated.
+pub(crate) fn read_frames(bytes: &[u8]) -> Vec<Id3Frame> {
+    if bytes.len() < 10 || &bytes[0..3] != b"ID3" {
+        return Vec::new();
+    }
+    let version = bytes[3];
+    let flags = bytes[5];
+    let end = (10 + syncsafe(&bytes[6..10])).min(bytes.len());
+
+    // Before 2.4 unsynchronisation applies to the whole tag, from 2.4 on to single frames.
+    let tag = if version < 4 && flags & 0x80 != 0 { resynchronise(&bytes[10..end]) } else { bytes[10..end].to_vec() };
+    let mut offset = 0;
+    if flags & 0x40 != 0 && version >= 3 && tag.len() >= 4 {
+        offset = match version {
+            3 => 4 + big_endian(&tag[0..4]),
+            _ => syncsafe(&tag[0..4]),
+        };
+    }
+
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut frames = Vec::new();
+    while offset + header_len <= tag.len() {
+        let header = &tag[offset..offset + header_len];
+        // The rest of the tag is padding.
+        if header[0] == 0 {
+            break;
+        }
+
+        let size = match version {
+            2 => big_endian(&header[3..6]),
+            3 => big_endian(&header[4..8]),
+            _ => syncsafe(&header[4..8]),
+        };
+        let Some(data) = tag.get(offset + header_len..offset + header_len + size) else {
+            break;
+        };
+
+        let mut data = data.to_vec();
+        if version >= 4 {
+            let format_flags = header[9];
+            // A data length indicator of four bytes comes first when this flag is set.
+            if format_flags & 0x01 != 0 && data.len() >= 4 {
+                data.drain(..4);
+            }
+            if format_flags & 0x02 != 0 {
+                data = resynchronise(&data);
+            }
+        }
+
+        frames.push(Id3Frame { id: String::from_utf8_lossy(&header[..id_len]).into_owned(), data });
+        offset += header_len + size;
+    }
+
+    frames
+}
+
+/// Length of a string terminated by a zero, or two for the UTF-16 encodings, including the
+/// terminator. The whole of `bytes` if it isn't terminated.
+fn terminated_len(bytes: &[u8], encoding: u8) -> usize {
+    if matches!(encoding, 1 | 2) {
+        (0..bytes.len() / 2).find(|index| bytes[index * 2] == 0 && bytes[index * 2 + 1] == 0)
+            .map_or(bytes.len(), |index| index * 2 + 2)
+    } else {
+        bytes.iter().position(|byte| *byte == 0).map_or(bytes.len(), |index| index + 1)
+    }
+}
+
+fn parse_picture(frame: &Id3Frame) -> Option<Picture> {
+    let data = &frame.data;
+    let encoding = *data.first()?;
+
+    let (mime, rest) = if frame.id == "PIC" {
+        let mime = match data.get(1..4)?.to_ascii_uppercase().as_slice() {
+            b"PNG" => "image/png".to_string(),
+            b"JPG" => "image/jpeg".to_string(),
+            format => format!("image/{}", String::from_utf8_lossy(format).to_ascii_lowercase()),
+        };
+        (mime, &data[4..])
+    } else {
+        let rest = &data[1..];
+        let mime_len = terminated_len(rest, 0);
+        let mime = String::from_utf8_lossy(&rest[..mime_len]).trim_end_matches('\0').to_ascii_lowercase();
+        (mime, &rest[mime_len..])
+    };
+
+    let picture_type = *rest.first()?;
+    let description_len = terminated_len(&rest[1..], encoding);
+    Some(Picture { mime, picture_type, data: rest[1 + description_len..].to_vec() })
+}
+
+/// Finds the cover art in an ID3v2 tag: the front cover if there is one, the first picture
+/// otherwise.
+pub(crate) fn cover_art(bytes: &[u8]) -> Option<Picture> {
+    let pictures: Vec<Picture> = read_frames(bytes).iter()
+        .filter(|frame| frame.id == "APIC" || frame.id == "PIC")
+        .filter_map(parse_picture)
+        .filter(|picture| !picture.data.is_empty())
+        .collect();
+
+    let front = pictures.iter().position(|picture| picture.picture_type == FRONT_COVER).unwrap_or(0);
+    pictures.into_iter().nth(front)
+}
+
+/// Whether `id` names a text information frame of ID3v2.3 or 2.4, like `TIT2` for the title.
+/// User defined `TXXX` frames also need a description and aren't one.
+pub(crate) fn is_text_frame(id: &str) -> bool {
+    id.len() == 4 && id.starts_with('T') && id != "TXXX" && id.bytes().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+}
+
+/// The data of a text frame holding `text`: UTF-8 for ID3v2.4, which 2.3 doesn't know, so it
+/// gets Latin-1 if that can hold the text and UTF-16 otherwise.
+pub(crate) fn text_frame_data(version: u8, text: &str) -> Vec<u8> {
+    if version >= 4 {
+        std::iter::once(3).chain(text.bytes()).collect()
+    } else if text.chars().all(|char| (char as u32) < 0x100) {
+        std::iter::once(0).chain(text.chars().map(|char| char as u8)).collect()
+    } else {
+        let mut data = vec![1, 0xFF, 0xFE];
+        data.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        data
+    }
+}
+
+fn to_syncsafe(size: usize) -> [u8; 4] {
+    std::array::from_fn(|index| ((size >> (7 * (3 - index))) & 0x7F) as u8)
+}
+
+/// Writes an ID3v2.3 or 2.4 tag holding `frames`, without unsynchronisation or padding.
+pub(crate) fn write_tag(version: u8, frames: &[Id3Frame]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for frame in frames {
+        body.extend_from_slice(frame.id.as_bytes());
+        let size = if version >= 4 { to_syncsafe(frame.data.len()) } else { (frame.data.len() as u32).to_be_bytes() };
+        body.extend_from_slice(&size);
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(&frame.data);
+    }
+
+    let mut tag = vec![b'I', b'D', b'3', version, 0, 0];
+    tag.extend_from_slice(&to_syncsafe(body.len()));
+    tag.extend_from_slice(&body);
+    tag
+}
@@ -0,0 +1,141 @@
+//! Integrated loudness as defined by ITU-R BS.1770-4, the measure EBU R128 normalizes to.
+
+use crate::converters::audio::AudioBuffer;
+
+/// Blocks quieter than this are left out of the measurement, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks this many LU below the loudness of the blocks above the absolute gate are left out.
+const RELATIVE_GATE: f64 = -10.0;
+
+/// A biquad filter in direct form I.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, inputs: [0.0; 2], outputs: [0.0; 2] }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.inputs[0] + self.b[2] * self.inputs[1]
+            - self.a[0] * self.outputs[0] - self.a[1] * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+/// The two stages of the K-weighting filter, a high shelf modelling the head followed by a
+/// high pass. BS.1770 only gives their coefficients at 48 kHz, these are derived from the
+/// analog prototypes so they match them there and hold at any other sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let sample_rate = sample_rate.max(1) as f64;
+
+    let (frequency, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+    let high_gain = 10f64.powf(gain / 20.0);
+    let band_gain = high_gain.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(high_gain + band_gain * k / q + k * k) / a0, 2.0 * (k * k - high_gain) / a0, (high_gain - band_gain * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let (frequency, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+    [shelf, high_pass]
+}
+
+/// Weight of every channel in the sum, with the channels in WAV order. Surround channels count
+/// for about 1.5 dB more and the LFE channel is left out.
+fn channel_weights(channels: u16) -> Vec<f64> {
+    let surround = 1.41;
+    match channels {
+        // L R BL BR
+        4 => vec![1.0, 1.0, surround, surround],
+        // L R C BL BR
+        5 => vec![1.0, 1.0, 1.0, surround, surround],
+        // L R C LFE BL BR, then SL SR
+        6 | 8 => (0..channels).map(|channel| match channel {
+            3 => 0.0,
+            0..=2 => 1.0,
+            _ => surround,
+        }).collect(),
+        _ => vec![1.0; channels as usize],
+    }
+}
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+impl AudioBuffer {
+    /// Measures the integrated loudness of the buffer in LUFS, over gated blocks of 400 ms
+    /// overlapping by 75%. Buffers shorter than a block are measured as a single block.
+    /// Returns `None` when every block is below the -70 LUFS gate, like for silence.
+    pub fn integrated_loudness(&self) -> Option<f64> {
+        let channels = self.channels.max(1) as usize;
+        let frames = self.frames();
+        let step = (self.sample_rate as usize / 10).max(1);
+        if frames == 0 {
+            return None;
+        }
+
+        // Weighted power of every 100 ms step, four of which make up a block.
+        let weights = channel_weights(self.channels);
+        let mut filters = vec![k_weighting(self.sample_rate); channels];
+        let mut steps = Vec::with_capacity(frames / step + 1);
+        for chunk in self.samples.chunks(step * channels) {
+            let mut power = 0.0;
+            for frame in chunk.chunks_exact(channels) {
+                for ((sample, [shelf, high_pass]), weight) in frame.iter().zip(&mut filters).zip(&weights) {
+                    let filtered = high_pass.process(shelf.process(*sample as f64));
+                    power += weight * filtered * filtered;
+                }
+            }
+            steps.push((power, chunk.len() / channels));
+        }
+
+        let blocks: Vec<f64> = if steps.len() < 4 {
+            vec![steps.iter().map(|(power, _)| power).sum::<f64>() / frames as f64]
+        } else {
+            steps.windows(4)
+                .map(|window| window.iter().map(|(power, _)| power).sum::<f64>() / window.iter().map(|(_, len)| *len).sum::<usize>() as f64)
+                .collect()
+        };
+
+        let gated_mean = |threshold: f64| {
+            let kept: Vec<f64> = blocks.iter().copied().filter(|power| to_lufs(*power) > threshold).collect();
+            (!kept.is_empty()).then(|| kept.iter().sum::<f64>() / kept.len() as f64)
+        };
+        let ungated = gated_mean(ABSOLUTE_GATE)?;
+        gated_mean(to_lufs(ungated) + RELATIVE_GATE).map(to_lufs)
+    }
+
+    /// Applies the gain that brings the [`Self::integrated_loudness`] of the buffer to
+    /// `target_lufs`. Silent buffers are left as they are.
+    pub fn normalize_loudness(&mut self, target_lufs: f32) {
+        let Some(loudness) = self.integrated_loudness() else {
+            println!("The audio is silent, not normalizing its loudness");
+            return;
+        };
+
+        let gain = 10f64.powf((target_lufs as f64 - loudness) / 20.0) as f32;
+        let mut peak = 0f32;
+        for sample in &mut self.samples {
+            *sample *= gain;
+            peak = peak.max(sample.abs());
+        }
+        if peak > 1.0 {
+            println!("Normalizing from {:.1} to {:.1} LUFS takes the peak to {:+.1} dBFS, integer outputs will clip", loudness, target_lufs, 20.0 * peak.log10());
+        }
+    }
+}
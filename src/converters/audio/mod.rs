@@ -1,6 +1,222 @@
+pub mod aiff;
+pub mod flac;
+pub(crate) mod id3;
+pub(crate) mod loudness;
+pub mod mp3;
+pub mod spectrogram;
+pub mod wav;
+
+use std::{io::Read, path::Path, time::Duration};
+
+use crate::{error::PathContext, ConvertOptions};
+
+/// Channel counts and sample rates above these only come from corrupt headers.
+const MAX_CHANNELS: u16 = 1024;
+const MAX_SAMPLE_RATE: u32 = 100_000_000;
+/// Longest format chunk read, they hold a few dozen bytes.
+pub(crate) const MAX_FORMAT_CHUNK_LEN: u64 = 64 * 1024;
+
+/// Rejects the channel count and sample rate read from a header if no recording can have them.
+pub(crate) fn check_layout(channels: u16, sample_rate: u32) -> anyhow::Result<()> {
+    if !(1..=MAX_CHANNELS).contains(&channels) {
+        return Err(anyhow::anyhow!("Invalid channel count {}", channels));
+    }
+    if !(1..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(anyhow::anyhow!("Invalid sample rate {} Hz", sample_rate));
+    }
+    Ok(())
+}
 
 #[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
 pub enum AudioFileType {
     MP3,
     WAV,
-}
\ No newline at end of file
+    AIFF,
+    FLAC,
+}
+
+/// Stream properties of an audio file, read from its headers without decoding any audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Bits per sample, `None` for compressed formats that have no fixed sample size.
+    pub bits_per_sample: Option<u16>,
+    pub duration: Duration,
+}
+
+/// Decoded audio as interleaved samples normalised to `-1.0..=1.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+impl AudioBuffer {
+    /// Number of samples per channel.
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// The samples of a single channel as a mono buffer.
+    pub fn channel(&self, channel: u16) -> AudioBuffer {
+        AudioBuffer {
+            sample_rate: self.sample_rate,
+            channels: 1,
+            samples: self.samples.iter()
+                .skip(channel as usize)
+                .step_by(self.channels.max(1) as usize)
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+/// Gain applied to the centre and surround channels when downmixing, -3 dB.
+const DOWNMIX_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// The ITU-R BS.775 matrix downmixing the standard WAV channel layouts to stereo, with the
+/// channels in WAV order (front left/right, centre, LFE, back left/right, side left/right).
+/// The LFE channel is dropped.
+fn stereo_downmix(channels: u16) -> Option<Vec<Vec<f32>>> {
+    let c = DOWNMIX_GAIN;
+    Some(match channels {
+        // L R C
+        3 => vec![vec![1.0, 0.0, c], vec![0.0, 1.0, c]],
+        // L R BL BR
+        4 => vec![vec![1.0, 0.0, c, 0.0], vec![0.0, 1.0, 0.0, c]],
+        // L R C BL BR
+        5 => vec![vec![1.0, 0.0, c, c, 0.0], vec![0.0, 1.0, c, 0.0, c]],
+        // L R C LFE BL BR
+        6 => vec![vec![1.0, 0.0, c, 0.0, c, 0.0], vec![0.0, 1.0, c, 0.0, 0.0, c]],
+        // L R C LFE BL BR SL SR
+        8 => vec![vec![1.0, 0.0, c, 0.0, c, 0.0, c, 0.0], vec![0.0, 1.0, c, 0.0, 0.0, c, 0.0, c]],
+        _ => return None,
+    })
+}
+
+/// Builds the matrix to remix `from` channels to `to` channels.
+pub(crate) fn remix_matrix(from: u16, to: u16) -> anyhow::Result<Vec<Vec<f32>>> {
+    if !(1..=MAX_CHANNELS).contains(&to) {
+        return Err(anyhow::anyhow!("Cannot remix audio to {} channels", to));
+    }
+    let (from_count, to_count) = (from as usize, to as usize);
+    let identity = |index: usize| (0..from_count).map(|channel| if channel == index { 1.0 } else { 0.0 }).collect::<Vec<f32>>();
+
+    let matrix = match (from, to) {
+        _ if from == to => (0..to_count).map(identity).collect(),
+        // Mono is copied to every output channel.
+        (1, _) => vec![vec![1.0]; to_count],
+        (2, 1) => vec![vec![0.5, 0.5]],
+        (_, 2) => stereo_downmix(from)
+            .ok_or_else(|| anyhow::anyhow!("No standard downmix from {} channels, provide a channel matrix", from))?,
+        (_, 1) => {
+            let stereo = stereo_downmix(from)
+                .ok_or_else(|| anyhow::anyhow!("No standard downmix from {} channels, provide a channel matrix", from))?;
+            vec![stereo[0].iter().zip(&stereo[1]).map(|(l, r)| (l + r) / 2.0).collect()]
+        }
+        _ => return Err(anyhow::anyhow!("Cannot remix {} channels to {}, provide a channel matrix", from, to)),
+    };
+
+    Ok(matrix)
+}
+
+impl AudioBuffer {
+    /// Remixes the buffer with `matrix`. Rows whose gains add up to more than 1 are scaled down
+    /// so the full scale of the input can't clip.
+    pub fn remix(&mut self, matrix: &[Vec<f32>]) -> anyhow::Result<()> {
+        let channels = self.channels as usize;
+        if matrix.is_empty() {
+            return Err(anyhow::anyhow!("The channel matrix has no rows"));
+        }
+        if matrix.len() > MAX_CHANNELS as usize {
+            return Err(anyhow::anyhow!("Cannot remix audio to {} channels", matrix.len()));
+        }
+        if let Some(row) = matrix.iter().find(|row| row.len() != channels) {
+            return Err(anyhow::anyhow!("Channel matrix row has {} gains for a {} channel source", row.len(), channels));
+        }
+
+        let matrix: Vec<Vec<f32>> = matrix.iter()
+            .map(|row| {
+                let total: f32 = row.iter().map(|gain| gain.abs()).sum();
+                let scale = if total > 1.0 { 1.0 / total } else { 1.0 };
+                row.iter().map(|gain| gain * scale).collect()
+            })
+            .collect();
+
+        self.samples = self.samples.chunks_exact(channels.max(1))
+            .flat_map(|frame| matrix.iter().map(move |row| row.iter().zip(frame).map(|(gain, sample)| gain * sample).sum::<f32>()))
+            .collect();
+        self.channels = matrix.len() as u16;
+        Ok(())
+    }
+}
+
+/// Runs the audio stage of the conversion pipeline on freshly decoded audio.
+pub(crate) fn apply_transforms(buffer: &mut AudioBuffer, options: &ConvertOptions) -> anyhow::Result<()> {
+    if let Some(matrix) = &options.channel_matrix {
+        buffer.remix(matrix)?;
+    } else if let Some(channels) = options.channels && channels != buffer.channels {
+        buffer.remix(&remix_matrix(buffer.channels, channels)?)?;
+    }
+    if let Some(target) = options.loudness_target {
+        buffer.normalize_loudness(target);
+    }
+
+    Ok(())
+}
+
+impl AudioFileType {
+    /// Every audio type, in declaration order.
+    pub const ALL: [AudioFileType; 4] = [AudioFileType::MP3, AudioFileType::WAV, AudioFileType::AIFF, AudioFileType::FLAC];
+
+    /// Reads the sample rate, channel count, bit depth and duration of the audio file at
+    /// `path`. The format is detected from the file contents.
+    pub fn probe(path: &Path) -> anyhow::Result<AudioInfo> {
+        let mut magic = [0u8; 12];
+        let read = std::fs::File::open(path).with_path("Cannot read", path)?.read(&mut magic)?;
+
+        match Self::detect(&magic[..read]) {
+            Some(AudioFileType::WAV) => wav::probe(path),
+            Some(AudioFileType::MP3) => mp3::probe(path),
+            Some(AudioFileType::AIFF) => aiff::probe(path),
+            Some(AudioFileType::FLAC) => flac::probe(path),
+            None => Err(anyhow::anyhow!("Unrecognised audio format: {}", path.display())),
+        }
+    }
+
+    /// Decodes the audio file at `path`, which must be of this type.
+    pub(crate) fn decode(self, path: &Path) -> anyhow::Result<AudioBuffer> {
+        self.decode_with_format(path).map(|(buffer, _)| buffer)
+    }
+
+    /// Like [`Self::decode`], also returning the format the samples were stored in.
+    pub(crate) fn decode_with_format(self, path: &Path) -> anyhow::Result<(AudioBuffer, wav::SampleFormat)> {
+        match self {
+            AudioFileType::WAV => wav::decode_with_format(path),
+            AudioFileType::AIFF => aiff::decode_with_format(path),
+            AudioFileType::MP3 => Err(anyhow::anyhow!("Decoding MP3 audio is not supported")),
+            AudioFileType::FLAC => Err(anyhow::anyhow!("Decoding FLAC audio is not supported")),
+        }
+    }
+
+    /// Guesses the audio format from the first bytes of a file.
+    pub(crate) fn detect(magic: &[u8]) -> Option<AudioFileType> {
+        if magic.len() >= 12 && matches!(&magic[0..4], b"RIFF" | b"RIFX") && &magic[8..12] == b"WAVE" {
+            Some(AudioFileType::WAV)
+        } else if magic.len() >= 12 && &magic[0..4] == b"FORM" && &magic[8..12] == b"AIFF" {
+            Some(AudioFileType::AIFF)
+        } else if magic.starts_with(b"fLaC") {
+            Some(AudioFileType::FLAC)
+        } else if magic.starts_with(b"ID3") || (magic.len() >= 2 && magic[0] == 0xFF && magic[1] & 0xE0 == 0xE0) {
+            Some(AudioFileType::MP3)
+        } else {
+            None
+        }
+    }
+}
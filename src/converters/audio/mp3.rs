@@ -0,0 +1,422 @@
+use std::{collections::HashMap, io::Cursor, path::Path, time::Duration};
+
+use image::ImageReader;
+
+use crate::{converters::{audio::{id3::{self, Id3Frame}, AudioFileType, AudioInfo}, image::{apply_transforms, check_dimensions, encode::write_image, ImageFileType}}, error::PathContext, ConvertOptions, Converter, FileType};
+
+const BITRATES_V1_L1: [u32; 15] = [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448];
+const BITRATES_V1_L2: [u32; 15] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384];
+pub(crate) const BITRATES_V1_L3: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const BITRATES_V2_L1: [u32; 15] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256];
+const BITRATES_V2_L23: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MpegVersion {
+    V1,
+    V2,
+    V25,
+}
+
+/// A decoded 4 byte MPEG audio frame header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameHeader {
+    version: MpegVersion,
+    layer: u8,
+    /// In kbit/s.
+    bitrate: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    padding: bool,
+}
+
+impl FrameHeader {
+    pub fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version = match (bytes[1] >> 3) & 0b11 {
+            0b00 => MpegVersion::V25,
+            0b10 => MpegVersion::V2,
+            0b11 => MpegVersion::V1,
+            _ => return None,
+        };
+        let layer = match (bytes[1] >> 1) & 0b11 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _ => return None,
+        };
+
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        let table = match (version, layer) {
+            (MpegVersion::V1, 1) => &BITRATES_V1_L1,
+            (MpegVersion::V1, 2) => &BITRATES_V1_L2,
+            (MpegVersion::V1, _) => &BITRATES_V1_L3,
+            (_, 1) => &BITRATES_V2_L1,
+            _ => &BITRATES_V2_L23,
+        };
+        let bitrate = *table.get(bitrate_index).filter(|bitrate| **bitrate != 0)?;
+
+        let base_rate = match (bytes[2] >> 2) & 0b11 {
+            0 => 44100,
+            1 => 48000,
+            2 => 32000,
+            _ => return None,
+        };
+        let sample_rate = match version {
+            MpegVersion::V1 => base_rate,
+            MpegVersion::V2 => base_rate / 2,
+            MpegVersion::V25 => base_rate / 4,
+        };
+
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate,
+            sample_rate,
+            channels: if bytes[3] >> 6 == 0b11 { 1 } else { 2 },
+            padding: (bytes[2] >> 1) & 1 == 1,
+        })
+    }
+
+    pub fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, MpegVersion::V2 | MpegVersion::V25) => 576,
+            _ => 1152,
+        }
+    }
+
+    /// Length of the whole frame in bytes, header included.
+    pub fn frame_len(&self) -> usize {
+        if self.layer == 1 {
+            ((12 * self.bitrate * 1000 / self.sample_rate + self.padding as u32) * 4) as usize
+        } else {
+            (self.samples_per_frame() / 8 * self.bitrate * 1000 / self.sample_rate + self.padding as u32) as usize
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples_per_frame() as f64 / self.sample_rate as f64)
+    }
+
+    /// Offset of the Xing/Info VBR header from the start of the frame, which sits right
+    /// after the side information.
+    fn xing_offset(&self) -> usize {
+        4 + match (self.version, self.channels) {
+            (MpegVersion::V1, 1) => 17,
+            (MpegVersion::V1, _) => 32,
+            (_, 1) => 9,
+            _ => 17,
+        }
+    }
+}
+
+/// Returns the length of the ID3v2 tag at the start of `bytes`, or 0 if there is none.
+pub(crate) fn id3v2_len(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[0..3] != b"ID3" {
+        return 0;
+    }
+
+    let size = bytes[6..10].iter().fold(0usize, |size, byte| (size << 7) | (*byte & 0x7F) as usize);
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+/// Finds the first frame header at or after `start`.
+pub(crate) fn find_frame(bytes: &[u8], start: usize) -> Option<(usize, FrameHeader)> {
+    (start..bytes.len().saturating_sub(3))
+        .find_map(|offset| FrameHeader::parse(&bytes[offset..]).map(|header| (offset, header)))
+}
+
+pub(crate) fn probe(path: &Path) -> anyhow::Result<AudioInfo> {
+    let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+    let (offset, header) = find_frame(&bytes, id3v2_len(&bytes))
+        .ok_or_else(|| anyhow::anyhow!("No MPEG audio frame found in {}", path.display()))?;
+
+    let frame = &bytes[offset..bytes.len().min(offset + header.frame_len())];
+    let xing = header.xing_offset();
+    let frame_count = match frame.get(xing..xing + 12) {
+        // A Xing/Info header with the frame count flag set knows the exact length of VBR files.
+        Some(tag) if (&tag[0..4] == b"Xing" || &tag[0..4] == b"Info") && tag[7] & 1 == 1 => {
+            Some(u32::from_be_bytes([tag[8], tag[9], tag[10], tag[11]]) as u64)
+        }
+        _ => match frame.get(36..36 + 18) {
+            Some(tag) if &tag[0..4] == b"VBRI" => Some(u32::from_be_bytes([tag[14], tag[15], tag[16], tag[17]]) as u64),
+            _ => None,
+        },
+    };
+
+    let duration = match frame_count {
+        Some(frames) => Duration::from_secs_f64(frames as f64 * header.samples_per_frame() as f64 / header.sample_rate as f64),
+        // Without a VBR header assume a constant bitrate, ignoring a trailing ID3v1 tag.
+        None => {
+            let mut audio_len = bytes.len() - offset;
+            // The tag can only follow the audio, not overlap the frame found.
+            if audio_len >= 128 && &bytes[bytes.len() - 128..bytes.len() - 125] == b"TAG" {
+                audio_len -= 128;
+            }
+            Duration::from_secs_f64(audio_len as f64 * 8.0 / (header.bitrate as f64 * 1000.0))
+        }
+    };
+
+    Ok(AudioInfo {
+        sample_rate: header.sample_rate,
+        channels: header.channels,
+        bits_per_sample: None,
+        duration,
+    })
+}
+
+/// Extracts the cover art embedded in the ID3v2 tag of an MP3: the front cover, or the first
+/// picture if none is marked as such. PNG art is written as it is, other formats are decoded
+/// and encoded as PNG.
+pub struct Mp3ToPng;
+
+impl Converter for Mp3ToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let bytes = std::fs::read(input_path).with_path("Cannot read", input_path)?;
+        let picture = id3::cover_art(&bytes)
+            .ok_or_else(|| anyhow::anyhow!("{} has no embedded cover art", input_path.display()))?;
+        if picture.mime == "-->" {
+            return Err(anyhow::anyhow!("The cover art of {} is a link, not an embedded image", input_path.display()));
+        }
+
+        let reader = ImageReader::new(Cursor::new(&picture.data)).with_guessed_format()?;
+        if reader.format() == Some(image::ImageFormat::Png) && !options.has_image_transforms() && options.force_alpha.is_none() {
+            std::fs::write(output_path, &picture.data).with_path("Cannot write", output_path)?;
+            return Ok(());
+        }
+
+        let (width, height) = reader.into_dimensions()?;
+        check_dimensions(width, height, options)?;
+        let mut img = ImageReader::new(Cursor::new(&picture.data)).with_guessed_format()?.decode()
+            .map_err(|error| anyhow::anyhow!("Cannot decode the {} cover art of {}: {}", picture.mime, input_path.display(), error))?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::MP3)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
+
+/// The consecutive audio frames of an MP3 from the first one at or after `start`, with their
+/// offsets. They end at the first bytes that aren't a frame, like an ID3v1 tag.
+fn audio_frames(bytes: &[u8], start: usize) -> Vec<(usize, FrameHeader)> {
+    let mut frames = Vec::new();
+    let Some((mut offset, _)) = find_frame(bytes, start) else {
+        return frames;
+    };
+    while let Some(header) = FrameHeader::parse(&bytes[offset..]) && offset + header.frame_len() <= bytes.len() {
+        frames.push((offset, header));
+        offset += header.frame_len();
+    }
+    frames
+}
+
+/// The kind of VBR header `frame` holds instead of audio, if any, and its offset in the frame.
+fn vbr_header(frame: &[u8], header: &FrameHeader) -> Option<(&'static [u8; 4], usize)> {
+    let xing = header.xing_offset();
+    match frame.get(xing..xing + 4) {
+        Some(b"Xing") => Some((b"Xing", xing)),
+        Some(b"Info") => Some((b"Info", xing)),
+        _ => (frame.get(36..40) == Some(b"VBRI")).then_some((b"VBRI", 36)),
+    }
+}
+
+/// Updates the frame count, byte count and seek table of a VBR header for a stream made of
+/// the header frame followed by frames of `frame_lens` bytes.
+fn update_vbr_header(frame: &mut [u8], kind: &[u8; 4], offset: usize, frame_lens: &[usize]) {
+    let frames = frame_lens.len() as u32;
+    let bytes = (frame.len() + frame_lens.iter().sum::<usize>()) as u32;
+    let put = |frame: &mut [u8], at: usize, value: u32| {
+        if let Some(field) = frame.get_mut(at..at + 4) {
+            field.copy_from_slice(&value.to_be_bytes());
+        }
+    };
+
+    if kind == b"VBRI" {
+        // Its seek table is made of entries of a size the header sets, it is left as it is.
+        put(frame, offset + 10, bytes);
+        put(frame, offset + 14, frames);
+        return;
+    }
+
+    let Some(flags) = frame.get(offset + 7).copied() else {
+        return;
+    };
+    let mut field = offset + 8;
+    if flags & 1 != 0 {
+        put(frame, field, frames);
+        field += 4;
+    }
+    if flags & 2 != 0 {
+        put(frame, field, bytes);
+        field += 4;
+    }
+    if flags & 4 != 0 {
+        // Entry i is where the frame i% of the way in starts, in 256ths of the stream, counting
+        // from the first audio frame like encoders do.
+        let mut starts = Vec::with_capacity(frame_lens.len());
+        let mut start = 0;
+        for len in frame_lens {
+            starts.push(start);
+            start += len;
+        }
+        let toc: Vec<u8> = (0..100)
+            .map(|entry| starts.get(entry * starts.len() / 100).map_or(0, |start| (*start as u64 * 256 / bytes as u64).min(255) as u8))
+            .collect();
+        if let Some(table) = frame.get_mut(field..field + 100) {
+            table.copy_from_slice(&toc);
+        }
+    }
+}
+
+/// The ID3v2 tag `tag` with the text frames in `edits` set, or removed for `None`. Frames that
+/// already exist keep their place, new ones come last. Tags without any frames left are
+/// dropped.
+fn edit_tag(tag: &[u8], edits: &HashMap<String, Option<String>>) -> anyhow::Result<Vec<u8>> {
+    let version = tag.get(3).copied().unwrap_or(4);
+    if !matches!(version, 3 | 4) {
+        return Err(anyhow::anyhow!("Editing ID3v2.{} tags is not supported, only 2.3 and 2.4", version));
+    }
+
+    let mut frames = id3::read_frames(tag);
+    let mut ids: Vec<_> = edits.keys().collect();
+    ids.sort();
+    for id in ids {
+        if !id3::is_text_frame(id) {
+            return Err(anyhow::anyhow!("{} is not an ID3v2 text frame, expected an ID like TIT2", id));
+        }
+        let position = frames.iter().position(|frame| frame.id == *id);
+        frames.retain(|frame| frame.id != *id);
+        if let Some(text) = &edits[id] {
+            let frame = Id3Frame { id: id.clone(), data: id3::text_frame_data(version, text) };
+            frames.insert(position.unwrap_or(frames.len()), frame);
+        }
+    }
+
+    Ok(if frames.is_empty() { Vec::new() } else { id3::write_tag(version, &frames) })
+}
+
+/// Rewrites MP3 files without decoding their audio, which there is no encoder for anyway:
+/// the text frames of [`ConvertOptions::id3_text`] are edited, and the audio is trimmed to
+/// [`ConvertOptions::mp3_trim`] at the nearest frame boundaries. Frames are copied byte for
+/// byte, VBR headers get their counts and seek table updated. Gapless playback information
+/// some encoders add isn't updated. Fails if other audio transformations are set.
+pub struct Mp3ToMp3;
+
+impl Converter for Mp3ToMp3 {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        if options.has_audio_transforms() {
+            return Err(anyhow::anyhow!("MP3 audio can't be re-encoded, only its tags can be edited and its frames trimmed"));
+        }
+
+        let bytes = std::fs::read(input_path).with_path("Cannot read", input_path)?;
+        let tag_len = id3v2_len(&bytes).min(bytes.len());
+        let frames = audio_frames(&bytes, tag_len);
+        let (Some(&(first, _)), Some(&(last, last_header))) = (frames.first(), frames.last()) else {
+            return Err(anyhow::anyhow!("No MPEG audio frame found in {}", input_path.display()));
+        };
+
+        let mut output = if options.id3_text.is_empty() { bytes[..tag_len].to_vec() } else { edit_tag(&bytes[..tag_len], &options.id3_text)? };
+        // Whatever sits between the tag and the audio, and after it, is kept as it is.
+        output.extend_from_slice(&bytes[tag_len..first]);
+        let end = last + last_header.frame_len();
+
+        match options.mp3_trim {
+            None => output.extend_from_slice(&bytes[first..end]),
+            Some((start, stop)) => {
+                let frame_of = |(offset, header): &(usize, FrameHeader)| &bytes[*offset..offset + header.frame_len()];
+                let vbr = vbr_header(frame_of(&frames[0]), &frames[0].1);
+                let audio = if vbr.is_some() { &frames[1..] } else { &frames[..] };
+
+                let frame_duration = frames[0].1.duration().as_secs_f64();
+                let boundary = |time: Duration| ((time.as_secs_f64() / frame_duration).round() as usize).min(audio.len());
+                // A range ending before it starts is empty too.
+                let (from, to) = (boundary(start), stop.map_or(audio.len(), boundary));
+                if from >= to {
+                    return Err(anyhow::anyhow!("Trimming {} to {:?}..{:?} leaves no audio frame", input_path.display(), start, stop));
+                }
+                let kept = &audio[from..to];
+
+                if let Some((kind, offset)) = vbr {
+                    let mut header_frame = frame_of(&frames[0]).to_vec();
+                    let lens: Vec<usize> = kept.iter().map(|(_, header)| header.frame_len()).collect();
+                    update_vbr_header(&mut header_frame, kind, offset, &lens);
+                    output.extend_from_slice(&header_frame);
+                }
+                for frame in kept {
+                    output.extend_from_slice(frame_of(frame));
+                }
+            }
+        }
+        output.extend_from_slice(&bytes[end..]);
+
+        std::fs::write(output_path, output).with_path("Cannot write", output_path)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::MP3)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::MP3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An MPEG-1 Layer III frame header for 128 kbit/s at 44.1 kHz in stereo.
+    const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+
+    fn probe_bytes(name: &str, bytes: &[u8]) -> anyhow::Result<AudioInfo> {
+        let path = std::env::temp_dir().join(format!("phase_change_{}_{}.mp3", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let info = probe(&path);
+        std::fs::remove_file(&path).unwrap();
+        info
+    }
+
+    #[test]
+    fn parses_frame_headers() {
+        let header = FrameHeader::parse(&FRAME_HEADER).unwrap();
+        assert_eq!((header.bitrate, header.sample_rate, header.channels), (128, 44_100, 2));
+        assert_eq!(header.frame_len(), 417);
+        assert!(FrameHeader::parse(&FRAME_HEADER[..3]).is_none());
+    }
+
+    #[test]
+    fn ignores_a_trailing_id3v1_tag() {
+        let mut bytes = FRAME_HEADER.to_vec();
+        bytes.resize(16_000, 0);
+        bytes.extend_from_slice(b"TAG");
+        bytes.resize(16_128, 0);
+        assert_eq!(probe_bytes("id3v1", &bytes).unwrap().duration, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn does_not_subtract_a_tag_overlapping_the_frame() {
+        // The frame found starts inside what looks like the last 128 bytes of an ID3v1 tag.
+        let mut bytes = b"TAG".to_vec();
+        bytes.resize(64, 0);
+        bytes.extend_from_slice(&FRAME_HEADER);
+        bytes.resize(128, 0);
+        let info = probe_bytes("overlap", &bytes).unwrap();
+        assert_eq!(info.duration, Duration::from_secs_f64(64.0 * 8.0 / 128_000.0));
+    }
+
+    #[test]
+    fn rejects_files_without_frames() {
+        assert!(probe_bytes("empty", b"").is_err());
+        assert!(probe_bytes("garbage", &[0xFF; 3]).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Rendering audio as a spectrogram image.
+
+use std::path::Path;
+
+use image::{DynamicImage, RgbImage};
+
+use crate::{converters::{audio::{apply_transforms, wav, AudioBuffer, AudioFileType}, image::{check_dimensions, encode::write_image, ImageFileType}}, options::SpectrogramOptions, ConvertOptions, Converter, FileType};
+
+/// Magnitudes this far below full scale and quieter get the first colour of the map, in dB.
+const DYNAMIC_RANGE: f32 = 100.0;
+
+/// In place radix-2 FFT of `re` and `im`, whose length must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let bits = n.trailing_zeros();
+    for index in 0..n {
+        let reversed = index.reverse_bits() >> (usize::BITS - bits);
+        if reversed > index {
+            re.swap(index, reversed);
+            im.swap(index, reversed);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for offset in 0..len / 2 {
+                let (sin, cos) = (angle * offset as f32).sin_cos();
+                let (even, odd) = (start + offset, start + offset + len / 2);
+                let odd_re = re[odd] * cos - im[odd] * sin;
+                let odd_im = re[odd] * sin + im[odd] * cos;
+                re[odd] = re[even] - odd_re;
+                im[odd] = im[even] - odd_im;
+                re[even] += odd_re;
+                im[even] += odd_im;
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// Renders the spectrogram of `buffer`, its channels mixed down to mono: time runs left to
+/// right over windows overlapping by 75%, frequency bottom to top up to half the sample
+/// rate. Magnitudes are in dB relative to a full scale sine.
+pub(crate) fn render(buffer: &AudioBuffer, spectrogram: &SpectrogramOptions, options: &ConvertOptions) -> anyhow::Result<RgbImage> {
+    let fft_size = spectrogram.fft_size;
+    if !fft_size.is_power_of_two() || !(16..=65536).contains(&fft_size) {
+        return Err(anyhow::anyhow!("Invalid FFT size {}, expected a power of two from 16 to 65536", fft_size));
+    }
+
+    let channels = buffer.channels.max(1) as usize;
+    let mono: Vec<f32> = buffer.samples.chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    let hop = fft_size / 4;
+    let columns = mono.len().saturating_sub(fft_size).div_ceil(hop) + 1;
+    let rows = fft_size / 2;
+    check_dimensions(columns as u32, rows as u32, options)?;
+
+    let window: Vec<f32> = (0..fft_size)
+        .map(|index| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * index as f32 / fft_size as f32).cos())
+        .collect();
+    // A full scale sine peaks at a quarter of the FFT size once windowed.
+    let full_scale = fft_size as f32 / 4.0;
+
+    let mut img = RgbImage::new(columns as u32, rows as u32);
+    let (mut re, mut im) = (vec![0.0; fft_size], vec![0.0; fft_size]);
+    for column in 0..columns {
+        let start = column * hop;
+        for (index, (re, im)) in re.iter_mut().zip(&mut im).enumerate() {
+            *re = mono.get(start + index).copied().unwrap_or(0.0) * window[index];
+            *im = 0.0;
+        }
+        fft(&mut re, &mut im);
+
+        for bin in 0..rows {
+            let magnitude = (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() / full_scale;
+            let db = 20.0 * magnitude.max(1e-12).log10();
+            let color = spectrogram.colormap.color((db + DYNAMIC_RANGE) / DYNAMIC_RANGE);
+            img.put_pixel(column as u32, (rows - 1 - bin) as u32, image::Rgb(color));
+        }
+    }
+    Ok(img)
+}
+
+/// Renders WAV files as spectrogram PNGs, see [`ConvertOptions::spectrogram`]. Audio
+/// transformations apply before the transform, image transformations to the rendered image.
+pub struct WavToPng;
+
+impl Converter for WavToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let (mut buffer, _) = wav::decode_with_format(input_path)?;
+        apply_transforms(&mut buffer, options)?;
+
+        let mut img = DynamicImage::ImageRgb8(render(&buffer, &options.spectrogram, options)?);
+        crate::converters::image::apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::WAV)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,427 @@
+use std::{fs::File, io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::Path, time::Duration};
+
+use crate::{converters::audio::{apply_transforms, check_layout, AudioBuffer, AudioFileType, AudioInfo, MAX_FORMAT_CHUNK_LEN}, error::PathContext, ConvertOptions, Converter, Endian, FileType};
+
+pub(crate) const WAVE_FORMAT_PCM: u16 = 1;
+pub(crate) const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// How the samples of a WAV file are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 8-bit unsigned integers centred on 128.
+    U8,
+    I16,
+    /// 24-bit signed integers, packed in three bytes.
+    I24,
+    I32,
+    /// 32-bit IEEE floating point, nominally in `-1.0..=1.0`.
+    F32,
+}
+
+impl SampleFormat {
+    pub(crate) fn from_header(header: &WavHeader) -> anyhow::Result<Self> {
+        match (header.format_tag, header.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => Ok(SampleFormat::U8),
+            (WAVE_FORMAT_PCM, 16) => Ok(SampleFormat::I16),
+            (WAVE_FORMAT_PCM, 24) => Ok(SampleFormat::I24),
+            (WAVE_FORMAT_PCM, 32) => Ok(SampleFormat::I32),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => Ok(SampleFormat::F32),
+            (format_tag, bits) => Err(anyhow::anyhow!(
+                "Unsupported WAV sample format {} with {} bits per sample", format_tag, bits
+            )),
+        }
+    }
+
+    pub fn bits_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::U8 => 8,
+            SampleFormat::I16 => 16,
+            SampleFormat::I24 => 24,
+            SampleFormat::I32 | SampleFormat::F32 => 32,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            SampleFormat::F32 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+
+    pub(crate) fn bytes_per_sample(self) -> usize {
+        self.bits_per_sample() as usize / 8
+    }
+
+    /// Reads a little endian sample.
+    pub(crate) fn read(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::U8 => (bytes[0] as f32 - 128.0) / 128.0,
+            SampleFormat::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            // Shifting the three bytes into the top of an i32 sign extends them.
+            SampleFormat::I24 => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8_388_608.0,
+            SampleFormat::I32 => (i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / 2_147_483_648.0) as f32,
+            SampleFormat::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    /// Writes a little endian sample. Integer formats use the same scale as [`Self::read`], so
+    /// decoding and encoding again gives back the original samples.
+    pub(crate) fn write<W: Write>(self, writer: &mut W, sample: f32) -> std::io::Result<()> {
+        let sample = sample.clamp(-1.0, 1.0) as f64;
+        let quantize = |scale: f64| (sample * scale).round().clamp(-scale, scale - 1.0) as i32;
+        match self {
+            SampleFormat::U8 => writer.write_all(&[(quantize(128.0) + 128) as u8]),
+            SampleFormat::I16 => writer.write_all(&(quantize(32_768.0) as i16).to_le_bytes()),
+            SampleFormat::I24 => writer.write_all(&quantize(8_388_608.0).to_le_bytes()[0..3]),
+            SampleFormat::I32 => writer.write_all(&quantize(2_147_483_648.0).to_le_bytes()),
+            SampleFormat::F32 => writer.write_all(&(sample as f32).to_le_bytes()),
+        }
+    }
+}
+
+/// The contents of a WAV file's `fmt ` chunk and the location of its `data` chunk.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WavHeader {
+    /// The sample format, with `WAVE_FORMAT_EXTENSIBLE` already resolved to its sub format.
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub block_align: u16,
+    pub data_len: u64,
+    /// Byte order of the header fields and samples, big endian for RIFX files.
+    pub endian: Endian,
+}
+
+impl WavHeader {
+    pub fn frames(&self) -> u64 {
+        self.data_len / self.block_align.max(1) as u64
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// Walks the RIFF chunks up to the `data` chunk, leaving `reader` positioned at its start.
+/// RIFX files, which store every field and sample big endian, are read too.
+pub(crate) fn read_header<R: Read + Seek>(reader: &mut R) -> anyhow::Result<WavHeader> {
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    let endian = match &riff[0..4] {
+        b"RIFF" => Endian::Little,
+        b"RIFX" => Endian::Big,
+        _ => return Err(anyhow::anyhow!("Not a WAV file")),
+    };
+    if &riff[8..12] != b"WAVE" {
+        return Err(anyhow::anyhow!("Not a WAV file"));
+    }
+    let u16_at = |bytes: &[u8], at: usize| match endian {
+        Endian::Little => u16::from_le_bytes([bytes[at], bytes[at + 1]]),
+        Endian::Big => u16::from_be_bytes([bytes[at], bytes[at + 1]]),
+    };
+    let u32_at = |bytes: &[u8], at: usize| match endian {
+        Endian::Little => u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]),
+        Endian::Big => u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]),
+    };
+
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk)
+            .map_err(|_| anyhow::anyhow!("WAV file has no data chunk"))?;
+        let len = u32_at(&chunk, 4) as u64;
+
+        match &chunk[0..4] {
+            b"fmt " => {
+                if len > MAX_FORMAT_CHUNK_LEN {
+                    return Err(anyhow::anyhow!("WAV fmt chunk of {} bytes is too long", len));
+                }
+                let mut fmt = vec![0u8; len as usize];
+                reader.read_exact(&mut fmt)?;
+                if fmt.len() < 16 {
+                    return Err(anyhow::anyhow!("WAV fmt chunk is too short"));
+                }
+
+                let mut format_tag = u16_at(&fmt, 0);
+                if format_tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+                    // The first two bytes of the sub format GUID hold the actual format tag.
+                    format_tag = u16_at(&fmt, 24);
+                }
+                format = Some((format_tag, u16_at(&fmt, 2), u32_at(&fmt, 4), u16_at(&fmt, 12), u16_at(&fmt, 14)));
+            }
+            b"data" => {
+                let (format_tag, channels, sample_rate, block_align, bits_per_sample) = format
+                    .ok_or_else(|| anyhow::anyhow!("WAV data chunk comes before the fmt chunk"))?;
+                check_layout(channels, sample_rate)?;
+                return Ok(WavHeader {
+                    format_tag,
+                    channels,
+                    sample_rate,
+                    bits_per_sample,
+                    block_align,
+                    data_len: len,
+                    endian,
+                });
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+
+        // Chunks are padded to an even length.
+        if len & 1 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+}
+
+pub(crate) fn probe(path: &Path) -> anyhow::Result<AudioInfo> {
+    let header = read_header(&mut BufReader::new(File::open(path).with_path("Cannot read", path)?))?;
+
+    Ok(AudioInfo {
+        sample_rate: header.sample_rate,
+        channels: header.channels,
+        bits_per_sample: Some(header.bits_per_sample),
+        duration: header.duration(),
+    })
+}
+
+/// The layout of the samples returned by [`decode_samples`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// How the samples were stored in the file.
+    pub format: SampleFormat,
+}
+
+/// Decodes the WAV file at `path` into interleaved samples normalised to `-1.0..=1.0`, for
+/// processing them directly instead of converting the file.
+pub fn decode_samples(path: &Path) -> anyhow::Result<(Vec<f32>, SampleSpec)> {
+    let (buffer, format) = decode_with_format(path)?;
+    let spec = SampleSpec {
+        sample_rate: buffer.sample_rate,
+        channels: buffer.channels,
+        format,
+    };
+    Ok((buffer.samples, spec))
+}
+
+/// Decodes a WAV file into normalised floating point samples, also returning the format the
+/// samples were stored in.
+pub(crate) fn decode_with_format(path: &Path) -> anyhow::Result<(AudioBuffer, SampleFormat)> {
+    let (header, format, data) = read_data(path)?;
+    Ok((samples_to_buffer(&data, format, header.channels, header.sample_rate), format))
+}
+
+/// Reverses the bytes of every sample, turning big endian samples into little endian ones and
+/// back.
+fn swap_bytes(data: &mut [u8], format: SampleFormat) {
+    if format.bytes_per_sample() > 1 {
+        data.chunks_exact_mut(format.bytes_per_sample()).for_each(|sample| sample.reverse());
+    }
+}
+
+/// Reads the raw contents of the `data` chunk of a WAV file, with the samples of RIFX files
+/// turned little endian.
+pub(crate) fn read_data(path: &Path) -> anyhow::Result<(WavHeader, SampleFormat, Vec<u8>)> {
+    let mut reader = BufReader::new(File::open(path).with_path("Cannot read", path)?);
+    let header = read_header(&mut reader)?;
+    let format = SampleFormat::from_header(&header)?;
+
+    let mut data = Vec::new();
+    reader.take(header.data_len).read_to_end(&mut data)?;
+    if header.endian == Endian::Big {
+        swap_bytes(&mut data, format);
+    }
+    Ok((header, format, data))
+}
+
+/// Normalises little endian samples laid out as in a WAV `data` chunk.
+pub(crate) fn samples_to_buffer(data: &[u8], format: SampleFormat, channels: u16, sample_rate: u32) -> AudioBuffer {
+    let samples = data.chunks_exact(format.bytes_per_sample())
+        .map(|sample| format.read(sample))
+        .collect();
+
+    AudioBuffer {
+        sample_rate,
+        channels,
+        samples,
+    }
+}
+
+/// Quantises the samples of `buffer` to `format`, laid out as in a WAV `data` chunk.
+pub(crate) fn buffer_to_samples(buffer: &AudioBuffer, format: SampleFormat) -> Vec<u8> {
+    let mut data = Vec::with_capacity(buffer.samples.len() * format.bytes_per_sample());
+    for sample in &buffer.samples {
+        // Writing to a Vec can't fail.
+        let _ = format.write(&mut data, *sample);
+    }
+    data
+}
+
+/// Writes `buffer` as a WAV file, quantising the samples to `format`.
+pub(crate) fn encode(buffer: &AudioBuffer, format: SampleFormat, endian: Endian, path: &Path) -> anyhow::Result<()> {
+    write_data(path, format, endian, buffer.channels, buffer.sample_rate, &buffer_to_samples(buffer, format))
+}
+
+/// Writes a WAV file around raw little endian `data` chunk contents. Big endian files are
+/// written as RIFX, with the samples swapped.
+pub(crate) fn write_data(path: &Path, format: SampleFormat, endian: Endian, channels: u16, sample_rate: u32, data: &[u8]) -> anyhow::Result<()> {
+    let block_align = channels.checked_mul(format.bytes_per_sample() as u16)
+        .ok_or_else(|| anyhow::anyhow!("{} channels of {}-bit samples don't fit in a WAV header", channels, format.bits_per_sample()))?;
+    let byte_rate = sample_rate.checked_mul(block_align as u32)
+        .ok_or_else(|| anyhow::anyhow!("A sample rate of {} Hz with {} channels doesn't fit in a WAV header", sample_rate, channels))?;
+    // The RIFF chunk holds the 36 bytes of header after its length and a padding byte.
+    let data_len = u32::try_from(data.len()).ok().filter(|len| len.checked_add(37).is_some())
+        .ok_or_else(|| anyhow::anyhow!("{} bytes of samples are too many for a WAV file", data.len()))?;
+    let u16_bytes = |value: u16| match endian {
+        Endian::Little => value.to_le_bytes(),
+        Endian::Big => value.to_be_bytes(),
+    };
+    let u32_bytes = |value: u32| match endian {
+        Endian::Little => value.to_le_bytes(),
+        Endian::Big => value.to_be_bytes(),
+    };
+
+    let mut writer = BufWriter::new(File::create(path).with_path("Cannot write", path)?);
+    writer.write_all(match endian {
+        Endian::Little => b"RIFF",
+        Endian::Big => b"RIFX",
+    })?;
+    // Samples are padded to an even length like any other chunk.
+    writer.write_all(&u32_bytes(36 + data_len + (data_len & 1)))?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&u32_bytes(16))?;
+    writer.write_all(&u16_bytes(format.format_tag()))?;
+    writer.write_all(&u16_bytes(channels))?;
+    writer.write_all(&u32_bytes(sample_rate))?;
+    writer.write_all(&u32_bytes(byte_rate))?;
+    writer.write_all(&u16_bytes(block_align))?;
+    writer.write_all(&u16_bytes(format.bits_per_sample()))?;
+    writer.write_all(b"data")?;
+    writer.write_all(&u32_bytes(data_len))?;
+    if endian == Endian::Big {
+        let mut data = data.to_vec();
+        swap_bytes(&mut data, format);
+        writer.write_all(&data)?;
+    } else {
+        writer.write_all(data)?;
+    }
+    if data_len & 1 == 1 {
+        writer.write_all(&[0])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// The sample format to write a WAV output in, given the format of its source.
+pub(crate) fn output_format(source: SampleFormat, options: &ConvertOptions) -> SampleFormat {
+    options.wav.map_or(source, |wav| wav.format.into())
+}
+
+/// Re-encodes a WAV file, applying the requested audio transformations and keeping its sample
+/// format unless [`ConvertOptions::wav`] asks for another. Without either, and in the byte
+/// order of [`ConvertOptions::wav_endian`] already, the file is copied unchanged.
+pub struct WavToWav;
+
+impl Converter for WavToWav {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let header = read_header(&mut BufReader::new(File::open(input_path).with_path("Cannot read", input_path)?))?;
+        let format = SampleFormat::from_header(&header)?;
+        let output_format = output_format(format, options);
+        if !options.has_audio_transforms() && output_format == format {
+            if header.endian == options.wav_endian {
+                crate::copy_unchanged(input_path, output_path)?;
+                return Ok(());
+            }
+            let (header, _, data) = read_data(input_path)?;
+            return write_data(output_path, format, options.wav_endian, header.channels, header.sample_rate, &data);
+        }
+
+        let (mut buffer, _) = decode_with_format(input_path)?;
+        apply_transforms(&mut buffer, options)?;
+        encode(&buffer, output_format, options.wav_endian, output_path)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::WAV)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Audio(AudioFileType::WAV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, data) in chunks {
+            bytes.extend_from_slice(*id);
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn fmt(channels: u16, sample_rate: u32) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        // Wrapping, the layouts tested include impossible ones.
+        fmt.extend_from_slice(&sample_rate.wrapping_mul(channels as u32 * 2).to_le_bytes());
+        fmt.extend_from_slice(&channels.wrapping_mul(2).to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt
+    }
+
+    #[test]
+    fn reads_the_header() {
+        let bytes = riff(&[(b"fmt ", &fmt(2, 44_100)), (b"data", &[0; 8])]);
+        let header = read_header(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!((header.channels, header.sample_rate, header.bits_per_sample, header.data_len), (2, 44_100, 16, 8));
+    }
+
+    #[test]
+    fn rejects_oversized_fmt_chunks() {
+        let mut bytes = riff(&[(b"fmt ", &fmt(2, 44_100))]);
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = read_header(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(error.to_string().contains("too long"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_truncated_fmt_chunks() {
+        let mut bytes = riff(&[(b"fmt ", &fmt(2, 44_100))]);
+        bytes.truncate(bytes.len() - 6);
+        assert!(read_header(&mut Cursor::new(bytes)).is_err());
+
+        let short = riff(&[(b"fmt ", &fmt(2, 44_100)[..8]), (b"data", &[])]);
+        assert!(read_header(&mut Cursor::new(short)).is_err());
+    }
+
+    #[test]
+    fn rejects_impossible_layouts() {
+        for (channels, sample_rate) in [(0, 44_100), (2, 0), (u16::MAX, 44_100)] {
+            let bytes = riff(&[(b"fmt ", &fmt(channels, sample_rate)), (b"data", &[])]);
+            assert!(read_header(&mut Cursor::new(bytes)).is_err(), "{} channels at {} Hz", channels, sample_rate);
+        }
+    }
+
+    #[test]
+    fn write_data_rejects_overflowing_headers() {
+        let path = std::env::temp_dir().join(format!("phase_change_wav_{}.wav", std::process::id()));
+        assert!(write_data(&path, SampleFormat::I24, Endian::Little, 40_000, 44_100, &[]).is_err());
+        assert!(write_data(&path, SampleFormat::F32, Endian::Little, 8, u32::MAX / 4, &[]).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,138 @@
+//! Converting with external command line tools.
+
+use std::{ffi::OsString, path::{Path, PathBuf}, process::Command};
+
+use crate::{ConvertOptions, Converter, FileType};
+
+/// Splits a command template into words at whitespace. Single or double quotes group words
+/// with spaces, there are no escapes.
+fn split_words(template: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for char in template.chars() {
+        match (quote, char) {
+            (Some(open), _) if char == open => quote = None,
+            (Some(_), _) => word.get_or_insert_with(String::new).push(char),
+            (None, '\'' | '"') => {
+                quote = Some(char);
+                word.get_or_insert_with(String::new);
+            }
+            (None, _) if char.is_whitespace() => words.extend(word.take()),
+            (None, _) => word.get_or_insert_with(String::new).push(char),
+        }
+    }
+    if let Some(open) = quote {
+        return Err(anyhow::anyhow!("Unterminated {} quote in command {:?}", open, template));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// The program `name` found on the `PATH`, or `name` itself if it is a path with more than a
+/// file name. Like the system does, a missing `.exe` is added on Windows.
+pub(crate) fn find_program(name: &str) -> Option<PathBuf> {
+    let program = Path::new(name);
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+    let with_suffix = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| [dir.join(program), dir.join(&with_suffix)])
+        .find(|candidate| candidate.is_file())
+}
+
+/// Replaces the placeholders of `arg` in a single pass, so a path containing one isn't
+/// substituted again.
+fn substitute(arg: &str, input: &Path, output: &Path) -> OsString {
+    let mut result = OsString::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        result.push(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{input}") {
+            result.push(input);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{output}") {
+            result.push(output);
+            rest = after;
+        } else {
+            result.push("{");
+            rest = &rest[1..];
+        }
+    }
+    result.push(rest);
+    result
+}
+
+/// Converts by running an external program, like ffmpeg or ImageMagick, for formats this crate
+/// can't handle itself. The program is started directly rather than through a shell, and the
+/// paths are substituted into its arguments after the template was split, so a path with
+/// spaces or quotes stays a single argument and can't inject commands. The options aren't
+/// passed on, the tool does the conversion as its command says.
+pub struct CommandConverter {
+    program: String,
+    args: Vec<String>,
+    from: FileType,
+    to: FileType,
+    lossy: bool,
+}
+
+impl CommandConverter {
+    /// A converter from `from` to `to` running `template`, like
+    /// `ffmpeg -y -i {input} {output}`. It takes the program and its arguments, split at
+    /// whitespace unless quoted, and must contain both the `{input}` and `{output}`
+    /// placeholders.
+    pub fn new(template: &str, from: FileType, to: FileType) -> anyhow::Result<Self> {
+        let mut words = split_words(template)?.into_iter();
+        let program = words.next().ok_or_else(|| anyhow::anyhow!("The command template is empty"))?;
+        let args: Vec<String> = words.collect();
+        for placeholder in ["{input}", "{output}"] {
+            if !args.iter().any(|arg| arg.contains(placeholder)) {
+                return Err(anyhow::anyhow!("The command {:?} has no {} argument", template, placeholder));
+            }
+        }
+        Ok(Self { program, args, from, to, lossy: false })
+    }
+
+    /// Marks the conversion as lossy, see [`Converter::is_lossy`].
+    pub fn lossy(mut self) -> Self {
+        self.lossy = true;
+        self
+    }
+
+}
+
+impl Converter for CommandConverter {
+    fn convert(&self, input_path: &Path, output_path: &Path, _options: &ConvertOptions) -> anyhow::Result<()> {
+        let args: Vec<OsString> = self.args.iter().map(|arg| substitute(arg, input_path, output_path)).collect();
+
+        let result = Command::new(&self.program).args(&args).output()
+            .map_err(|error| anyhow::anyhow!("Cannot run {}: {}", self.program, error))?;
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            return Err(anyhow::anyhow!("{} failed with {}: {}", self.program, result.status, stderr.trim()));
+        }
+        if !output_path.exists() {
+            return Err(anyhow::anyhow!("{} succeeded but didn't write {}", self.program, output_path.display()));
+        }
+        Ok(())
+    }
+
+    fn from_type(&self) -> FileType {
+        self.from
+    }
+
+    fn to_type(&self) -> FileType {
+        self.to
+    }
+
+    /// Whether the program can be found, so conversion paths avoid it when it isn't installed.
+    fn is_available(&self) -> bool {
+        find_program(&self.program).is_some()
+    }
+
+    fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+}
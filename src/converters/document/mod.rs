@@ -0,0 +1,12 @@
+#[cfg(feature = "pdf")]
+pub mod pdf;
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub enum DocumentFileType {
+    PDF,
+}
+
+impl DocumentFileType {
+    /// Every document type, in declaration order.
+    pub const ALL: [DocumentFileType; 1] = [DocumentFileType::PDF];
+}
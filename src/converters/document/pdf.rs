@@ -0,0 +1,165 @@
+//! Writing images as single page PDFs.
+
+use std::{io::Write, path::Path};
+
+use image::DynamicImage;
+
+use crate::{converters::{document::DocumentFileType, image::{apply_transforms, encode::drop_alpha, metadata, open_image, ImageFileType}}, error::PathContext, options::DEFAULT_PDF_DPI, ConvertOptions, Converter, FileType};
+
+/// Deflates 8-bit samples with the PNG encoder, returning the contents of its IDAT chunks. PDF
+/// reads them as they are with the PNG predictors, so every row keeps the filter the encoder
+/// chose for it.
+fn deflate_rows(width: u32, height: u32, color: png::ColorType, samples: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(samples)?;
+    writer.finish()?;
+
+    // The 8 byte signature, then chunks of a length, a type, the data and a CRC.
+    let mut idat = Vec::new();
+    let mut chunks = &png[8..];
+    while chunks.len() >= 12 {
+        let len = u32::from_be_bytes([chunks[0], chunks[1], chunks[2], chunks[3]]) as usize;
+        if &chunks[4..8] == b"IDAT" {
+            idat.extend_from_slice(&chunks[8..8 + len]);
+        }
+        chunks = &chunks[12 + len..];
+    }
+    Ok(idat)
+}
+
+/// An image XObject dictionary and its deflated samples.
+fn image_object(width: u32, height: u32, gray: bool, samples: &[u8], soft_mask: Option<usize>) -> anyhow::Result<Vec<u8>> {
+    let (color, color_space, colors) = if gray {
+        (png::ColorType::Grayscale, "DeviceGray", 1)
+    } else {
+        (png::ColorType::Rgb, "DeviceRGB", 3)
+    };
+    let data = deflate_rows(width, height, color, samples)?;
+    let soft_mask = soft_mask.map(|object| format!(" /SMask {} 0 R", object)).unwrap_or_default();
+
+    let mut object = format!(
+        "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} /BitsPerComponent 8{} /Filter /FlateDecode \
+         /DecodeParms << /Predictor 15 /Colors {} /BitsPerComponent 8 /Columns {} >> /Length {} >>\nstream\n",
+        width, height, color_space, soft_mask, colors, width, data.len(),
+    ).into_bytes();
+    object.extend_from_slice(&data);
+    object.extend_from_slice(b"\nendstream");
+    Ok(object)
+}
+
+/// Rounds a length in points to two decimals, plenty for a page size.
+fn points(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+/// An image to place on a page of its own, with its horizontal and vertical resolution.
+pub(crate) struct Page {
+    pub image: DynamicImage,
+    pub dpi: (f32, f32),
+}
+
+/// The resolution to place the image at `path` at: [`ConvertOptions::pdf_dpi`] if set, else
+/// the one the file declares, else [`DEFAULT_PDF_DPI`].
+pub(crate) fn page_dpi(path: &Path, options: &ConvertOptions) -> anyhow::Result<(f32, f32)> {
+    if let Some(dpi) = options.pdf_dpi {
+        return Ok((dpi, dpi));
+    }
+    let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+    Ok(metadata::read_dpi(&bytes).unwrap_or((DEFAULT_PDF_DPI, DEFAULT_PDF_DPI)))
+}
+
+/// Writes a PDF with every page the size of its image at its resolution. 16-bit images are
+/// reduced to 8 bits and translucent ones keep their transparency as a soft mask.
+pub(crate) fn write_pdf(pages: &[Page], output_path: &Path) -> anyhow::Result<()> {
+    if pages.is_empty() {
+        return Err(anyhow::anyhow!("A PDF needs at least one page"));
+    }
+
+    // The catalog and page tree come first, then the page, image, contents and soft mask of
+    // every page with the numbers they are given here.
+    let mut objects = vec![b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(), Vec::new()];
+    let mut kids = Vec::with_capacity(pages.len());
+    for Page { image: img, dpi } in pages {
+        if !(dpi.0 > 0.0 && dpi.0.is_finite() && dpi.1 > 0.0 && dpi.1.is_finite()) {
+            return Err(anyhow::anyhow!("Invalid PDF resolution {}x{} DPI, expected positive numbers", dpi.0, dpi.1));
+        }
+        let color = img.color();
+        if color.bytes_per_pixel() / color.channel_count() > 1 {
+            println!("Reducing {:?} image to 8 bits per channel for PDF", color);
+        }
+
+        let (width, height) = (img.width(), img.height());
+        let gray = !color.has_color();
+        let samples = if gray { img.to_luma8().into_raw() } else { img.to_rgb8().into_raw() };
+        let alpha: Option<Vec<u8>> = color.has_alpha()
+            .then(|| img.to_rgba8().pixels().map(|pixel| pixel[3]).collect())
+            .filter(|alpha: &Vec<u8>| alpha.iter().any(|alpha| *alpha < u8::MAX));
+
+        let page = objects.len() + 1;
+        let (page_width, page_height) = (points(width as f32 * 72.0 / dpi.0), points(height as f32 * 72.0 / dpi.1));
+        let contents = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q", page_width, page_height);
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+            page_width, page_height, page + 1, page + 2,
+        ).into_bytes());
+        objects.push(image_object(width, height, gray, &samples, alpha.as_ref().map(|_| page + 3))?);
+        objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", contents.len(), contents).into_bytes());
+        if let Some(alpha) = &alpha {
+            objects.push(image_object(width, height, true, alpha, None)?);
+        }
+        kids.push(format!("{} 0 R", page));
+    }
+    objects[1] = format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), kids.len()).into_bytes();
+
+    // The binary comment tells tools the file isn't plain text.
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        writeln!(pdf, "{} 0 obj", index + 1)?;
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+
+    // Every entry of the cross-reference table is exactly 20 bytes long.
+    let xref = pdf.len();
+    write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1)?;
+    for offset in offsets {
+        writeln!(pdf, "{:010} 00000 n ", offset)?;
+    }
+    write!(pdf, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref)?;
+
+    std::fs::write(output_path, pdf).with_path("Cannot write", output_path)
+}
+
+/// Decodes the image at `path` for a page, with the image transformations applied.
+pub(crate) fn open_page(path: &Path, options: &ConvertOptions) -> anyhow::Result<Page> {
+    let mut img = open_image(path, options)?;
+    apply_transforms(&mut img, options);
+    if options.force_alpha == Some(false) && img.color().has_alpha() {
+        img = drop_alpha(&img);
+    }
+    Ok(Page { image: img, dpi: page_dpi(path, options)? })
+}
+
+/// Places PNGs on a single page PDF, sized to the image at its resolution, see
+/// [`page_dpi`]. The pixels are kept losslessly.
+pub struct PngToPdf;
+
+impl Converter for PngToPdf {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        write_pdf(&[open_page(input_path, options)?], output_path)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Document(DocumentFileType::PDF)
+    }
+}
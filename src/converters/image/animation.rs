@@ -0,0 +1,232 @@
+use std::{fs::File, io::{BufReader, Write}, path::Path, time::Duration};
+
+use image::{codecs::{gif::GifDecoder, webp::{WebPDecoder, WebPEncoder}}, AnimationDecoder, DynamicImage, ImageEncoder, RgbaImage};
+
+use crate::{converters::image::{apply_transforms, check_dimensions, gif::{quantize, quantize_together}, open_image, ImageFileType}, error::PathContext, options::{GifLoop, GifPalette}, report::AnimationInfo, ConvertOptions};
+
+/// WebP frame flag asking for the frame to replace the canvas instead of being blended onto it.
+const WEBP_NO_BLEND: u8 = 0x02;
+const WEBP_ANIMATION_FLAG: u8 = 0x02;
+const WEBP_ALPHA_FLAG: u8 = 0x10;
+
+/// A frame of an animation, already composited onto the full canvas.
+pub(crate) struct AnimationFrame {
+    pub image: RgbaImage,
+    pub delay: Duration,
+}
+
+/// Decodes every frame of an animated GIF or WebP, running the image transformations on each.
+/// Still images give a single frame.
+pub(crate) fn decode_frames(path: &Path, format: ImageFileType, options: &ConvertOptions) -> anyhow::Result<Vec<AnimationFrame>> {
+    let reader = BufReader::new(File::open(path).with_path("Cannot read", path)?);
+    let frames = match format {
+        ImageFileType::GIF => GifDecoder::new(reader)?.into_frames(),
+        ImageFileType::WEBP => {
+            let decoder = WebPDecoder::new(reader)?;
+            // Still WebPs have no frames to iterate over.
+            if !decoder.has_animation() {
+                let mut img = open_image(path, options)?;
+                apply_transforms(&mut img, options);
+                return Ok(vec![AnimationFrame { image: img.into_rgba8(), delay: Duration::ZERO }]);
+            }
+            decoder.into_frames()
+        }
+        _ => return Err(anyhow::anyhow!("{:?} images can't be animated", format)),
+    };
+
+    let mut decoded = Vec::new();
+    let mut canvas = None;
+    for frame in frames {
+        let frame = frame?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay = Duration::from_secs_f64(numerator as f64 / denominator.max(1) as f64 / 1000.0);
+
+        let mut img = DynamicImage::ImageRgba8(frame.into_buffer());
+        if canvas.is_none() {
+            check_dimensions(img.width(), img.height(), options)?;
+        }
+        apply_transforms(&mut img, options);
+
+        let size = (img.width(), img.height());
+        if *canvas.get_or_insert(size) != size {
+            return Err(anyhow::anyhow!("Image transformations gave the frames of {} different sizes", path.display()));
+        }
+        decoded.push(AnimationFrame { image: img.into_rgba8(), delay });
+    }
+
+    if decoded.is_empty() {
+        return Err(anyhow::anyhow!("{} has no frames", path.display()));
+    }
+    Ok(decoded)
+}
+
+/// Writes `frames` as a GIF looping and with palettes as [`ConvertOptions::gif_loop`] and
+/// [`ConvertOptions::gif_palette`] say.
+pub(crate) fn write_gif_frames(frames: &[AnimationFrame], path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+    let (width, height) = frames[0].image.dimensions();
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(anyhow::anyhow!("GIF images can be at most 65535 pixels wide and high"));
+    };
+
+    let (global_palette, mut shared) = match options.gif_palette {
+        GifPalette::PerFrame => (Vec::new(), None),
+        GifPalette::Global => {
+            let images: Vec<&RgbaImage> = frames.iter().map(|frame| &frame.image).collect();
+            let (palette, indices, transparent) = quantize_together(&images, options.gif_dither);
+            (palette, Some((indices.into_iter(), transparent)))
+        }
+    };
+
+    let mut encoder = gif::Encoder::new(File::create(path).with_path("Cannot write", path)?, width, height, &global_palette)?;
+    match options.gif_loop {
+        GifLoop::Forever => encoder.set_repeat(gif::Repeat::Infinite)?,
+        GifLoop::Repeat(repeats) => encoder.set_repeat(gif::Repeat::Finite(repeats))?,
+        // Without a loop extension viewers play the animation once.
+        GifLoop::Once => {}
+    }
+    for frame in frames {
+        let mut gif_frame = match &mut shared {
+            Some((indices, transparent)) => gif::Frame::from_indexed_pixels(width, height, indices.next().unwrap_or_default(), *transparent),
+            None => {
+                let paletted = quantize(&frame.image, options.gif_dither);
+                gif::Frame::from_palette_pixels(width, height, paletted.indices, paletted.palette, paletted.transparent)
+            }
+        };
+        // GIF delays are in hundredths of a second.
+        gif_frame.delay = (frame.delay.as_millis() as f64 / 10.0).round().min(u16::MAX as f64) as u16;
+        // Every frame covers the whole canvas, clear it so transparent areas don't show the previous one.
+        gif_frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&gif_frame)?;
+    }
+    Ok(())
+}
+
+/// Writes `frames` as a looping animated WebP with lossless frames.
+pub(crate) fn write_webp_frames(frames: &[AnimationFrame], path: &Path) -> anyhow::Result<()> {
+    let (width, height) = frames[0].image.dimensions();
+    let has_alpha = frames.iter().any(|frame| frame.image.pixels().any(|pixel| pixel[3] < u8::MAX));
+
+    let mut chunks = Vec::new();
+    let mut vp8x = vec![WEBP_ANIMATION_FLAG | if has_alpha { WEBP_ALPHA_FLAG } else { 0 }, 0, 0, 0];
+    vp8x.extend_from_slice(&u24(width - 1));
+    vp8x.extend_from_slice(&u24(height - 1));
+    push_chunk(&mut chunks, b"VP8X", &vp8x);
+    // Transparent background, looping forever.
+    push_chunk(&mut chunks, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+
+    for frame in frames {
+        let mut anmf = Vec::new();
+        // The frame covers the whole canvas from its top left corner.
+        anmf.extend_from_slice(&u24(0));
+        anmf.extend_from_slice(&u24(0));
+        anmf.extend_from_slice(&u24(width - 1));
+        anmf.extend_from_slice(&u24(height - 1));
+        anmf.extend_from_slice(&u24(frame.delay.as_millis().min(0xFF_FFFF) as u32));
+        anmf.push(WEBP_NO_BLEND);
+        anmf.extend_from_slice(&encode_vp8l(&frame.image)?);
+        push_chunk(&mut chunks, b"ANMF", &anmf);
+    }
+
+    let mut output = File::create(path).with_path("Cannot write", path)?;
+    output.write_all(b"RIFF")?;
+    output.write_all(&(4 + chunks.len() as u32).to_le_bytes())?;
+    output.write_all(b"WEBP")?;
+    output.write_all(&chunks)?;
+    Ok(())
+}
+
+/// Writes `frames` as a looping animated PNG. Viewers without APNG support show the first frame.
+pub(crate) fn write_apng_frames(frames: &[AnimationFrame], path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+    let (width, height) = frames[0].image.dimensions();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(File::create(path).with_path("Cannot write", path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    if options.embed_srgb.unwrap_or(true) {
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    }
+
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.set_frame_delay(frame.delay.as_millis().min(u16::MAX as u128) as u16, 1000)?;
+        writer.write_image_data(frame.image.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+fn push_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// Encodes a single lossless frame and returns its `VP8L` chunk, header included.
+fn encode_vp8l(img: &RgbaImage) -> anyhow::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    WebPEncoder::new_lossless(&mut encoded).write_image(img.as_raw(), img.width(), img.height(), image::ExtendedColorType::Rgba8)?;
+
+    // Skip the RIFF header and look for the bitstream among the chunks.
+    let mut offset = 12;
+    while offset + 8 <= encoded.len() {
+        let len = u32::from_le_bytes(encoded[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let end = (offset + 8 + len + len % 2).min(encoded.len());
+        if &encoded[offset..offset + 4] == b"VP8L" {
+            return Ok(encoded[offset..end].to_vec());
+        }
+        offset = end;
+    }
+
+    Err(anyhow::anyhow!("The WebP encoder didn't produce a VP8L bitstream"))
+}
+
+/// Counts the frames of a GIF or WebP file and adds up their delays, without decoding them.
+pub(crate) fn read_timing(path: &Path, format: ImageFileType) -> anyhow::Result<AnimationInfo> {
+    match format {
+        ImageFileType::GIF => {
+            let mut options = gif::DecodeOptions::new();
+            options.set_color_output(gif::ColorOutput::Indexed);
+            let mut decoder = options.read_info(BufReader::new(File::open(path).with_path("Cannot read", path)?))?;
+            let mut info = AnimationInfo { frames: 0, duration: Duration::ZERO };
+            while let Some(frame) = decoder.next_frame_info()? {
+                info.frames += 1;
+                // GIF delays are in hundredths of a second.
+                info.duration += Duration::from_millis(frame.delay as u64 * 10);
+            }
+            Ok(info)
+        }
+        ImageFileType::WEBP => {
+            let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+            if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+                return Err(anyhow::anyhow!("{} is not a WebP file", path.display()));
+            }
+
+            let mut info = AnimationInfo { frames: 0, duration: Duration::ZERO };
+            let mut offset = 12;
+            while offset + 8 <= bytes.len() {
+                let len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+                let data = &bytes[offset + 8..(offset + 8 + len).min(bytes.len())];
+                match &bytes[offset..offset + 4] {
+                    // The duration follows the position and size of the frame, 24 bits each.
+                    b"ANMF" if data.len() >= 15 => {
+                        info.frames += 1;
+                        info.duration += Duration::from_millis(u32::from_le_bytes([data[12], data[13], data[14], 0]) as u64);
+                    }
+                    b"VP8 " | b"VP8L" => info.frames = 1,
+                    _ => {}
+                }
+                offset += 8 + len + len % 2;
+            }
+            Ok(info)
+        }
+        _ => Err(anyhow::anyhow!("{:?} images can't be animated", format)),
+    }
+}
@@ -0,0 +1,54 @@
+use std::{path::Path, sync::OnceLock};
+
+use crate::{converters::image::{apply_transforms, encode::write_image, open_image, ImageFileType}, ConvertOptions, Converter, FileType};
+
+pub struct PngToAvif;
+
+impl Converter for PngToAvif {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::AVIF, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::AVIF)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+/// Decodes AVIFs to PNG. Needs an AV1 decoder, which the `image` crate only has when built
+/// with its `avif-native` feature.
+pub struct AvifToPng;
+
+impl Converter for AvifToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::AVIF)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn is_available(&self) -> bool {
+        // `ImageFormat::reading_enabled` says yes as soon as encoding is enabled, ask the decoder
+        // instead. Without one even empty input is unsupported rather than invalid.
+        static AVAILABLE: OnceLock<bool> = OnceLock::new();
+        *AVAILABLE.get_or_init(|| {
+            !matches!(image::load_from_memory_with_format(&[], image::ImageFormat::Avif), Err(image::ImageError::Unsupported(_)))
+        })
+    }
+}
@@ -0,0 +1,93 @@
+//! BlurHash placeholders, see [`crate::FileConvertBuilder::to_blurhash`].
+
+use std::f32::consts::PI;
+
+use image::{imageops::FilterType, DynamicImage};
+
+const BASE83: &[u8; 83] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Images are shrunk to fit this size first, a few components can't tell the difference.
+const MAX_SIZE: u32 = 64;
+
+fn push_base83(hash: &mut String, value: u32, digits: u32) {
+    for digit in (0..digits).rev() {
+        hash.push(BASE83[(value / 83u32.pow(digit) % 83) as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+/// Square root keeping the sign, how AC components are spread over their quantisation steps.
+fn sign_sqrt(value: f32) -> f32 {
+    value.abs().sqrt().copysign(value)
+}
+
+/// Encodes `img` as a BlurHash of `x` by `y` components, each from 1 to 9. Transparency is
+/// ignored.
+pub(crate) fn encode(img: &DynamicImage, (x, y): (u32, u32)) -> anyhow::Result<String> {
+    if !(1..=9).contains(&x) || !(1..=9).contains(&y) {
+        return Err(anyhow::anyhow!("BlurHash components go from 1 to 9, got {}x{}", x, y));
+    }
+    if img.width() == 0 || img.height() == 0 {
+        return Err(anyhow::anyhow!("Cannot make a BlurHash of an empty image"));
+    }
+
+    let img = match img.width().max(img.height()) > MAX_SIZE {
+        true => img.resize(MAX_SIZE, MAX_SIZE, FilterType::Triangle),
+        false => img.clone(),
+    }.to_rgb8();
+    let (width, height) = img.dimensions();
+    let linear: Vec<[f32; 3]> = img.pixels().map(|pixel| pixel.0.map(srgb_to_linear)).collect();
+
+    let mut factors = Vec::with_capacity((x * y) as usize);
+    for j in 0..y {
+        for i in 0..x {
+            let mut factor = [0.0f32; 3];
+            for py in 0..height {
+                let basis_y = (PI * j as f32 * py as f32 / height as f32).cos();
+                for px in 0..width {
+                    let basis = basis_y * (PI * i as f32 * px as f32 / width as f32).cos();
+                    let pixel = linear[(py * width + px) as usize];
+                    for channel in 0..3 {
+                        factor[channel] += basis * pixel[channel];
+                    }
+                }
+            }
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            factors.push(factor.map(|value| value * normalisation / (width * height) as f32));
+        }
+    }
+
+    let mut hash = String::new();
+    push_base83(&mut hash, (x - 1) + (y - 1) * 9, 1);
+
+    let (dc, ac) = factors.split_first().expect("there is at least one component");
+    let max_value = match ac.iter().flatten().map(|value| value.abs()).reduce(f32::max) {
+        Some(actual_max) => {
+            let quantised_max = (actual_max * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+            push_base83(&mut hash, quantised_max, 1);
+            (quantised_max + 1) as f32 / 166.0
+        }
+        None => {
+            push_base83(&mut hash, 0, 1);
+            1.0
+        }
+    };
+
+    push_base83(&mut hash, (linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]), 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| (sign_sqrt(value / max_value) * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32);
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+
+    Ok(hash)
+}
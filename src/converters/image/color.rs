@@ -0,0 +1,115 @@
+use std::sync::OnceLock;
+
+/// Entries in the tone curve table, enough to stay within 1/65535 of the exact sRGB curve.
+const CURVE_POINTS: usize = 1024;
+
+/// A compact ICC v2 display profile describing sRGB (IEC 61966-2.1), with a tabulated
+/// tone curve and the primaries adapted to the D50 profile connection space.
+pub(crate) fn srgb_icc_profile() -> &'static [u8] {
+    static PROFILE: OnceLock<Vec<u8>> = OnceLock::new();
+    PROFILE.get_or_init(build_srgb_profile)
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(x: f64, y: f64, z: f64) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in [x, y, z] {
+        tag.extend_from_slice(&s15_fixed16(value));
+    }
+    tag
+}
+
+fn text_description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // Empty Unicode and ScriptCode descriptions.
+    tag.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+fn curve_tag() -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(CURVE_POINTS as u32).to_be_bytes());
+    for index in 0..CURVE_POINTS {
+        let encoded = index as f64 / (CURVE_POINTS - 1) as f64;
+        let linear = if encoded <= 0.04045 {
+            encoded / 12.92
+        } else {
+            ((encoded + 0.055) / 1.055).powf(2.4)
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+fn build_srgb_profile() -> Vec<u8> {
+    let curve = curve_tag();
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", text_description_tag("sRGB")),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", xyz_tag(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", xyz_tag(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", xyz_tag(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let table_len = 4 + 12 * tags.len();
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let mut written: Vec<(&[u8], usize)> = Vec::new();
+    for (signature, tag) in &tags {
+        // The three tone curves are identical and share their data.
+        let offset = match written.iter().find(|(previous, _)| *previous == tag.as_slice()) {
+            Some((_, offset)) => *offset,
+            None => {
+                let offset = 128 + table_len + data.len();
+                data.extend_from_slice(tag);
+                // Tag data is aligned to 4 bytes.
+                data.resize(data.len().next_multiple_of(4), 0);
+                written.push((tag, offset));
+                offset
+            }
+        };
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]);
+    profile.extend_from_slice(&[2, 0x10, 0, 0]);
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    // Creation date, fixed so outputs stay reproducible.
+    for part in [2000u16, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&part.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 24]);
+    // Perceptual rendering intent.
+    profile.extend_from_slice(&[0; 4]);
+    for value in [0.9642, 1.0, 0.8249] {
+        profile.extend_from_slice(&s15_fixed16(value));
+    }
+    profile.resize(128, 0);
+
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
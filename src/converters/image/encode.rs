@@ -0,0 +1,221 @@
+use std::{io::Write, path::Path};
+
+use image::{codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder}, ColorType, DynamicImage, GrayImage, ImageEncoder, RgbImage};
+
+use crate::{converters::image::{color::srgb_icc_profile, gif::write_gif, hdr::{tone_map, write_hdr}, jpeg_lossless, pnm::write_pnm, qoi::write_qoi, tga::write_tga, ImageFileType}, error::PathContext, ConvertOptions};
+
+/// AVIF encoder speed from 1 (slowest, smallest) to 10. The `image` default of 4 is too slow
+/// for interactive use.
+const AVIF_SPEED: u8 = 8;
+const AVIF_QUALITY: u8 = 80;
+/// Quality used by the `image` JPEG encoder when none is given.
+const JPEG_QUALITY: u8 = 75;
+
+/// Encodes `img` as `format` into `output_path`, honouring the encoding related options.
+pub(crate) fn write_image(img: &DynamicImage, format: ImageFileType, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+    // Floating point images hold linear light, which only HDR and EXR outputs store as it is.
+    let tone_mapped;
+    let img = if !matches!(format, ImageFileType::HDR | ImageFileType::EXR) && matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F) {
+        tone_mapped = tone_map(img, options)?;
+        &tone_mapped
+    } else {
+        img
+    };
+
+    let forced;
+    let img = match options.force_alpha {
+        Some(alpha) if format != ImageFileType::JPEG && alpha != img.color().has_alpha() => {
+            forced = if alpha { add_alpha(img) } else { drop_alpha(img) };
+            &forced
+        }
+        _ => img,
+    };
+
+    let mut output = std::fs::File::create(output_path).with_path("Cannot write", output_path)?;
+    let embed_srgb = options.embed_srgb.unwrap_or(format.is_web_format());
+
+    match format {
+        ImageFileType::PNG => {
+            let mut encoder = PngEncoder::new(&mut output);
+            if embed_srgb {
+                encoder.set_icc_profile(srgb_icc_profile().to_vec())?;
+            }
+            img.write_with_encoder(encoder)?;
+        }
+        ImageFileType::JPEG => {
+            let quality = options.quality.unwrap_or(JPEG_QUALITY);
+            let encode = |img: &DynamicImage| -> image::ImageResult<Vec<u8>> {
+                let mut bytes = Vec::new();
+                let mut encoder = JpegEncoder::new_with_quality(&mut bytes, quality);
+                if embed_srgb {
+                    encoder.set_icc_profile(srgb_icc_profile().to_vec()).map_err(image::ImageError::Unsupported)?;
+                }
+                img.write_with_encoder(encoder)?;
+                Ok(bytes)
+            };
+
+            // Encoded in memory, so a rejected image can be retried as RGB without leaving a
+            // truncated file behind.
+            let compatible = to_jpeg_compatible(img);
+            let bytes = match encode(&compatible) {
+                Err(image::ImageError::Unsupported(_)) if compatible.color() != ColorType::Rgb8 => {
+                    println!("The JPEG encoder rejected the {:?} image, encoding it as RGB instead", compatible.color());
+                    encode(&DynamicImage::ImageRgb8(compatible.to_rgb8()))?
+                }
+                result => result?,
+            };
+            let bytes = if options.jpeg_progressive == Some(true) {
+                // The encoder only writes baseline JPEGs, the coefficients are rearranged after.
+                match jpeg_lossless::to_progressive(&bytes, options) {
+                    Ok(progressive) => progressive,
+                    Err(err) => {
+                        println!("Cannot make the JPEG progressive, writing a baseline one: {}", err);
+                        bytes
+                    }
+                }
+            } else {
+                bytes
+            };
+            output.write_all(&bytes)?;
+        }
+        ImageFileType::WEBP => {
+            // Only lossless WebP encoding is available, so the quality option has no effect.
+            let near_lossless;
+            let img = match options.webp.near_lossless {
+                Some(level) => {
+                    near_lossless = snap_near_lossless(img, level);
+                    &near_lossless
+                }
+                None => img,
+            };
+            let mut encoder = WebPEncoder::new_lossless(&mut output);
+            if embed_srgb {
+                encoder.set_icc_profile(srgb_icc_profile().to_vec())?;
+            }
+            // The WebP encoder only accepts 8-bit RGB(A) input.
+            if img.color().has_alpha() {
+                img.to_rgba8().write_with_encoder(encoder)?;
+            } else {
+                img.to_rgb8().write_with_encoder(encoder)?;
+            }
+        }
+        ImageFileType::AVIF => {
+            // The AVIF encoder always tags its output as sRGB.
+            let encoder = AvifEncoder::new_with_speed_quality(&mut output, AVIF_SPEED, options.quality.unwrap_or(AVIF_QUALITY));
+            if img.color().has_alpha() {
+                img.to_rgba8().write_with_encoder(encoder)?;
+            } else {
+                img.to_rgb8().write_with_encoder(encoder)?;
+            }
+        }
+        // GIF has no use for an ICC profile, its colours are always taken as sRGB.
+        ImageFileType::GIF => write_gif(img, &mut output, options)?,
+        ImageFileType::ICO => return Err(anyhow::anyhow!("Encoding ICO images is not supported")),
+        ImageFileType::PSD => return Err(anyhow::anyhow!("Encoding PSD images is not supported")),
+        ImageFileType::TGA => write_tga(img, &mut output, options.tga_rle)?,
+        ImageFileType::QOI => write_qoi(img, &mut output)?,
+        ImageFileType::HDR => write_hdr(img, &mut output)?,
+        #[cfg(feature = "exr")]
+        ImageFileType::EXR => crate::converters::image::exr::write_exr(img, &mut output)?,
+        #[cfg(not(feature = "exr"))]
+        ImageFileType::EXR => return Err(anyhow::anyhow!("Encoding EXR images needs the exr feature")),
+        ImageFileType::PNM => write_pnm(img, &mut output)?,
+    }
+
+    Ok(())
+}
+
+/// Converts `img` to the 8-bit grayscale or RGB the JPEG encoder takes. Translucent pixels are
+/// composited over white rather than letting whatever colour hides under them show through, and
+/// a warning is printed for every reduction that loses information. Every JPEG output goes
+/// through here, whichever converter decoded the image.
+pub(crate) fn to_jpeg_compatible(img: &DynamicImage) -> DynamicImage {
+    let color = img.color();
+    if matches!(color, ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 | ColorType::Rgb32F | ColorType::Rgba32F) {
+        println!("Reducing {:?} image to 8 bits per channel for JPEG", color);
+    }
+
+    let translucent = color.has_alpha() && img.to_rgba8().pixels().any(|pixel| pixel[3] < u8::MAX);
+    if translucent {
+        println!("JPEG has no transparency, compositing the {:?} image over white", color);
+    }
+
+    let grayscale = !color.has_color();
+    match (grayscale, translucent) {
+        (true, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (false, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (true, true) => {
+            let gray = img.to_luma_alpha8();
+            DynamicImage::ImageLuma8(GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+                let [luma, alpha] = gray.get_pixel(x, y).0;
+                image::Luma([over_white(luma, alpha)])
+            }))
+        }
+        (false, true) => {
+            let rgba = img.to_rgba8();
+            DynamicImage::ImageRgb8(RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                let [r, g, b, alpha] = rgba.get_pixel(x, y).0;
+                image::Rgb([r, g, b].map(|channel| over_white(channel, alpha)))
+            }))
+        }
+    }
+}
+
+/// Adds an opaque alpha channel, keeping the bit depth.
+fn add_alpha(img: &DynamicImage) -> DynamicImage {
+    match img.color() {
+        ColorType::L8 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        ColorType::L16 => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgba16(img.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgba32F(img.to_rgba32f()),
+        _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+/// Composites translucent pixels over white and drops the alpha channel, keeping the bit depth.
+pub(crate) fn drop_alpha(img: &DynamicImage) -> DynamicImage {
+    let mut rgba = img.to_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        pixel.0[..3].iter_mut().for_each(|channel| *channel = *channel * alpha + 1.0 - alpha);
+        pixel[3] = 1.0;
+    }
+
+    let opaque = DynamicImage::ImageRgba32F(rgba);
+    match img.color() {
+        ColorType::La8 => DynamicImage::ImageLuma8(opaque.to_luma8()),
+        ColorType::La16 => DynamicImage::ImageLuma16(opaque.to_luma16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgb16(opaque.to_rgb16()),
+        ColorType::Rgba32F => DynamicImage::ImageRgb32F(opaque.to_rgb32f()),
+        _ => DynamicImage::ImageRgb8(opaque.to_rgb8()),
+    }
+}
+
+fn over_white(channel: u8, alpha: u8) -> u8 {
+    ((channel as u32 * alpha as u32 + 255 * (255 - alpha as u32) + 127) / 255) as u8
+}
+
+/// Rounds every channel to a multiple of a power of two depending on the near-lossless `level`,
+/// so the lossless encoder has fewer distinct values to deal with.
+fn snap_near_lossless(img: &DynamicImage, level: u8) -> DynamicImage {
+    let bits = (100 - level.min(100) as u32).div_ceil(20);
+    if bits == 0 {
+        return img.clone();
+    }
+
+    let step = 1u32 << bits;
+    let snap = |channel: &mut u8| {
+        let snapped = (*channel as u32 + step / 2) / step * step;
+        *channel = snapped.min(255) as u8;
+    };
+    if img.color().has_alpha() {
+        let mut rgba = img.to_rgba8();
+        // Alpha is kept exact, rounding it would make opaque areas slightly see-through.
+        rgba.pixels_mut().for_each(|pixel| pixel.0[..3].iter_mut().for_each(snap));
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = img.to_rgb8();
+        rgb.pixels_mut().for_each(|pixel| pixel.0.iter_mut().for_each(snap));
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
@@ -0,0 +1,109 @@
+use std::{io::{Seek, Write}, path::Path};
+
+use image::{codecs::openexr::OpenExrEncoder, DynamicImage};
+
+use crate::{converters::image::{apply_transforms, encode::write_image, hdr::to_linear, open_image, ImageFileType}, ConvertOptions, Converter, FileType};
+
+/// Encodes `img` as an OpenEXR of 32-bit float channels in linear light, see [`to_linear`].
+/// Floating point images are written exactly, an alpha channel is kept and premultiplied like
+/// the format specifies.
+pub(crate) fn write_exr(img: &DynamicImage, output: &mut (impl Write + Seek)) -> anyhow::Result<()> {
+    let linear = to_linear(img);
+    if !linear.color().has_alpha() {
+        linear.write_with_encoder(OpenExrEncoder::new(output))?;
+        return Ok(());
+    }
+
+    let mut rgba = linear.to_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel[3];
+        pixel.0[..3].iter_mut().for_each(|channel| *channel *= alpha);
+    }
+    DynamicImage::ImageRgba32F(rgba).write_with_encoder(OpenExrEncoder::new(output))?;
+    Ok(())
+}
+
+/// Tone-maps OpenEXRs to 8-bit PNGs after applying [`ConvertOptions::exposure`], like HDRs.
+pub struct ExrToPng;
+
+impl Converter for ExrToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::EXR)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+/// Encodes PNGs as OpenEXRs in linear light. 32-bit floats hold every 8 and 16-bit value.
+pub struct PngToExr;
+
+impl Converter for PngToExr {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::EXR, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::EXR)
+    }
+}
+
+/// Converts Radiance HDRs to OpenEXRs, keeping their light exactly.
+pub struct HdrToExr;
+
+impl Converter for HdrToExr {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::EXR, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::HDR)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::EXR)
+    }
+}
+
+/// Converts OpenEXRs to Radiance HDRs without going through 8 bits, see
+/// [`PngToHdr`](crate::converters::image::hdr::PngToHdr) for what RGBE loses.
+pub struct ExrToHdr;
+
+impl Converter for ExrToHdr {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::HDR, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::EXR)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::HDR)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
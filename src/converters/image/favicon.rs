@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+
+use image::{codecs::ico::{IcoEncoder, IcoFrame}, DynamicImage, ExtendedColorType};
+
+use crate::{converters::image::{encode::write_image, resize_filter, ImageFileType}, error::PathContext, json::JsonValue, ConvertOptions};
+
+/// Sizes stored in `favicon.ico`, so browsers and the Windows shell can each pick theirs.
+const ICO_SIZES: [u32; 3] = [16, 32, 48];
+
+/// The PNG icons of a bundle with their size, all listed in `site.webmanifest`.
+const PNG_ICONS: [(&str, u32); 3] = [
+    ("favicon-16.png", 16),
+    ("favicon-32.png", 32),
+    ("apple-touch-icon.png", 180),
+];
+
+/// Scales `img` to a `size` pixel square, cropping the longer side around the centre.
+fn square(img: &DynamicImage, size: u32, options: &ConvertOptions) -> DynamicImage {
+    let side = img.width().min(img.height());
+    img.resize_to_fill(size, size, resize_filter(options, (side, side), (size, size)))
+}
+
+/// Writes `favicon.ico`, the PNG icons and a `site.webmanifest` referencing them into `out_dir`,
+/// returning the written paths in that order. Non-square images are cropped to a square first.
+pub(crate) fn write_bundle(img: &DynamicImage, out_dir: &Path, options: &ConvertOptions) -> anyhow::Result<Vec<PathBuf>> {
+    if img.width() == 0 || img.height() == 0 {
+        return Err(anyhow::anyhow!("Cannot make favicons of an empty image"));
+    }
+    if img.width().min(img.height()) < 180 {
+        println!("Upscaling a {}x{} image to 180px for the Apple touch icon", img.width(), img.height());
+    }
+
+    let mut written = Vec::with_capacity(PNG_ICONS.len() + 2);
+
+    let frames = ICO_SIZES.iter()
+        .map(|&size| {
+            let icon = square(img, size, options).to_rgba8();
+            Ok(IcoFrame::as_png(icon.as_raw(), size, size, ExtendedColorType::Rgba8)?)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let ico_path = out_dir.join("favicon.ico");
+    IcoEncoder::new(std::fs::File::create(&ico_path).with_path("Cannot write", &ico_path)?).encode_images(&frames)?;
+    written.push(ico_path);
+
+    for (name, size) in PNG_ICONS {
+        let path = out_dir.join(name);
+        write_image(&square(img, size, options), ImageFileType::PNG, &path, options)?;
+        written.push(path);
+    }
+
+    let icons = PNG_ICONS.iter()
+        .map(|(name, size)| JsonValue::object([
+            ("src", (*name).into()),
+            ("sizes", format!("{}x{}", size, size).into()),
+            ("type", ImageFileType::PNG.mime_type().into()),
+        ]))
+        .collect();
+    let manifest_path = out_dir.join("site.webmanifest");
+    std::fs::write(&manifest_path, JsonValue::object([("icons", JsonValue::Array(icons))]).to_string())
+        .with_path("Cannot write", &manifest_path)?;
+    written.push(manifest_path);
+
+    Ok(written)
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, path::Path};
+
+use color_quant::NeuQuant;
+use image::{DynamicImage, RgbaImage};
+
+use crate::{converters::image::{animation::{decode_frames, write_gif_frames}, apply_transforms, encode::write_image, open_image, ImageFileType}, ConvertOptions, Converter, FileType};
+
+/// Pixels less opaque than this become the transparent palette entry.
+const ALPHA_THRESHOLD: u8 = 128;
+/// NeuQuant sampling factor from 1 (best) to 30 (fastest).
+const QUANTIZER_SAMPLE_FACTOR: i32 = 10;
+
+/// An image reduced to at most 256 colours, as GIF requires.
+pub(crate) struct PalettedImage {
+    /// RGB triplets, including the transparent entry if there is one.
+    pub palette: Vec<u8>,
+    pub indices: Vec<u8>,
+    pub transparent: Option<u8>,
+}
+
+/// Reduces `img` to a palette of at most 256 colours, keeping one entry for transparent
+/// pixels if it has any. Images that already fit are mapped exactly, others are quantised
+/// and, if `dither` is set, Floyd-Steinberg dithered to hide the banding.
+pub(crate) fn quantize(img: &RgbaImage, dither: bool) -> PalettedImage {
+    let has_transparency = img.pixels().any(|pixel| pixel[3] < ALPHA_THRESHOLD);
+    let max_colors = if has_transparency { 255 } else { 256 };
+
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(img.len() / 4);
+    if let Some(exact) = exact_palette(img, max_colors) {
+        for pixel in img.pixels() {
+            indices.push(if pixel[3] < ALPHA_THRESHOLD { 0 } else { exact[&[pixel[0], pixel[1], pixel[2]]] });
+        }
+        let mut colors: Vec<_> = exact.into_iter().collect();
+        colors.sort_by_key(|(_, index)| *index);
+        palette.extend(colors.into_iter().flat_map(|(color, _)| color));
+    } else {
+        let opaque: Vec<u8> = img.pixels()
+            .filter(|pixel| pixel[3] >= ALPHA_THRESHOLD)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect();
+        let quantizer = NeuQuant::new(QUANTIZER_SAMPLE_FACTOR, max_colors, &opaque);
+        palette = quantizer.color_map_rgb();
+        indices = map_pixels(img, &quantizer, &palette, dither);
+    }
+
+    let transparent = has_transparency.then(|| {
+        let index = (palette.len() / 3) as u8;
+        palette.extend([0, 0, 0]);
+        for (index_ref, pixel) in indices.iter_mut().zip(img.pixels()) {
+            if pixel[3] < ALPHA_THRESHOLD {
+                *index_ref = index;
+            }
+        }
+        index
+    });
+
+    PalettedImage { palette, indices, transparent }
+}
+
+/// Reduces all of `frames`, which have the same size, to one palette of at most 256 colours
+/// as [`quantize`] does, returning the indices of every frame.
+pub(crate) fn quantize_together(frames: &[&RgbaImage], dither: bool) -> (Vec<u8>, Vec<Vec<u8>>, Option<u8>) {
+    let (width, height) = frames[0].dimensions();
+    let stacked: Vec<u8> = frames.iter().flat_map(|frame| frame.as_raw().iter().copied()).collect();
+    let stacked = RgbaImage::from_raw(width, height * frames.len() as u32, stacked).expect("frames have the same size");
+
+    let paletted = quantize(&stacked, dither);
+    let frame_len = (width * height) as usize;
+    let indices = paletted.indices.chunks(frame_len).map(<[u8]>::to_vec).collect();
+    (paletted.palette, indices, paletted.transparent)
+}
+
+/// Assigns an index to every opaque colour of `img`, or returns `None` if there are more
+/// than `max_colors` of them.
+fn exact_palette(img: &RgbaImage, max_colors: usize) -> Option<HashMap<[u8; 3], u8>> {
+    let mut colors = HashMap::new();
+    for pixel in img.pixels().filter(|pixel| pixel[3] >= ALPHA_THRESHOLD) {
+        let next = colors.len();
+        colors.entry([pixel[0], pixel[1], pixel[2]]).or_insert(next as u8);
+        if colors.len() > max_colors {
+            return None;
+        }
+    }
+
+    Some(colors)
+}
+
+fn map_pixels(img: &RgbaImage, quantizer: &NeuQuant, palette: &[u8], dither: bool) -> Vec<u8> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let lookup = |color: [f32; 3]| {
+        let [r, g, b] = color.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+        quantizer.index_of(&[r, g, b, 255]) as u8
+    };
+
+    if !dither {
+        return img.pixels().map(|pixel| lookup([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])).collect();
+    }
+
+    // Quantisation error carried over to the pixels that haven't been mapped yet.
+    let mut error = vec![[0.0f32; 3]; width * height];
+    let mut indices = Vec::with_capacity(width * height);
+    for (i, pixel) in img.pixels().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let wanted: [f32; 3] = std::array::from_fn(|c| pixel[c] as f32 + error[i][c]);
+        let index = lookup(wanted);
+        indices.push(index);
+        if pixel[3] < ALPHA_THRESHOLD {
+            continue;
+        }
+
+        let got = &palette[index as usize * 3..index as usize * 3 + 3];
+        let diff: [f32; 3] = std::array::from_fn(|c| wanted[c] - got[c] as f32);
+        let mut spread = |dx: isize, dy: usize, weight: f32| {
+            let nx = x as isize + dx;
+            if nx >= 0 && (nx as usize) < width && y + dy < height {
+                let target = &mut error[(y + dy) * width + nx as usize];
+                for c in 0..3 {
+                    target[c] += diff[c] * weight;
+                }
+            }
+        };
+        spread(1, 0, 7.0 / 16.0);
+        spread(-1, 1, 3.0 / 16.0);
+        spread(0, 1, 5.0 / 16.0);
+        spread(1, 1, 1.0 / 16.0);
+    }
+
+    indices
+}
+
+/// Encodes `img` as a single frame GIF.
+pub(crate) fn write_gif(img: &DynamicImage, output: &mut std::fs::File, options: &ConvertOptions) -> anyhow::Result<()> {
+    let (width, height) = (u16::try_from(img.width()), u16::try_from(img.height()));
+    let (Ok(width), Ok(height)) = (width, height) else {
+        return Err(anyhow::anyhow!("GIF images can be at most 65535 pixels wide and high"));
+    };
+
+    let paletted = quantize(&img.to_rgba8(), options.gif_dither);
+    let mut encoder = gif::Encoder::new(output, width, height, &paletted.palette)?;
+    encoder.write_frame(&gif::Frame::from_indexed_pixels(width, height, paletted.indices, paletted.transparent))?;
+    Ok(())
+}
+
+/// Converts PNGs to GIF, quantising them to 256 colours.
+pub struct PngToGif;
+
+impl Converter for PngToGif {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::GIF, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::GIF)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+/// Converts WebP images to GIF, keeping every frame of animated ones along with their delays.
+pub struct WebpToGif;
+
+impl Converter for WebpToGif {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let frames = decode_frames(input_path, ImageFileType::WEBP, options)?;
+        write_gif_frames(&frames, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::WEBP)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::GIF)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
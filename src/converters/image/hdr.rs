@@ -0,0 +1,146 @@
+use std::{borrow::Cow, path::Path};
+
+use image::{codecs::hdr::HdrEncoder, ColorType, DynamicImage};
+
+use crate::{converters::image::{apply_transforms, encode::{drop_alpha, write_image}, open_image, ImageFileType}, options::ToneMapping, ConvertOptions, Converter, FileType};
+
+fn srgb_to_linear(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Brings linear light of any brightness into `0.0..=1.0` with `operator`.
+fn map(operator: ToneMapping, rgb: [f32; 3]) -> [f32; 3] {
+    let rgb = rgb.map(|channel| channel.max(0.0));
+    let mapped = match operator {
+        ToneMapping::Clamp => rgb,
+        ToneMapping::Reinhard => {
+            let luminance = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+            rgb.map(|channel| channel / (1.0 + luminance))
+        }
+        ToneMapping::Aces => rgb.map(|channel| {
+            // Narkowicz's fit expects the exposure scaled down like this.
+            let x = channel * 0.6;
+            (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+        }),
+    };
+    mapped.map(|channel| channel.clamp(0.0, 1.0))
+}
+
+/// Maps the linear light of a floating point image to 8-bit sRGB, scaled by
+/// [`ConvertOptions::exposure`] then brought into range with [`ConvertOptions::tone_mapping`].
+/// The alpha channel is kept.
+pub(crate) fn tone_map(img: &DynamicImage, options: &ConvertOptions) -> anyhow::Result<DynamicImage> {
+    if !options.exposure.is_finite() {
+        return Err(anyhow::anyhow!("Invalid exposure {}, expected a finite number of stops", options.exposure));
+    }
+    let gain = options.exposure.exp2();
+    let mut rgba = img.to_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        let mapped = map(options.tone_mapping, [pixel[0] * gain, pixel[1] * gain, pixel[2] * gain]);
+        pixel.0[..3].copy_from_slice(&mapped.map(linear_to_srgb));
+    }
+
+    let mapped = DynamicImage::ImageRgba32F(rgba);
+    Ok(if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(mapped.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(mapped.to_rgb8())
+    })
+}
+
+/// `img` as floating point linear light, keeping its alpha channel. Floating point images are
+/// taken to be linear already, others to be sRGB.
+pub(crate) fn to_linear(img: &DynamicImage) -> Cow<'_, DynamicImage> {
+    let color = img.color();
+    if matches!(color, ColorType::Rgb32F | ColorType::Rgba32F) {
+        return Cow::Borrowed(img);
+    }
+
+    let mut rgba = img.to_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        pixel.0[..3].iter_mut().for_each(|channel| *channel = srgb_to_linear(*channel));
+    }
+    let linear = DynamicImage::ImageRgba32F(rgba);
+    Cow::Owned(if color.has_alpha() { linear } else { DynamicImage::ImageRgb32F(linear.to_rgb32f()) })
+}
+
+/// Encodes `img` as a Radiance HDR, which stores linear RGB light without alpha, see
+/// [`to_linear`]. Translucent pixels are composited over white.
+pub(crate) fn write_hdr(img: &DynamicImage, output: &mut impl std::io::Write) -> anyhow::Result<()> {
+    let color = img.color();
+    let translucent = color.has_alpha() && img.to_rgba8().pixels().any(|pixel| pixel[3] < u8::MAX);
+    if translucent {
+        println!("HDR has no transparency, compositing the {:?} image over white", color);
+    }
+
+    let opaque;
+    let img = if color.has_alpha() {
+        opaque = drop_alpha(img);
+        &opaque
+    } else {
+        img
+    };
+    DynamicImage::ImageRgb32F(to_linear(img).to_rgb32f()).write_with_encoder(HdrEncoder::new(output))?;
+    Ok(())
+}
+
+/// Tone-maps Radiance HDRs to 8-bit PNGs, see [`tone_map`]. Every other 8-bit output of an
+/// HDR is tone-mapped the same way.
+pub struct HdrToPng;
+
+impl Converter for HdrToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::HDR)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+/// Encodes PNGs as Radiance HDRs in linear light, for rendering workflows.
+pub struct PngToHdr;
+
+impl Converter for PngToHdr {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::HDR, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::HDR)
+    }
+
+    /// The channels of a pixel share one exponent, so those much darker than the brightest
+    /// one lose precision.
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
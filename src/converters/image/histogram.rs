@@ -0,0 +1,60 @@
+//! Colour statistics of decoded images, see [`crate::histogram`] and [`crate::dominant_color`].
+
+use image::DynamicImage;
+
+/// Number of pixels taking every 8-bit value, per channel. Images with more bits per channel
+/// are counted after reducing them to 8, images without colour count their luma in all three
+/// colour channels and opaque images have every pixel at 255 in `alpha`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub red: [u64; 256],
+    pub green: [u64; 256],
+    pub blue: [u64; 256],
+    pub alpha: [u64; 256],
+}
+
+impl Histogram {
+    pub(crate) fn of(img: &DynamicImage) -> Self {
+        let mut histogram = Histogram { red: [0; 256], green: [0; 256], blue: [0; 256], alpha: [0; 256] };
+        for pixel in img.to_rgba8().pixels() {
+            let [r, g, b, a] = pixel.0;
+            histogram.red[r as usize] += 1;
+            histogram.green[g as usize] += 1;
+            histogram.blue[b as usize] += 1;
+            histogram.alpha[a as usize] += 1;
+        }
+        histogram
+    }
+}
+
+/// Bits of every channel a colour is bucketed by, so shades too close to tell apart count as
+/// one colour.
+const BUCKET_BITS: u32 = 4;
+
+/// The most common colour of `img`: pixels are grouped into buckets of similar colours, and
+/// the mean colour of the fullest bucket is returned. Pixels count as much as they are opaque,
+/// `None` meaning the image is fully transparent.
+pub(crate) fn dominant_color(img: &DynamicImage) -> Option<[u8; 3]> {
+    let shift = 8 - BUCKET_BITS;
+    // Total alpha and the alpha weighted sum of every channel, per bucket.
+    let mut buckets = vec![[0u64; 4]; 1 << (3 * BUCKET_BITS)];
+    for pixel in img.to_rgba8().pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a == 0 {
+            continue;
+        }
+        let index = ((r as usize >> shift) << (2 * BUCKET_BITS)) | ((g as usize >> shift) << BUCKET_BITS) | (b as usize >> shift);
+        let bucket = &mut buckets[index];
+        bucket[0] += a as u64;
+        bucket[1] += r as u64 * a as u64;
+        bucket[2] += g as u64 * a as u64;
+        bucket[3] += b as u64 * a as u64;
+    }
+
+    let [weight, r, g, b] = *buckets.iter().max_by_key(|bucket| bucket[0])?;
+    if weight == 0 {
+        return None;
+    }
+    let mean = |sum: u64| ((sum + weight / 2) / weight) as u8;
+    Some([mean(r), mean(g), mean(b)])
+}
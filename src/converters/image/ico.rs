@@ -0,0 +1,112 @@
+use std::{io::Cursor, path::Path};
+
+use image::{codecs::ico::IcoDecoder, DynamicImage};
+
+use crate::{converters::image::{apply_transforms, check_dimensions, encode::write_image, ImageFileType}, error::PathContext, ConvertOptions, Converter, FileType};
+
+const ICONDIR_SIZE: usize = 6;
+const ICONDIRENTRY_SIZE: usize = 16;
+
+/// Extracts a single image out of an ICO file. By default the entry with the highest
+/// resolution is picked, use [`IcoToPng::with_size`] to ask for a specific one instead.
+#[derive(Default)]
+pub struct IcoToPng {
+    size: Option<u32>,
+}
+
+impl IcoToPng {
+    /// Extracts the entry whose width is `size` pixels, failing if the ICO doesn't contain one.
+    pub fn with_size(size: u32) -> Self {
+        Self { size: Some(size) }
+    }
+}
+
+struct IcoEntry<'a> {
+    width: u32,
+    height: u32,
+    bit_count: u16,
+    header: &'a [u8],
+    data: &'a [u8],
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_entries(bytes: &[u8]) -> anyhow::Result<Vec<IcoEntry<'_>>> {
+    if bytes.len() < ICONDIR_SIZE || read_u16(bytes, 0) != 0 || read_u16(bytes, 2) != 1 {
+        return Err(anyhow::anyhow!("Not an ICO file"));
+    }
+
+    let count = read_u16(bytes, 4) as usize;
+    let mut entries = Vec::with_capacity(count);
+    for index in 0..count {
+        let start = ICONDIR_SIZE + index * ICONDIRENTRY_SIZE;
+        let header = bytes.get(start..start + ICONDIRENTRY_SIZE)
+            .ok_or_else(|| anyhow::anyhow!("ICO directory is truncated"))?;
+
+        let size = read_u32(header, 8) as usize;
+        let offset = read_u32(header, 12) as usize;
+        let data = offset.checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| anyhow::anyhow!("ICO entry {} points outside of the file", index))?;
+
+        // A width or height of 0 in the directory means 256 pixels.
+        let dimension = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+        entries.push(IcoEntry {
+            width: dimension(header[0]),
+            height: dimension(header[1]),
+            bit_count: read_u16(header, 6),
+            header,
+            data,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Decodes a single entry by wrapping it in an ICO of its own, which lets the `image` crate
+/// deal with both PNG compressed and BMP based entries.
+fn decode_entry(entry: &IcoEntry) -> anyhow::Result<DynamicImage> {
+    let mut ico = Vec::with_capacity(ICONDIR_SIZE + ICONDIRENTRY_SIZE + entry.data.len());
+    ico.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    ico.extend_from_slice(&entry.header[..12]);
+    ico.extend_from_slice(&((ICONDIR_SIZE + ICONDIRENTRY_SIZE) as u32).to_le_bytes());
+    ico.extend_from_slice(entry.data);
+
+    Ok(DynamicImage::from_decoder(IcoDecoder::new(Cursor::new(ico))?)?)
+}
+
+impl Converter for IcoToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let bytes = std::fs::read(input_path).with_path("Cannot read", input_path)?;
+        let entries = read_entries(&bytes)?;
+
+        let entry = match self.size {
+            Some(size) => entries.iter()
+                .filter(|entry| entry.width == size)
+                .max_by_key(|entry| entry.bit_count)
+                .ok_or_else(|| anyhow::anyhow!("ICO file has no {}px image", size))?,
+            None => entries.iter()
+                .max_by_key(|entry| (entry.width as u64 * entry.height as u64, entry.bit_count))
+                .ok_or_else(|| anyhow::anyhow!("ICO file contains no images"))?,
+        };
+
+        check_dimensions(entry.width, entry.height, options)?;
+        let mut img = decode_entry(entry)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::ICO)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::Path;
+
+use image::{DynamicImage, RgbImage};
+use zune_core::{colorspace::ColorSpace, options::DecoderOptions};
+use zune_jpeg::JpegDecoder;
+
+use crate::{converters::image::{apply_transforms, check_dimensions, encode::write_image, jpeg_lossless, open_image, ImageFileType}, copy_unchanged, error::PathContext, ConvertOptions, Converter, FileType};
+
+/// Adobe APP14 transform value for YCCK encoded images.
+const ADOBE_TRANSFORM_YCCK: u8 = 2;
+
+/// Color related facts read from the JPEG markers before the image data.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct JpegColorInfo {
+    pub components: u8,
+    /// The color transform of an Adobe APP14 marker, if there is one.
+    pub adobe_transform: Option<u8>,
+    /// Whether the frame is progressive rather than sequential.
+    pub progressive: bool,
+}
+
+impl JpegColorInfo {
+    /// Four component images without a YCCK transform store CMYK directly.
+    pub fn is_cmyk(&self) -> bool {
+        self.components == 4 && self.adobe_transform != Some(ADOBE_TRANSFORM_YCCK)
+    }
+}
+
+/// Walks the JPEG markers up to the start of the scan data.
+pub(crate) fn read_color_info(bytes: &[u8]) -> Option<JpegColorInfo> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut info = JpegColorInfo::default();
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() {
+        if bytes[offset] != 0xFF {
+            return None;
+        }
+        let marker = bytes[offset + 1];
+        // Fill bytes may precede a marker.
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+
+        let len = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        let segment = bytes.get(offset + 4..offset + 2 + len)?;
+        match marker {
+            // Every start of frame marker except DHT (C4), JPG (C8) and DAC (CC).
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                info.components = *segment.get(5)?;
+                // SOF2, SOF6, SOF10 and SOF14.
+                info.progressive = marker & 0x03 == 0x02;
+            }
+            0xEE if segment.starts_with(b"Adobe") => info.adobe_transform = segment.get(11).copied(),
+            // Start of scan, the headers are over.
+            0xDA => break,
+            _ => {}
+        }
+        offset += 2 + len;
+    }
+
+    Some(info)
+}
+
+/// Decodes a CMYK JPEG into RGB. Photoshop and other software writing an Adobe marker store
+/// the channels inverted, without one 0 means no ink. The conversion is the naive one; any
+/// embedded CMYK profile is ignored.
+pub(crate) fn decode_cmyk(bytes: &[u8], info: &JpegColorInfo, options: &ConvertOptions) -> anyhow::Result<DynamicImage> {
+    let mut decoder = JpegDecoder::new_with_options(bytes, DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::CMYK));
+    decoder.decode_headers()?;
+    let (width, height) = decoder.dimensions().ok_or_else(|| anyhow::anyhow!("JPEG has no dimensions"))?;
+    check_dimensions(width as u32, height as u32, options)?;
+
+    let cmyk = decoder.decode()?;
+    let inverted = info.adobe_transform.is_some();
+    let rgb = cmyk.chunks_exact(4)
+        .flat_map(|pixel| {
+            let (c, m, y, k) = if inverted {
+                (pixel[0], pixel[1], pixel[2], pixel[3])
+            } else {
+                (255 - pixel[0], 255 - pixel[1], 255 - pixel[2], 255 - pixel[3])
+            };
+            // Each channel now holds the amount of light left, multiply by what black lets through.
+            [c, m, y].map(|channel| ((channel as u32 * k as u32 + 127) / 255) as u8)
+        })
+        .collect();
+
+    let img = RgbImage::from_raw(width as u32, height as u32, rgb)
+        .ok_or_else(|| anyhow::anyhow!("Decoded JPEG has an unexpected size"))?;
+    Ok(DynamicImage::ImageRgb8(img))
+}
+
+pub struct JpegToPng;
+
+impl Converter for JpegToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::JPEG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
+
+/// Re-encodes JPEGs, for instance at another quality. A rotation alone is done losslessly when
+/// the image allows it, see [`crate::FileConvertBuilder::jpeg_lossless_rotate`]. Progressive
+/// sources stay progressive unless [`ConvertOptions::jpeg_progressive`] says otherwise.
+pub struct JpegToJpeg;
+
+impl Converter for JpegToJpeg {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let bytes = std::fs::read(input_path).with_path("Cannot read", input_path)?;
+        let source_progressive = read_color_info(&bytes).is_some_and(|info| info.progressive);
+        let progressive = options.jpeg_progressive.unwrap_or(source_progressive);
+
+        // Baseline results of the lossless paths can be made progressive losslessly as well,
+        // only turning a progressive JPEG into a baseline one needs a re-encode.
+        let only_rotates = options.image_filters.is_empty() && options.auto_crop_threshold.is_none() && options.resize.is_none() && options.max_dimensions.is_none() && options.quality.is_none()
+            && (progressive || !source_progressive);
+        if only_rotates && options.quarter_turns.is_multiple_of(4) && progressive == source_progressive {
+            return copy_unchanged(input_path, output_path);
+        }
+        if only_rotates {
+            let lossless = if options.quarter_turns.is_multiple_of(4) {
+                jpeg_lossless::to_progressive(&bytes, options)
+            } else {
+                jpeg_lossless::rotate(&bytes, options.quarter_turns, options)
+                    .and_then(|rotated| if progressive { jpeg_lossless::to_progressive(&rotated, options) } else { Ok(rotated) })
+            };
+            match lossless {
+                Ok(transformed) => return std::fs::write(output_path, transformed).with_path("Cannot write", output_path),
+                Err(err) => println!("Cannot transform {} losslessly, re-encoding it instead: {}", input_path.display(), err),
+            }
+        }
+
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        let options = ConvertOptions { jpeg_progressive: Some(progressive), ..options.clone() };
+        write_image(&img, ImageFileType::JPEG, output_path, &options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::JPEG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::JPEG)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,909 @@
+//! Lossless transformations of baseline JPEGs, done on the quantised DCT coefficients so that
+//! the image is never decoded to pixels and encoded again: rotations, and rewriting them as
+//! progressive JPEGs.
+
+use crate::{converters::image::check_dimensions, ConvertOptions};
+
+/// Natural (row major) index of every coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Quantised DCT coefficients of an 8x8 block, in natural order.
+type Block = [i16; 64];
+
+struct Component {
+    id: u8,
+    h: u8,
+    v: u8,
+    quant_table: u8,
+    blocks_wide: usize,
+    blocks_high: usize,
+    blocks: Vec<Block>,
+}
+
+/// A decoded baseline JPEG, down to its coefficients.
+struct Frame {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    quant_tables: [Option<[u16; 64]>; 4],
+    /// APPn and COM segments, written back unchanged.
+    metadata: Vec<(u8, Vec<u8>)>,
+}
+
+/// A canonical Huffman table as stored in a DHT segment.
+#[derive(Clone)]
+struct HuffmanTable {
+    counts: [u8; 16],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// Code and length of every symbol.
+    fn codes(&self) -> [(u16, u8); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut symbols = self.symbols.iter();
+        for (index, count) in self.counts.iter().enumerate() {
+            for symbol in symbols.by_ref().take(*count as usize) {
+                codes[*symbol as usize] = (code, index as u8 + 1);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    /// Builds the optimal table for the symbol frequencies, as in Annex K.2 of the JPEG
+    /// standard: no code is longer than 16 bits and none is all ones.
+    fn optimal(frequencies: &[u32; 256]) -> Self {
+        // Index 256 is a reserved symbol taking the all ones code.
+        let mut frequencies: Vec<u64> = frequencies.iter().map(|&frequency| frequency as u64).chain([1]).collect();
+        let mut code_sizes = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+
+        loop {
+            // The two least frequent symbols, the larger index first on ties.
+            let mut least = None::<usize>;
+            let mut second = None::<usize>;
+            for (symbol, &frequency) in frequencies.iter().enumerate() {
+                if frequency == 0 {
+                    continue;
+                }
+                if least.is_none_or(|least| frequency <= frequencies[least]) {
+                    second = least;
+                    least = Some(symbol);
+                } else if second.is_none_or(|second| frequency <= frequencies[second]) {
+                    second = Some(symbol);
+                }
+            }
+            let (Some(mut v1), Some(mut v2)) = (least, second) else {
+                break;
+            };
+
+            frequencies[v1] += frequencies[v2];
+            frequencies[v2] = 0;
+            code_sizes[v1] += 1;
+            while others[v1] != usize::MAX {
+                v1 = others[v1];
+                code_sizes[v1] += 1;
+            }
+            others[v1] = v2;
+            code_sizes[v2] += 1;
+            while others[v2] != usize::MAX {
+                v2 = others[v2];
+                code_sizes[v2] += 1;
+            }
+        }
+
+        let mut bits = [0i32; 33];
+        for &size in code_sizes.iter().filter(|&&size| size > 0) {
+            bits[size.min(32)] += 1;
+        }
+        // Shorten codes longer than 16 bits.
+        for length in (17..=32).rev() {
+            while bits[length] > 0 {
+                let mut shorter = length - 2;
+                while bits[shorter] == 0 {
+                    shorter -= 1;
+                }
+                bits[length] -= 2;
+                bits[length - 1] += 1;
+                bits[shorter + 1] += 2;
+                bits[shorter] -= 1;
+            }
+        }
+        // Give up the reserved code.
+        if let Some(longest) = (1..=16).rev().find(|&length| bits[length] > 0) {
+            bits[longest] -= 1;
+        }
+
+        let mut symbols: Vec<(usize, u8)> = (0..256)
+            .filter(|&symbol| code_sizes[symbol] > 0)
+            .map(|symbol| (code_sizes[symbol], symbol as u8))
+            .collect();
+        symbols.sort();
+
+        let mut counts = [0u8; 16];
+        for length in 1..=16 {
+            counts[length - 1] = bits[length] as u8;
+        }
+        Self { counts, symbols: symbols.into_iter().map(|(_, symbol)| symbol).collect() }
+    }
+}
+
+/// Decoding tables for a [`HuffmanTable`].
+struct HuffmanDecoder {
+    /// Largest code of every length, `-1` if there is none.
+    max_code: [i32; 17],
+    /// Index in `symbols` of the first code of every length, minus that code.
+    offsets: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    fn new(table: &HuffmanTable) -> Self {
+        let mut max_code = [-1; 17];
+        let mut offsets = [0; 17];
+        let (mut code, mut index) = (0i32, 0i32);
+        for length in 1..=16 {
+            let count = table.counts[length - 1] as i32;
+            offsets[length] = index - code;
+            if count > 0 {
+                max_code[length] = code + count - 1;
+            }
+            code = (code + count) << 1;
+            index += count;
+        }
+        Self { max_code, offsets, symbols: table.symbols.clone() }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> anyhow::Result<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | reader.bit() as i32;
+            if code <= self.max_code[length] {
+                let index = (code + self.offsets[length]) as usize;
+                return self.symbols.get(index).copied().ok_or_else(|| anyhow::anyhow!("Invalid JPEG Huffman code"));
+            }
+        }
+        Err(anyhow::anyhow!("Invalid JPEG Huffman code"))
+    }
+}
+
+/// Reads the entropy coded data of a scan, removing the stuffed zero bytes.
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    byte: u8,
+    bits_left: u8,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u8 {
+        if self.bits_left == 0 {
+            self.byte = match self.data.get(self.offset) {
+                // A marker ends the data, pad with zeros like decoders do.
+                Some(0xFF) if self.data.get(self.offset + 1) != Some(&0) => 0,
+                Some(0xFF) => {
+                    self.offset += 2;
+                    0xFF
+                }
+                Some(&byte) => {
+                    self.offset += 1;
+                    byte
+                }
+                None => 0,
+            };
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+        self.byte >> self.bits_left & 1
+    }
+
+    fn bits(&mut self, count: u8) -> u16 {
+        (0..count).fold(0, |value, _| value << 1 | self.bit() as u16)
+    }
+
+    /// Reads a restart marker, dropping the bits left in the current byte.
+    fn restart(&mut self) -> anyhow::Result<()> {
+        self.bits_left = 0;
+        match self.data.get(self.offset..self.offset + 2) {
+            Some([0xFF, 0xD0..=0xD7]) => {
+                self.offset += 2;
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("Missing JPEG restart marker")),
+        }
+    }
+}
+
+/// Turns `bits` read after a magnitude category into the signed value they encode. Categories
+/// above 15 don't fit in a coefficient, only corrupt data has them.
+fn extend(bits: u16, size: u8) -> anyhow::Result<i16> {
+    if size > 15 {
+        return Err(anyhow::anyhow!("Invalid JPEG coefficient size {}", size));
+    }
+    Ok(if size == 0 {
+        0
+    } else if bits < 1 << (size - 1) {
+        (bits as i32 - (1 << size) + 1) as i16
+    } else {
+        bits as i16
+    })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> anyhow::Result<usize> {
+    let pair = bytes.get(offset..offset + 2).ok_or_else(|| anyhow::anyhow!("JPEG file is truncated"))?;
+    Ok(u16::from_be_bytes([pair[0], pair[1]]) as usize)
+}
+
+/// Decodes the coefficients of a baseline JPEG, failing before anything is allocated if its
+/// frame header declares more than [`ConvertOptions::max_pixels`].
+fn decode(bytes: &[u8], options: &ConvertOptions) -> anyhow::Result<Frame> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err(anyhow::anyhow!("Not a JPEG file"));
+    }
+
+    let mut frame = Frame { width: 0, height: 0, components: Vec::new(), quant_tables: [None; 4], metadata: Vec::new() };
+    let mut dc_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut ac_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut restart_interval = 0;
+    let mut offset = 2;
+
+    loop {
+        if bytes.get(offset) != Some(&0xFF) {
+            return Err(anyhow::anyhow!("Invalid JPEG marker at byte {}", offset));
+        }
+        let marker = *bytes.get(offset + 1).ok_or_else(|| anyhow::anyhow!("JPEG file is truncated"))?;
+        match marker {
+            0xFF => {
+                offset += 1;
+                continue;
+            }
+            0xD9 => break,
+            _ => {}
+        }
+
+        let len = read_u16(bytes, offset + 2)?;
+        let segment = bytes.get(offset + 4..offset + 2 + len.max(2)).ok_or_else(|| anyhow::anyhow!("JPEG file is truncated"))?;
+        offset += 2 + len;
+
+        match marker {
+            0xC0 | 0xC1 => {
+                if segment.first() != Some(&8) {
+                    return Err(anyhow::anyhow!("Only 8-bit JPEGs can be transformed losslessly"));
+                }
+                if !frame.components.is_empty() {
+                    return Err(anyhow::anyhow!("JPEG has more than one frame header"));
+                }
+                frame.height = read_u16(segment, 1)?;
+                frame.width = read_u16(segment, 3)?;
+                if frame.width == 0 || frame.height == 0 {
+                    return Err(anyhow::anyhow!("JPEGs without a height in their frame header can't be transformed losslessly"));
+                }
+                check_dimensions(frame.width as u32, frame.height as u32, options)?;
+                let count = *segment.get(5).ok_or_else(|| anyhow::anyhow!("JPEG frame header is truncated"))? as usize;
+                if !matches!(count, 1 | 3 | 4) {
+                    return Err(anyhow::anyhow!("Invalid JPEG component count {}", count));
+                }
+                for index in 0..count {
+                    let spec = segment.get(6 + index * 3..9 + index * 3).ok_or_else(|| anyhow::anyhow!("JPEG frame header is truncated"))?;
+                    let (h, v) = (spec[1] >> 4, spec[1] & 0x0F);
+                    if !(1..=4).contains(&h) || !(1..=4).contains(&v) {
+                        return Err(anyhow::anyhow!("Invalid JPEG sampling factors {}x{}", h, v));
+                    }
+                    frame.components.push(Component {
+                        id: spec[0],
+                        h,
+                        v,
+                        quant_table: spec[2] & 3,
+                        blocks_wide: 0,
+                        blocks_high: 0,
+                        blocks: Vec::new(),
+                    });
+                }
+
+                let h_max = frame.components.iter().map(|component| component.h as usize).max().unwrap_or(1);
+                let v_max = frame.components.iter().map(|component| component.v as usize).max().unwrap_or(1);
+                let (mcus_wide, mcus_high) = (frame.width.div_ceil(8 * h_max), frame.height.div_ceil(8 * v_max));
+                for component in &mut frame.components {
+                    component.blocks_wide = mcus_wide * component.h as usize;
+                    component.blocks_high = mcus_high * component.v as usize;
+                    component.blocks = vec![[0; 64]; component.blocks_wide * component.blocks_high];
+                }
+            }
+            0xC2..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                return Err(anyhow::anyhow!("Only baseline JPEGs can be transformed losslessly"));
+            }
+            0xC4 => {
+                let mut rest = segment;
+                while rest.len() >= 17 {
+                    let (class, id) = (rest[0] >> 4, (rest[0] & 3) as usize);
+                    let counts: [u8; 16] = rest[1..17].try_into().unwrap();
+                    let total: usize = counts.iter().map(|&count| count as usize).sum();
+                    let symbols = rest.get(17..17 + total).ok_or_else(|| anyhow::anyhow!("JPEG Huffman table is truncated"))?.to_vec();
+                    let table = Some(HuffmanTable { counts, symbols });
+                    if class == 0 { dc_tables[id] = table } else { ac_tables[id] = table }
+                    rest = &rest[17 + total..];
+                }
+            }
+            0xDB => {
+                let mut rest = segment;
+                while let Some(&spec) = rest.first() {
+                    let (precision, id) = (spec >> 4, (spec & 3) as usize);
+                    let size = if precision == 0 { 64 } else { 128 };
+                    let values = rest.get(1..1 + size).ok_or_else(|| anyhow::anyhow!("JPEG quantisation table is truncated"))?;
+                    let mut table = [0u16; 64];
+                    for (k, natural) in ZIGZAG.iter().enumerate() {
+                        table[*natural] = if precision == 0 { values[k] as u16 } else { u16::from_be_bytes([values[k * 2], values[k * 2 + 1]]) };
+                    }
+                    frame.quant_tables[id] = Some(table);
+                    rest = &rest[1 + size..];
+                }
+            }
+            0xDD => restart_interval = read_u16(segment, 0)?,
+            0xE0..=0xEF | 0xFE => frame.metadata.push((marker, segment.to_vec())),
+            0xDA => {
+                // The entropy coded data runs up to the next marker that isn't a restart.
+                let mut end = offset;
+                while end + 1 < bytes.len() && !(bytes[end] == 0xFF && bytes[end + 1] != 0 && !(0xD0..=0xD7).contains(&bytes[end + 1])) {
+                    end += 1;
+                }
+                decode_scan(&mut frame, segment, &bytes[offset..end], &dc_tables, &ac_tables, restart_interval)?;
+                offset = end;
+            }
+            _ => {}
+        }
+    }
+
+    if frame.components.is_empty() {
+        return Err(anyhow::anyhow!("JPEG has no frame header"));
+    }
+    Ok(frame)
+}
+
+fn decode_scan(frame: &mut Frame, header: &[u8], data: &[u8], dc_tables: &[Option<HuffmanTable>; 4], ac_tables: &[Option<HuffmanTable>; 4], restart_interval: usize) -> anyhow::Result<()> {
+    let count = *header.first().ok_or_else(|| anyhow::anyhow!("JPEG scan header is truncated"))? as usize;
+    let mut scan = Vec::with_capacity(count);
+    for index in 0..count {
+        let spec = header.get(1 + index * 2..3 + index * 2).ok_or_else(|| anyhow::anyhow!("JPEG scan header is truncated"))?;
+        let component = frame.components.iter().position(|component| component.id == spec[0])
+            .ok_or_else(|| anyhow::anyhow!("JPEG scan refers to an unknown component"))?;
+        let table = |tables: &[Option<HuffmanTable>; 4], id: u8| tables[(id & 3) as usize].as_ref().map(HuffmanDecoder::new)
+            .ok_or_else(|| anyhow::anyhow!("JPEG scan refers to a missing Huffman table"));
+        scan.push((component, table(dc_tables, spec[1] >> 4)?, table(ac_tables, spec[1] & 0x0F)?));
+    }
+
+    let mut reader = BitReader { data, offset: 0, byte: 0, bits_left: 0 };
+    let mut predictions = vec![0i16; scan.len()];
+    let positions = block_order(frame, &scan.iter().map(|(component, ..)| *component).collect::<Vec<_>>());
+    for (mcu, blocks) in positions.iter().enumerate() {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            reader.restart()?;
+            predictions.iter_mut().for_each(|prediction| *prediction = 0);
+        }
+
+        for &(scan_index, block_index) in blocks {
+            let (component, dc, ac) = &scan[scan_index];
+            let block = &mut frame.components[*component].blocks[block_index];
+
+            // With 8-bit samples DC differences take at most 11 bits and DC values stay within
+            // -2048..=2047, which keeps the differences the encoders compute from overflowing.
+            let size = dc.decode(&mut reader)?;
+            if size > 11 {
+                return Err(anyhow::anyhow!("Invalid JPEG DC coefficient size {}", size));
+            }
+            predictions[scan_index] = predictions[scan_index].checked_add(extend(reader.bits(size), size)?)
+                .filter(|dc| (-2048..=2047).contains(dc))
+                .ok_or_else(|| anyhow::anyhow!("Invalid JPEG DC coefficient"))?;
+            block[0] = predictions[scan_index];
+
+            let mut k = 1;
+            while k < 64 {
+                let symbol = ac.decode(&mut reader)?;
+                let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+                if size == 0 {
+                    if run != 15 {
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run;
+                if k > 63 {
+                    return Err(anyhow::anyhow!("Invalid JPEG coefficient run"));
+                }
+                block[ZIGZAG[k]] = extend(reader.bits(size), size)?;
+                k += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The blocks of every MCU of a scan over `components`, as indices into `components` and into
+/// the component's blocks. A scan of a single component has one block per MCU and only covers
+/// the blocks inside the image.
+fn block_order(frame: &Frame, components: &[usize]) -> Vec<Vec<(usize, usize)>> {
+    if let [component_index] = components {
+        let component = &frame.components[*component_index];
+        let h_max = frame.components.iter().map(|component| component.h as usize).max().unwrap_or(1);
+        let v_max = frame.components.iter().map(|component| component.v as usize).max().unwrap_or(1);
+        let wide = (frame.width * component.h as usize).div_ceil(h_max).div_ceil(8);
+        let high = (frame.height * component.v as usize).div_ceil(v_max).div_ceil(8);
+        return (0..high)
+            .flat_map(|y| (0..wide).map(move |x| vec![(0, y * component.blocks_wide + x)]))
+            .collect();
+    }
+
+    let first = &frame.components[components[0]];
+    let (mcus_wide, mcus_high) = (first.blocks_wide / first.h as usize, first.blocks_high / first.v as usize);
+    let mut order = Vec::with_capacity(mcus_wide * mcus_high);
+    for mcu_y in 0..mcus_high {
+        for mcu_x in 0..mcus_wide {
+            let mut blocks = Vec::new();
+            for (scan_index, &component_index) in components.iter().enumerate() {
+                let component = &frame.components[component_index];
+                for y in 0..component.v as usize {
+                    for x in 0..component.h as usize {
+                        let (block_x, block_y) = (mcu_x * component.h as usize + x, mcu_y * component.v as usize + y);
+                        blocks.push((scan_index, block_y * component.blocks_wide + block_x));
+                    }
+                }
+            }
+            order.push(blocks);
+        }
+    }
+    order
+}
+
+/// Rotates every block and the block grid of every component clockwise by `quarter_turns`.
+fn rotate_frame(frame: &mut Frame, quarter_turns: u8) {
+    let transposes = quarter_turns % 2 == 1;
+    for component in &mut frame.components {
+        let (wide, high) = (component.blocks_wide, component.blocks_high);
+        let mut blocks = vec![[0; 64]; wide * high];
+        for y in 0..high {
+            for x in 0..wide {
+                let block = &component.blocks[y * wide + x];
+                let mut rotated = [0i16; 64];
+                for v in 0..8 {
+                    for u in 0..8 {
+                        // Flipping a block negates its odd frequencies along the flipped axis,
+                        // transposing it swaps the horizontal and vertical frequencies.
+                        let (value, destination) = match quarter_turns {
+                            1 => (if u % 2 == 1 { -block[u * 8 + v] } else { block[u * 8 + v] }, v * 8 + u),
+                            2 => (if (u + v) % 2 == 1 { -block[v * 8 + u] } else { block[v * 8 + u] }, v * 8 + u),
+                            _ => (if v % 2 == 1 { -block[u * 8 + v] } else { block[u * 8 + v] }, v * 8 + u),
+                        };
+                        rotated[destination] = value;
+                    }
+                }
+
+                let (new_x, new_y, new_wide) = match quarter_turns {
+                    1 => (high - 1 - y, x, high),
+                    2 => (wide - 1 - x, high - 1 - y, wide),
+                    _ => (y, wide - 1 - x, high),
+                };
+                blocks[new_y * new_wide + new_x] = rotated;
+            }
+        }
+
+        component.blocks = blocks;
+        if transposes {
+            std::mem::swap(&mut component.blocks_wide, &mut component.blocks_high);
+            std::mem::swap(&mut component.h, &mut component.v);
+        }
+    }
+
+    if transposes {
+        std::mem::swap(&mut frame.width, &mut frame.height);
+        for table in frame.quant_tables.iter_mut().flatten() {
+            let original = *table;
+            for v in 0..8 {
+                for u in 0..8 {
+                    table[v * 8 + u] = original[u * 8 + v];
+                }
+            }
+        }
+    }
+}
+
+/// Writes the bits of the entropy coded data, stuffing a zero after every 0xFF byte.
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u16, count: u8) {
+        for shift in (0..count).rev() {
+            self.buffer = self.buffer << 1 | (value >> shift & 1) as u32;
+            self.bits += 1;
+            if self.bits == 8 {
+                let byte = self.buffer as u8;
+                self.output.push(byte);
+                if byte == 0xFF {
+                    self.output.push(0);
+                }
+                self.buffer = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    /// Pads the last byte with ones.
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.write(0x7F, 8 - self.bits);
+        }
+        self.output
+    }
+}
+
+/// Number of bits needed for the magnitude of `value`.
+fn magnitude_size(value: i16) -> u8 {
+    16 - value.unsigned_abs().leading_zeros() as u8
+}
+
+/// Walks the symbols and extra bits of every block of the interleaved scan.
+fn for_each_symbol(frame: &Frame, mut emit: impl FnMut(usize, bool, u8, u16, u8)) {
+    let components: Vec<usize> = (0..frame.components.len()).collect();
+    let mut predictions = vec![0i16; components.len()];
+    for blocks in block_order(frame, &components) {
+        for (component, block_index) in blocks {
+            let block = &frame.components[component].blocks[block_index];
+            let diff = block[0] - predictions[component];
+            predictions[component] = block[0];
+            let size = magnitude_size(diff);
+            let bits = if diff < 0 { (diff - 1) as u16 } else { diff as u16 };
+            emit(component, false, size, bits, size);
+
+            let mut run = 0;
+            for natural in &ZIGZAG[1..] {
+                let value = block[*natural];
+                if value == 0 {
+                    run += 1;
+                    continue;
+                }
+                while run > 15 {
+                    emit(component, true, 0xF0, 0, 0);
+                    run -= 16;
+                }
+                let size = magnitude_size(value);
+                let bits = if value < 0 { (value - 1) as u16 } else { value as u16 };
+                emit(component, true, (run << 4) | size, bits, size);
+                run = 0;
+            }
+            if run > 0 {
+                emit(component, true, 0x00, 0, 0);
+            }
+        }
+    }
+}
+
+fn push_segment(output: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    output.extend_from_slice(data);
+}
+
+/// Encodes the frame as a baseline JPEG with a single interleaved scan and optimal Huffman
+/// tables. The first component uses tables 0, the others share tables 1.
+fn encode(frame: &Frame) -> anyhow::Result<Vec<u8>> {
+    let table_of = |component: usize| (component > 0) as usize;
+    let mut frequencies = [[[0u32; 256]; 2]; 2];
+    for_each_symbol(frame, |component, ac, symbol, _, _| frequencies[ac as usize][table_of(component)][symbol as usize] += 1);
+    let tables = frequencies.map(|class| class.map(|frequencies| HuffmanTable::optimal(&frequencies)));
+
+    let mut output = vec![0xFF, 0xD8];
+    for (marker, data) in &frame.metadata {
+        push_segment(&mut output, *marker, data);
+    }
+
+    let mut dqt = Vec::new();
+    for (id, table) in frame.quant_tables.iter().enumerate() {
+        let Some(table) = table else { continue };
+        let wide = table.iter().any(|&value| value > 255);
+        dqt.push(((wide as u8) << 4) | id as u8);
+        for natural in ZIGZAG {
+            if wide {
+                dqt.extend_from_slice(&table[natural].to_be_bytes());
+            } else {
+                dqt.push(table[natural] as u8);
+            }
+        }
+    }
+    push_segment(&mut output, 0xDB, &dqt);
+
+    let mut sof = vec![8];
+    sof.extend_from_slice(&(frame.height as u16).to_be_bytes());
+    sof.extend_from_slice(&(frame.width as u16).to_be_bytes());
+    sof.push(frame.components.len() as u8);
+    for component in &frame.components {
+        sof.extend_from_slice(&[component.id, component.h << 4 | component.v, component.quant_table]);
+    }
+    push_segment(&mut output, 0xC0, &sof);
+
+    let mut dht = Vec::new();
+    let used_tables = if frame.components.len() > 1 { 2 } else { 1 };
+    for (class, class_tables) in tables.iter().enumerate() {
+        for (id, table) in class_tables.iter().enumerate().take(used_tables) {
+            dht.push((class as u8) << 4 | id as u8);
+            dht.extend_from_slice(&table.counts);
+            dht.extend_from_slice(&table.symbols);
+        }
+    }
+    push_segment(&mut output, 0xC4, &dht);
+
+    let mut sos = vec![frame.components.len() as u8];
+    for (index, component) in frame.components.iter().enumerate() {
+        let table = table_of(index) as u8;
+        sos.extend_from_slice(&[component.id, table << 4 | table]);
+    }
+    // Spectral selection over all 64 coefficients, no successive approximation.
+    sos.extend_from_slice(&[0, 63, 0]);
+    push_segment(&mut output, 0xDA, &sos);
+
+    let codes = tables.map(|class| class.map(|table| table.codes()));
+    let mut writer = BitWriter { output, buffer: 0, bits: 0 };
+    for_each_symbol(frame, |component, ac, symbol, bits, size| {
+        let (code, length) = codes[ac as usize][table_of(component)][symbol as usize];
+        writer.write(code, length);
+        writer.write(bits & ((1u32 << size) - 1) as u16, size);
+    });
+
+    let mut output = writer.finish();
+    output.extend_from_slice(&[0xFF, 0xD9]);
+    Ok(output)
+}
+
+/// Walks the symbols and extra bits of the DC coefficients of `components`, for a DC scan of a
+/// progressive JPEG.
+fn for_each_dc_symbol(frame: &Frame, components: &[usize], mut emit: impl FnMut(usize, u8, u16, u8)) {
+    let mut predictions = vec![0i16; components.len()];
+    for blocks in block_order(frame, components) {
+        for (scan_index, block_index) in blocks {
+            let component = components[scan_index];
+            let dc = frame.components[component].blocks[block_index][0];
+            let diff = dc - predictions[scan_index];
+            predictions[scan_index] = dc;
+            let size = magnitude_size(diff);
+            emit(component, size, if diff < 0 { (diff - 1) as u16 } else { diff as u16 }, size);
+        }
+    }
+}
+
+/// Walks the symbols and extra bits of a progressive AC scan over all 63 AC coefficients of a
+/// single component. Runs of blocks with nothing left to code share one end-of-band symbol.
+fn for_each_ac_symbol(frame: &Frame, component: usize, mut emit: impl FnMut(u8, u16, u8)) {
+    let mut end_of_bands = 0u16;
+    let flush = |end_of_bands: &mut u16, emit: &mut dyn FnMut(u8, u16, u8)| {
+        if *end_of_bands > 0 {
+            let size = magnitude_size(*end_of_bands as i16) - 1;
+            emit(size << 4, *end_of_bands - (1 << size), size);
+            *end_of_bands = 0;
+        }
+    };
+
+    for blocks in block_order(frame, &[component]) {
+        let block = &frame.components[component].blocks[blocks[0].1];
+        let mut run = 0;
+        for natural in &ZIGZAG[1..] {
+            let value = block[*natural];
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            flush(&mut end_of_bands, &mut emit);
+            while run > 15 {
+                emit(0xF0, 0, 0);
+                run -= 16;
+            }
+            let size = magnitude_size(value);
+            emit((run << 4) | size, if value < 0 { (value - 1) as u16 } else { value as u16 }, size);
+            run = 0;
+        }
+        if run > 0 {
+            end_of_bands += 1;
+            // The longest run a single symbol can code.
+            if end_of_bands == 0x7FFF {
+                flush(&mut end_of_bands, &mut emit);
+            }
+        }
+    }
+    flush(&mut end_of_bands, &mut emit);
+}
+
+fn push_huffman_table(output: &mut Vec<u8>, class: u8, id: u8, table: &HuffmanTable) {
+    let mut dht = vec![class << 4 | id];
+    dht.extend_from_slice(&table.counts);
+    dht.extend_from_slice(&table.symbols);
+    push_segment(output, 0xC4, &dht);
+}
+
+/// Encodes the frame as a progressive JPEG: the DC coefficients of every component first, then
+/// for each component a scan of its AC coefficients, each with their own optimal Huffman table.
+/// The DC scan is interleaved when the sampling factors allow it.
+fn encode_progressive(frame: &Frame) -> anyhow::Result<Vec<u8>> {
+    let mut output = vec![0xFF, 0xD8];
+    for (marker, data) in &frame.metadata {
+        push_segment(&mut output, *marker, data);
+    }
+
+    let mut dqt = Vec::new();
+    for (id, table) in frame.quant_tables.iter().enumerate() {
+        let Some(table) = table else { continue };
+        let wide = table.iter().any(|&value| value > 255);
+        dqt.push(((wide as u8) << 4) | id as u8);
+        for natural in ZIGZAG {
+            if wide {
+                dqt.extend_from_slice(&table[natural].to_be_bytes());
+            } else {
+                dqt.push(table[natural] as u8);
+            }
+        }
+    }
+    push_segment(&mut output, 0xDB, &dqt);
+
+    let mut sof = vec![8];
+    sof.extend_from_slice(&(frame.height as u16).to_be_bytes());
+    sof.extend_from_slice(&(frame.width as u16).to_be_bytes());
+    sof.push(frame.components.len() as u8);
+    for component in &frame.components {
+        sof.extend_from_slice(&[component.id, component.h << 4 | component.v, component.quant_table]);
+    }
+    push_segment(&mut output, 0xC2, &sof);
+
+    let blocks_per_mcu: u32 = frame.components.iter().map(|component| component.h as u32 * component.v as u32).sum();
+    let components: Vec<usize> = (0..frame.components.len()).collect();
+    let dc_scans: Vec<Vec<usize>> = if frame.components.len() <= 4 && blocks_per_mcu <= 10 {
+        vec![components.clone()]
+    } else {
+        components.iter().map(|component| vec![*component]).collect()
+    };
+
+    // The first component uses DC table 0, the others share table 1.
+    let table_of = |component: usize| (component > 0) as usize;
+    for scan in dc_scans {
+        let mut frequencies = [[0u32; 256]; 2];
+        for_each_dc_symbol(frame, &scan, |component, symbol, _, _| frequencies[table_of(component)][symbol as usize] += 1);
+        let used: Vec<usize> = (0..2).filter(|table| scan.iter().any(|component| table_of(*component) == *table)).collect();
+        let tables = frequencies.map(|frequencies| HuffmanTable::optimal(&frequencies));
+        for table in &used {
+            push_huffman_table(&mut output, 0, *table as u8, &tables[*table]);
+        }
+
+        let mut sos = vec![scan.len() as u8];
+        for component in &scan {
+            sos.extend_from_slice(&[frame.components[*component].id, (table_of(*component) as u8) << 4]);
+        }
+        sos.extend_from_slice(&[0, 0, 0]);
+        push_segment(&mut output, 0xDA, &sos);
+
+        let codes = tables.map(|table| table.codes());
+        let mut writer = BitWriter { output, buffer: 0, bits: 0 };
+        for_each_dc_symbol(frame, &scan, |component, symbol, bits, size| {
+            let (code, length) = codes[table_of(component)][symbol as usize];
+            writer.write(code, length);
+            writer.write(bits & ((1u32 << size) - 1) as u16, size);
+        });
+        output = writer.finish();
+    }
+
+    for component in components {
+        let mut frequencies = [0u32; 256];
+        for_each_ac_symbol(frame, component, |symbol, _, _| frequencies[symbol as usize] += 1);
+        let table = HuffmanTable::optimal(&frequencies);
+        push_huffman_table(&mut output, 1, 0, &table);
+        push_segment(&mut output, 0xDA, &[1, frame.components[component].id, 0x00, 1, 63, 0]);
+
+        let codes = table.codes();
+        let mut writer = BitWriter { output, buffer: 0, bits: 0 };
+        for_each_ac_symbol(frame, component, |symbol, bits, size| {
+            let (code, length) = codes[symbol as usize];
+            writer.write(code, length);
+            writer.write(bits & ((1u32 << size) - 1) as u16, size);
+        });
+        output = writer.finish();
+    }
+
+    output.extend_from_slice(&[0xFF, 0xD9]);
+    Ok(output)
+}
+
+/// Turns a baseline JPEG into a progressive one holding the same coefficients, so the image
+/// loses nothing. Fails for JPEGs that aren't baseline to begin with.
+pub(crate) fn to_progressive(bytes: &[u8], options: &ConvertOptions) -> anyhow::Result<Vec<u8>> {
+    encode_progressive(&decode(bytes, options)?)
+}
+
+/// Rotates a baseline JPEG clockwise by `quarter_turns` without decoding it to pixels, so the
+/// image loses nothing. Fails for progressive and arithmetic coded JPEGs and for images whose
+/// dimensions aren't a multiple of the MCU size, since the partial blocks along the edges
+/// would end up on the wrong side.
+pub(crate) fn rotate(bytes: &[u8], quarter_turns: u8, options: &ConvertOptions) -> anyhow::Result<Vec<u8>> {
+    let mut frame = decode(bytes, options)?;
+    let h_max = frame.components.iter().map(|component| component.h as usize).max().unwrap_or(1);
+    let v_max = frame.components.iter().map(|component| component.v as usize).max().unwrap_or(1);
+    if frame.width % (8 * h_max) != 0 || frame.height % (8 * v_max) != 0 {
+        return Err(anyhow::anyhow!(
+            "{}x{} is not a multiple of the {}x{} MCU size", frame.width, frame.height, 8 * h_max, 8 * v_max
+        ));
+    }
+    let blocks_per_mcu: u32 = frame.components.iter().map(|component| component.h as u32 * component.v as u32).sum();
+    if frame.components.len() > 4 || blocks_per_mcu > 10 {
+        return Err(anyhow::anyhow!("The JPEG's sampling factors don't fit in a single interleaved scan"));
+    }
+
+    rotate_frame(&mut frame, quarter_turns % 4);
+    encode(&frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConvertError;
+
+    /// The start of a JPEG whose frame header declares `components` as (id, sampling factors,
+    /// quantisation table), with no segment after it.
+    fn jpeg(width: u16, height: u16, components: &[(u8, u8, u8)]) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xC0];
+        bytes.extend_from_slice(&(8 + 3 * components.len() as u16).to_be_bytes());
+        bytes.push(8);
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.push(components.len() as u8);
+        for &(id, sampling, table) in components {
+            bytes.extend_from_slice(&[id, sampling, table]);
+        }
+        bytes
+    }
+
+    fn decode_error(bytes: &[u8]) -> anyhow::Error {
+        decode(bytes, &ConvertOptions::default()).err().expect("the frame header should be rejected")
+    }
+
+    #[test]
+    fn rejects_frames_above_max_pixels() {
+        let error = decode_error(&jpeg(65_535, 65_535, &[(1, 0x11, 0)]));
+        assert!(matches!(error.downcast_ref(), Some(ConvertError::TooLarge { width: 65_535, height: 65_535, .. })), "{}", error);
+    }
+
+    #[test]
+    fn rejects_invalid_component_counts() {
+        for count in [0, 2, 5, 255] {
+            let components = vec![(1, 0x11, 0); count];
+            let error = decode_error(&jpeg(8, 8, &components));
+            assert!(error.to_string().contains("component count"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sampling_factors() {
+        for sampling in [0x00, 0x10, 0x01, 0x51, 0x15, 0xFF] {
+            let error = decode_error(&jpeg(8, 8, &[(1, sampling, 0), (2, 0x11, 1), (3, 0x11, 1)]));
+            assert!(error.to_string().contains("sampling factors"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frame_headers() {
+        let mut bytes = jpeg(8, 8, &[(1, 0x22, 0), (2, 0x11, 1), (3, 0x11, 1)]);
+        bytes.truncate(bytes.len() - 4);
+        decode_error(&bytes);
+        decode_error(&[0xFF, 0xD8, 0xFF, 0xC0, 0x00]);
+        decode_error(&[0xFF, 0xD8]);
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        decode_error(&jpeg(0, 8, &[(1, 0x11, 0)]));
+        decode_error(&jpeg(8, 0, &[(1, 0x11, 0)]));
+    }
+}
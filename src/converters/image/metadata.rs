@@ -0,0 +1,311 @@
+use std::path::Path;
+
+use crate::{converters::image::ImageFileType, error::PathContext};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// EXIF tags holding a date: DateTime, DateTimeOriginal and DateTimeDigitized.
+const EXIF_DATE_TAGS: [u16; 3] = [0x0132, 0x9003, 0x9004];
+const EXIF_IFD_POINTER: u16 = 0x8769;
+/// What EXIF uses for a date that isn't known.
+const EXIF_UNKNOWN_DATE: &[u8] = b"    :  :     :  :  ";
+
+/// Removes the timestamps an image file may carry, so converting the same input always gives
+/// the same bytes. PNG `tIME` chunks are dropped and JPEG EXIF dates blanked out in place,
+/// other metadata is left alone.
+pub(crate) fn strip_timestamps(path: &Path, format: ImageFileType) -> anyhow::Result<()> {
+    let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+    let stripped = match format {
+        ImageFileType::PNG => strip_png_time(&bytes),
+        ImageFileType::JPEG => blank_jpeg_exif_dates(&bytes),
+        _ => None,
+    };
+
+    if let Some(stripped) = stripped {
+        std::fs::write(path, stripped).with_path("Cannot write", path)?;
+    }
+    Ok(())
+}
+
+/// The horizontal and vertical resolution an image file declares in dots per inch, from the
+/// `pHYs` chunk of PNGs or the JFIF header of JPEGs. `None` for other formats, files that
+/// don't declare one and ones only giving an aspect ratio.
+#[cfg(feature = "pdf")]
+pub(crate) fn read_dpi(bytes: &[u8]) -> Option<(f32, f32)> {
+    const INCHES_PER_METER: f32 = 0.0254;
+    const INCHES_PER_CENTIMETER: f32 = 2.54;
+
+    let dpi = if bytes.starts_with(PNG_SIGNATURE) {
+        let mut offset = PNG_SIGNATURE.len();
+        loop {
+            let chunk = bytes.get(offset..offset + 8)?;
+            let len = u32::from_be_bytes(chunk[0..4].try_into().unwrap()) as usize;
+            // The chunk has to come before the image data.
+            match &chunk[4..8] {
+                b"pHYs" => {
+                    let data = bytes.get(offset + 8..offset + 17)?;
+                    let x = u32::from_be_bytes(data[0..4].try_into().unwrap()) as f32;
+                    let y = u32::from_be_bytes(data[4..8].try_into().unwrap()) as f32;
+                    // Unit 1 is pixels per meter, 0 an aspect ratio only.
+                    break (data[8] == 1).then_some((x * INCHES_PER_METER, y * INCHES_PER_METER))?;
+                }
+                b"IDAT" => return None,
+                _ => offset += 12 + len,
+            }
+        }
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        let app0 = bytes.get(2..20)?;
+        if app0[0..2] != [0xFF, 0xE0] || &app0[4..9] != b"JFIF\0" {
+            return None;
+        }
+        let x = u16::from_be_bytes([app0[12], app0[13]]) as f32;
+        let y = u16::from_be_bytes([app0[14], app0[15]]) as f32;
+        match app0[11] {
+            1 => (x, y),
+            2 => (x * INCHES_PER_CENTIMETER, y * INCHES_PER_CENTIMETER),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    (dpi.0 > 0.0 && dpi.1 > 0.0).then_some(dpi)
+}
+
+/// Returns the PNG without its `tIME` chunks, or `None` if it had none.
+fn strip_png_time(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut found = false;
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        // Length, type, data and CRC.
+        let end = (offset + 12 + len).min(bytes.len());
+        if &bytes[offset + 4..offset + 8] == b"tIME" {
+            found = true;
+        } else {
+            output.extend_from_slice(&bytes[offset..end]);
+        }
+        offset = end;
+    }
+
+    found.then_some(output)
+}
+
+/// Returns the JPEG with the dates of its EXIF segment blanked, or `None` if it had none.
+fn blank_jpeg_exif_dates(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut output = bytes.to_vec();
+    let mut found = false;
+    let mut offset = 2;
+    // Metadata segments all come before the start of scan.
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF && bytes[offset + 1] != 0xDA {
+        let len = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        if len < 2 {
+            break;
+        }
+        let end = (offset + 2 + len).min(bytes.len());
+        let segment = &mut output[offset + 4..end];
+        if bytes[offset + 1] == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            found |= blank_tiff_dates(&mut segment[6..]);
+        }
+        offset = end;
+    }
+
+    found.then_some(output)
+}
+
+/// Blanks the date tags of the first IFD of a TIFF structure and of its EXIF IFD.
+fn blank_tiff_dates(tiff: &mut [u8]) -> bool {
+    let little_endian = match tiff.get(0..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return false,
+    };
+    let read_u16 = |tiff: &[u8], at: usize| -> Option<u16> {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let read_u32 = |tiff: &[u8], at: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+
+    let mut found = false;
+    let mut ifds = vec![read_u32(tiff, 4).unwrap_or(0) as usize];
+    let mut visited = Vec::new();
+    while let Some(ifd) = ifds.pop() {
+        // Guards against IFDs pointing back at each other.
+        if visited.contains(&ifd) {
+            continue;
+        }
+        visited.push(ifd);
+
+        let Some(count) = read_u16(tiff, ifd) else { continue };
+        for index in 0..count as usize {
+            let entry = ifd + 2 + index * 12;
+            let (Some(tag), Some(len), Some(value)) = (read_u16(tiff, entry), read_u32(tiff, entry + 4), read_u32(tiff, entry + 8)) else {
+                break;
+            };
+
+            if tag == EXIF_IFD_POINTER {
+                ifds.push(value as usize);
+            } else if EXIF_DATE_TAGS.contains(&tag) && len as usize > 4 {
+                // The date doesn't fit in the entry, so the value is an offset to it. The last
+                // byte is the terminating NUL.
+                let start = value as usize;
+                if let Some(date) = tiff.get_mut(start..start + len as usize - 1) {
+                    for (byte, blank) in date.iter_mut().zip(EXIF_UNKNOWN_DATE.iter().chain(std::iter::repeat(&b' '))) {
+                        *byte = *blank;
+                    }
+                    found = true;
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// Largest comment a single JPEG COM segment holds, its length field counts itself.
+const MAX_JPEG_COMMENT: usize = u16::MAX as usize - 2;
+/// WebP VP8X flag telling an XMP chunk follows the image data.
+const WEBP_XMP_FLAG: u8 = 0x04;
+/// WebP VP8X flag for images with an alpha channel.
+const WEBP_ALPHA_FLAG: u8 = 0x10;
+
+/// Embeds `comment` in the image file, as a COM segment in JPEGs and as the XMP description
+/// in WebP files. Returns `false` for other formats, which are left alone.
+pub(crate) fn write_comment(path: &Path, format: ImageFileType, comment: &str) -> anyhow::Result<bool> {
+    let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+    let commented = match format {
+        ImageFileType::JPEG => jpeg_with_comment(&bytes, comment)?,
+        ImageFileType::WEBP => webp_with_xmp(&bytes, &xmp_description(comment))?,
+        _ => return Ok(false),
+    };
+
+    std::fs::write(path, commented).with_path("Cannot write", path)?;
+    Ok(true)
+}
+
+/// Inserts a COM segment after the APPn segments, which readers expect right after the SOI.
+fn jpeg_with_comment(bytes: &[u8], comment: &str) -> anyhow::Result<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err(anyhow::anyhow!("Cannot add a comment, the output is not a JPEG"));
+    }
+    if comment.len() > MAX_JPEG_COMMENT {
+        return Err(anyhow::anyhow!("JPEG comments are limited to {} bytes, got {}", MAX_JPEG_COMMENT, comment.len()));
+    }
+
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF && (0xE0..=0xEF).contains(&bytes[offset + 1]) {
+        offset += 2 + u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+    }
+    let offset = offset.min(bytes.len());
+
+    let mut output = Vec::with_capacity(bytes.len() + comment.len() + 4);
+    output.extend_from_slice(&bytes[..offset]);
+    output.extend_from_slice(&[0xFF, 0xFE]);
+    output.extend_from_slice(&(comment.len() as u16 + 2).to_be_bytes());
+    output.extend_from_slice(comment.as_bytes());
+    output.extend_from_slice(&bytes[offset..]);
+    Ok(output)
+}
+
+/// An XMP packet holding `comment` as the Dublin Core description.
+fn xmp_description(comment: &str) -> String {
+    let mut escaped = String::with_capacity(comment.len());
+    for c in comment.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    format!(
+        "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+         <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\
+         <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\
+         </rdf:Description></rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>",
+        escaped
+    )
+}
+
+/// Width, height and whether the image has alpha, read from a simple lossy or lossless
+/// WebP bitstream, for the VP8X header it needs once it carries metadata.
+fn webp_canvas(fourcc: &[u8], data: &[u8]) -> Option<(u32, u32, bool)> {
+    match fourcc {
+        b"VP8L" if data.first() == Some(&0x2F) => {
+            let bits = u32::from_le_bytes(data.get(1..5)?.try_into().ok()?);
+            Some(((bits & 0x3FFF) + 1, (bits >> 14 & 0x3FFF) + 1, bits >> 28 & 1 == 1))
+        }
+        b"VP8 " if data.get(3..6) == Some(&[0x9D, 0x01, 0x2A]) => {
+            let width = u16::from_le_bytes(data.get(6..8)?.try_into().ok()?) & 0x3FFF;
+            let height = u16::from_le_bytes(data.get(8..10)?.try_into().ok()?) & 0x3FFF;
+            Some((width as u32, height as u32, false))
+        }
+        _ => None,
+    }
+}
+
+/// Adds an XMP chunk to a WebP file, replacing any it had. Simple files are turned into
+/// extended ones, since only those can carry metadata.
+fn webp_with_xmp(bytes: &[u8], xmp: &str) -> anyhow::Result<Vec<u8>> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err(anyhow::anyhow!("Cannot add a comment, the output is not a WebP file"));
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let end = (offset + 8 + len).min(bytes.len());
+        chunks.push((&bytes[offset..offset + 4], &bytes[offset + 8..end]));
+        // Chunks are padded to an even length.
+        offset = end + len % 2;
+    }
+    chunks.retain(|(fourcc, _)| *fourcc != b"XMP ");
+
+    let mut vp8x = match chunks.first() {
+        Some((b"VP8X", data)) if data.len() >= 10 => data.to_vec(),
+        Some((fourcc, data)) => {
+            let (width, height, alpha) = webp_canvas(fourcc, data)
+                .ok_or_else(|| anyhow::anyhow!("Cannot add a comment, the WebP image data is not recognised"))?;
+            let mut header = vec![if alpha { WEBP_ALPHA_FLAG } else { 0 }, 0, 0, 0];
+            header.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            header.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+            header
+        }
+        None => return Err(anyhow::anyhow!("Cannot add a comment, the WebP file has no image data")),
+    };
+    vp8x[0] |= WEBP_XMP_FLAG;
+    if chunks.first().is_some_and(|(fourcc, _)| *fourcc == b"VP8X") {
+        chunks.remove(0);
+    }
+
+    let mut body = b"WEBP".to_vec();
+    let mut push_chunk = |fourcc: &[u8], data: &[u8]| {
+        body.extend_from_slice(fourcc);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    };
+    push_chunk(b"VP8X", &vp8x);
+    for (fourcc, data) in chunks {
+        push_chunk(fourcc, data);
+    }
+    // XMP comes after the image data and any EXIF.
+    push_chunk(b"XMP ", xmp.as_bytes());
+
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(&body);
+    Ok(output)
+}
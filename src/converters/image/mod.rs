@@ -1,7 +1,362 @@
+pub(crate) mod animation;
+pub mod avif;
+pub(crate) mod blurhash;
+mod color;
+pub(crate) mod encode;
+#[cfg(feature = "exr")]
+pub mod exr;
+pub(crate) mod favicon;
+pub mod gif;
+pub mod hdr;
+pub mod histogram;
+pub mod ico;
+pub mod jpeg;
+pub(crate) mod jpeg_lossless;
+pub(crate) mod metadata;
 pub mod png;
+pub mod pnm;
+pub mod qoi;
+pub mod sprite;
+#[cfg(feature = "psd")]
+pub mod psd;
+pub mod tga;
+pub(crate) mod tiled;
+pub mod webp;
+
+use std::path::Path;
+
+use image::{imageops::FilterType, ColorType, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+use crate::{error::PathContext, ConvertError, ConvertOptions};
+
+/// Properties of an image file, read from its headers without decoding any pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    /// The colour type the image decodes to.
+    pub color_type: ColorType,
+}
 
 #[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
 pub enum ImageFileType {
     PNG,
     JPEG,
-}
\ No newline at end of file
+    ICO,
+    WEBP,
+    AVIF,
+    GIF,
+    /// Photoshop documents, read through their flattened composite.
+    PSD,
+    TGA,
+    /// The Netpbm formats: PBM, PGM, PPM and PAM.
+    PNM,
+    /// The Quite OK Image format, lossless and fast.
+    QOI,
+    /// Radiance RGBE images, which store linear light brighter than white, as rendered.
+    HDR,
+    /// OpenEXR images, storing linear light as floating point for VFX pipelines. Converting
+    /// them needs the `exr` feature.
+    EXR,
+}
+
+impl ImageFileType {
+    /// Every image type, in declaration order.
+    pub const ALL: [ImageFileType; 12] = [
+        ImageFileType::PNG, ImageFileType::JPEG, ImageFileType::ICO, ImageFileType::WEBP, ImageFileType::AVIF,
+        ImageFileType::GIF, ImageFileType::PSD, ImageFileType::TGA, ImageFileType::PNM, ImageFileType::QOI,
+        ImageFileType::HDR, ImageFileType::EXR,
+    ];
+
+    /// The matching format of the `image` crate.
+    pub fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            ImageFileType::PNG => Some(ImageFormat::Png),
+            ImageFileType::JPEG => Some(ImageFormat::Jpeg),
+            ImageFileType::ICO => Some(ImageFormat::Ico),
+            ImageFileType::WEBP => Some(ImageFormat::WebP),
+            ImageFileType::AVIF => Some(ImageFormat::Avif),
+            ImageFileType::GIF => Some(ImageFormat::Gif),
+            ImageFileType::PSD => None,
+            ImageFileType::TGA => Some(ImageFormat::Tga),
+            ImageFileType::PNM => Some(ImageFormat::Pnm),
+            ImageFileType::QOI => Some(ImageFormat::Qoi),
+            ImageFileType::HDR => Some(ImageFormat::Hdr),
+            ImageFileType::EXR => Some(ImageFormat::OpenExr),
+        }
+    }
+
+    /// The type matching a format of the `image` crate, if this crate handles it.
+    pub fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(ImageFileType::PNG),
+            ImageFormat::Jpeg => Some(ImageFileType::JPEG),
+            ImageFormat::Ico => Some(ImageFileType::ICO),
+            ImageFormat::WebP => Some(ImageFileType::WEBP),
+            ImageFormat::Avif => Some(ImageFileType::AVIF),
+            ImageFormat::Gif => Some(ImageFileType::GIF),
+            ImageFormat::Tga => Some(ImageFileType::TGA),
+            ImageFormat::Pnm => Some(ImageFileType::PNM),
+            ImageFormat::Qoi => Some(ImageFileType::QOI),
+            ImageFormat::Hdr => Some(ImageFileType::HDR),
+            ImageFormat::OpenExr => Some(ImageFileType::EXR),
+            _ => None,
+        }
+    }
+
+    /// Reads the dimensions and colour type of the image at `path`. The format is detected from
+    /// the file contents.
+    pub fn probe(path: &Path) -> anyhow::Result<(ImageFileType, ImageInfo)> {
+        #[cfg(feature = "psd")]
+        {
+            let mut magic = [0u8; 4];
+            if std::io::Read::read_exact(&mut std::fs::File::open(path).with_path("Cannot read", path)?, &mut magic).is_ok() && magic == psd::PSD_SIGNATURE {
+                return Ok((ImageFileType::PSD, psd::probe(&std::fs::read(path).with_path("Cannot read", path)?)?));
+            }
+        }
+
+        let reader = ImageReader::open(path).with_path("Cannot read", path)?.with_guessed_format()?;
+        let file_type = reader.format()
+            .and_then(Self::from_image_format)
+            .ok_or_else(|| anyhow::anyhow!("Unrecognised image format: {}", path.display()))?;
+
+        let decoder = reader.into_decoder()?;
+        let (width, height) = decoder.dimensions();
+        Ok((file_type, ImageInfo { width, height, color_type: decoder.color_type() }))
+    }
+
+    /// The MIME type of the format, as used in HTTP headers and data URIs.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFileType::PNG => "image/png",
+            ImageFileType::JPEG => "image/jpeg",
+            ImageFileType::ICO => "image/x-icon",
+            ImageFileType::WEBP => "image/webp",
+            ImageFileType::AVIF => "image/avif",
+            ImageFileType::GIF => "image/gif",
+            ImageFileType::PSD => "image/vnd.adobe.photoshop",
+            ImageFileType::TGA => "image/x-tga",
+            ImageFileType::PNM => "image/x-portable-anymap",
+            ImageFileType::QOI => "image/qoi",
+            ImageFileType::HDR => "image/vnd.radiance",
+            ImageFileType::EXR => "image/x-exr",
+        }
+    }
+
+    /// Formats commonly displayed by web browsers.
+    pub fn is_web_format(self) -> bool {
+        matches!(self, ImageFileType::PNG | ImageFileType::JPEG | ImageFileType::WEBP | ImageFileType::AVIF | ImageFileType::GIF)
+    }
+}
+
+/// Decodes the image at `path`, refusing to do so if its header declares more pixels than
+/// [`ConvertOptions::max_pixels`] allows.
+pub(crate) fn open_image(path: &Path, options: &ConvertOptions) -> anyhow::Result<DynamicImage> {
+    #[cfg(feature = "psd")]
+    {
+        let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+        if bytes.starts_with(psd::PSD_SIGNATURE) {
+            let mut img = psd::decode(&bytes, options)?;
+            if options.premultiplied == Some(true) {
+                unpremultiply(&mut img);
+            }
+            return Ok(img);
+        }
+    }
+
+    let reader = ImageReader::open(path).with_path("Cannot read", path)?.with_guessed_format()?;
+    // The `image` crate assumes CMYK JPEGs store inverted channels, which isn't always the case.
+    if reader.format() == Some(ImageFormat::Jpeg) {
+        let bytes = std::fs::read(path).with_path("Cannot read", path)?;
+        if let Some(info) = jpeg::read_color_info(&bytes) && info.is_cmyk() {
+            return jpeg::decode_cmyk(&bytes, &info, options);
+        }
+    }
+
+    // OpenEXR stores premultiplied alpha, the other formats straight alpha.
+    let premultiplied = options.premultiplied.unwrap_or(reader.format() == Some(ImageFormat::OpenExr));
+    let (width, height) = reader.into_dimensions()?;
+    check_dimensions(width, height, options)?;
+
+    let mut img = ImageReader::open(path).with_path("Cannot read", path)?.with_guessed_format()?.decode().with_path("Cannot decode", path)?;
+    if premultiplied {
+        unpremultiply(&mut img);
+    }
+    Ok(img)
+}
+
+/// Divides the colour channels of an image with premultiplied alpha by the alpha, giving the
+/// straight alpha the encoders expect. Fully transparent pixels become black.
+pub(crate) fn unpremultiply(img: &mut DynamicImage) {
+    // Rounded to the nearest value, and clamped as rounding in the source can overshoot.
+    let divide = |channel: u32, alpha: u32, max: u32| (channel * max + alpha / 2).checked_div(alpha).map_or(0, |value| value.min(max));
+    match img {
+        DynamicImage::ImageLumaA8(buffer) => buffer.pixels_mut().for_each(|pixel| pixel[0] = divide(pixel[0] as u32, pixel[1] as u32, 255) as u8),
+        DynamicImage::ImageLumaA16(buffer) => buffer.pixels_mut().for_each(|pixel| pixel[0] = divide(pixel[0] as u32, pixel[1] as u32, 65535) as u16),
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels_mut().for_each(|pixel| {
+            let alpha = pixel[3] as u32;
+            pixel.0[..3].iter_mut().for_each(|channel| *channel = divide(*channel as u32, alpha, 255) as u8);
+        }),
+        DynamicImage::ImageRgba16(buffer) => buffer.pixels_mut().for_each(|pixel| {
+            let alpha = pixel[3] as u32;
+            pixel.0[..3].iter_mut().for_each(|channel| *channel = divide(*channel as u32, alpha, 65535) as u16);
+        }),
+        // Floating point light isn't bounded, so nothing is clamped.
+        DynamicImage::ImageRgba32F(buffer) => buffer.pixels_mut().for_each(|pixel| {
+            let alpha = pixel[3];
+            pixel.0[..3].iter_mut().for_each(|channel| *channel = if alpha > 0.0 { *channel / alpha } else { 0.0 });
+        }),
+        _ => {}
+    }
+}
+
+/// Errors if an image of the given size exceeds [`ConvertOptions::max_pixels`].
+pub(crate) fn check_dimensions(width: u32, height: u32, options: &ConvertOptions) -> anyhow::Result<()> {
+    if width as u64 * height as u64 > options.max_pixels {
+        return Err(ConvertError::TooLarge { width, height, max_pixels: options.max_pixels }.into());
+    }
+
+    Ok(())
+}
+
+/// Runs the image stage of the conversion pipeline on a freshly decoded image.
+pub(crate) fn apply_transforms(img: &mut DynamicImage, options: &ConvertOptions) {
+    if let Some(threshold) = options.auto_crop_threshold {
+        crop_borders(img, threshold);
+    }
+
+    match options.quarter_turns % 4 {
+        1 => *img = img.rotate90(),
+        2 => *img = img.rotate180(),
+        3 => *img = img.rotate270(),
+        _ => {}
+    }
+
+    if let Some((width, height)) = options.resize && (width, height) != (img.width(), img.height()) {
+        *img = img.resize_exact(width, height, resize_filter(options, (img.width(), img.height()), (width, height)));
+    }
+
+    if let Some(bounds) = options.max_dimensions && let Some((width, height)) = fit_within((img.width(), img.height()), bounds) {
+        *img = img.resize_exact(width, height, options.resize_down_filter);
+    }
+
+    for filter in &options.image_filters {
+        filter(img);
+    }
+}
+
+/// The size an image of `size` has to be scaled down to so it fits in `bounds` with the same
+/// aspect ratio, `None` if it already fits.
+pub(crate) fn fit_within(size: (u32, u32), bounds: (u32, u32)) -> Option<(u32, u32)> {
+    let (width, height) = (size.0 as u64, size.1 as u64);
+    let (max_width, max_height) = (bounds.0 as u64, bounds.1 as u64);
+    if width <= max_width && height <= max_height {
+        return None;
+    }
+
+    // Whichever side overshoots its bound the most decides the scale.
+    let fitted = if width * max_height >= height * max_width {
+        (max_width, (height * max_width + width / 2) / width)
+    } else {
+        ((width * max_height + height / 2) / height, max_height)
+    };
+    Some((fitted.0.max(1) as u32, fitted.1.max(1) as u32))
+}
+
+/// The filter to resize an image of size `from` to `to` with: the one for upscaling if it gains
+/// pixels, the one for downscaling otherwise.
+pub(crate) fn resize_filter(options: &ConvertOptions, from: (u32, u32), to: (u32, u32)) -> FilterType {
+    if to.0 as u64 * to.1 as u64 > from.0 as u64 * from.1 as u64 {
+        options.resize_up_filter
+    } else {
+        options.resize_down_filter
+    }
+}
+
+/// Crops the rows and columns along the edges whose pixels are all dark, as left around scans.
+/// A pixel is dark if none of its colour channels exceeds `threshold`. Images that are dark all
+/// over are left alone.
+pub(crate) fn crop_borders(img: &mut DynamicImage, threshold: u8) {
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let is_dark = |x: u32, y: u32| rgb.get_pixel(x, y).0.iter().all(|&channel| channel <= threshold);
+    let row_dark = |y: u32| (0..width).all(|x| is_dark(x, y));
+    let column_dark = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| is_dark(x, y));
+
+    let Some(top) = (0..height).find(|&y| !row_dark(y)) else {
+        return;
+    };
+    let bottom = (0..height).rev().find(|&y| !row_dark(y)).map_or(height, |y| y + 1);
+    let left = (0..width).find(|&x| !column_dark(x, top, bottom)).unwrap_or(0);
+    let right = (0..width).rev().find(|&x| !column_dark(x, top, bottom)).map_or(width, |x| x + 1);
+
+    if (left, top, right, bottom) != (0, 0, width, height) {
+        *img = img.crop_imm(left, top, right - left, bottom - top);
+    }
+}
+
+/// Side of the square windows [`ssim`] compares.
+const SSIM_WINDOW: u32 = 8;
+
+/// Mean structural similarity (SSIM) of the luma of two images, over 8x8 windows spaced half
+/// a window apart. 1.0 means identical, lower values are increasingly different.
+pub(crate) fn ssim(a: &DynamicImage, b: &DynamicImage) -> anyhow::Result<f64> {
+    let (a, b) = (a.to_luma8(), b.to_luma8());
+    if a.dimensions() != b.dimensions() {
+        return Err(anyhow::anyhow!("Cannot compare a {}x{} image with a {}x{} one", a.width(), a.height(), b.width(), b.height()));
+    }
+    let (width, height) = a.dimensions();
+    if width == 0 || height == 0 {
+        return Err(anyhow::anyhow!("Cannot compare empty images"));
+    }
+
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (window_width, window_height) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let step = (SSIM_WINDOW / 2) as usize;
+    let mut total = 0.0;
+    let mut windows = 0;
+    for top in (0..=height - window_height).step_by(step) {
+        for left in (0..=width - window_width).step_by(step) {
+            let pixels = (top..top + window_height)
+                .flat_map(|y| (left..left + window_width).map(move |x| (x, y)))
+                .map(|(x, y)| (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64));
+            let count = (window_width * window_height) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for (pa, pb) in pixels {
+                sum_a += pa;
+                sum_b += pb;
+                sum_aa += pa * pa;
+                sum_bb += pb * pb;
+                sum_ab += pa * pb;
+            }
+
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let variance_a = sum_aa / count - mean_a * mean_a;
+            let variance_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+            total += (2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2)
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+
+    Ok(total / windows as f64)
+}
+
+/// Peak signal-to-noise ratio between two images in decibels, compared as 8-bit RGB. Higher
+/// is closer, identical images give infinity.
+pub(crate) fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    if a.dimensions() != b.dimensions() {
+        return 0.0;
+    }
+
+    let squared_error: f64 = a.as_raw().iter()
+        .zip(b.as_raw())
+        .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len().max(1) as f64;
+
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
@@ -1,19 +1,14 @@
-use std::path::PathBuf;
+use std::path::Path;
 
-use crate::{converters::image::ImageFileType, Converter, FileType};
+use crate::{converters::image::{apply_transforms, encode::write_image, open_image, ImageFileType}, copy_unchanged, ConvertOptions, Converter, FileType};
 
 pub struct PngToJpeg;
 
 impl Converter for PngToJpeg {
-    fn convert(&self, input_path: &PathBuf, output_path: &PathBuf) -> anyhow::Result<()> {        
-        let img = image::open(input_path)?;
-        let img = img.to_rgb8();
-        let mut output = std::fs::File::create(output_path)?;
-        let encoder = image::codecs::jpeg::JpegEncoder::new(&mut output);
-        img.write_with_encoder(encoder)?;
-        
-        std::fs::copy(input_path, output_path)?;
-        Ok(())
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::JPEG, output_path, options)
     }
 
     fn from_type(&self) -> FileType {
@@ -23,4 +18,32 @@ impl Converter for PngToJpeg {
     fn to_type(&self) -> FileType {
         FileType::Image(ImageFileType::JPEG)
     }
-}
\ No newline at end of file
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+/// Re-encodes PNGs when the image changes, e.g. with [`crate::FileConvertBuilder::force_alpha`],
+/// and copies them unchanged otherwise.
+pub struct PngToPng;
+
+impl Converter for PngToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        if !options.has_image_transforms() && options.force_alpha.is_none() {
+            return copy_unchanged(input_path, output_path);
+        }
+
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use image::{codecs::pnm::{GraymapHeader, PixmapHeader, PnmEncoder, PnmHeader, SampleEncoding}, ColorType, DynamicImage, ExtendedColorType};
+
+use crate::{converters::image::{apply_transforms, encode::{drop_alpha, write_image}, open_image, ImageFileType}, ConvertOptions, Converter, FileType};
+
+/// Encodes `img` as a binary PGM (P5) if it is grayscale and a binary PPM (P6) otherwise,
+/// keeping 16-bit samples. Neither stores alpha, translucent pixels are composited over white.
+pub(crate) fn write_pnm(img: &DynamicImage, output: &mut impl std::io::Write) -> anyhow::Result<()> {
+    let opaque;
+    let img = if img.color().has_alpha() {
+        if img.to_rgba8().pixels().any(|pixel| pixel[3] < u8::MAX) {
+            println!("PNM has no transparency, compositing the {:?} image over white", img.color());
+        }
+        opaque = drop_alpha(img);
+        &opaque
+    } else {
+        img
+    };
+
+    // Float samples don't exist in PNM, 16 bits is as close as it gets.
+    let img = match img.color() {
+        ColorType::L8 | ColorType::L16 | ColorType::Rgb8 | ColorType::Rgb16 => img.clone(),
+        color if color.has_color() => DynamicImage::ImageRgb16(img.to_rgb16()),
+        _ => DynamicImage::ImageLuma16(img.to_luma16()),
+    };
+
+    let (width, height) = (img.width(), img.height());
+    // The encoder only takes 8-bit samples for PPM, 16-bit ones are written by hand.
+    if let DynamicImage::ImageRgb16(buffer) = &img {
+        write!(output, "P6\n{} {}\n{}\n", width, height, u16::MAX)?;
+        let samples: Vec<u8> = buffer.as_raw().iter().flat_map(|sample| sample.to_be_bytes()).collect();
+        output.write_all(&samples)?;
+        return Ok(());
+    }
+
+    let maxval = if img.color().bytes_per_pixel() > img.color().channel_count() { u16::MAX as u32 } else { u8::MAX as u32 };
+    let encoding = SampleEncoding::Binary;
+    let header: PnmHeader = if img.color().has_color() {
+        PixmapHeader { encoding, width, height, maxval }.into()
+    } else {
+        GraymapHeader { encoding, width, height, maxwhite: maxval }.into()
+    };
+    // `write_with_encoder` passes samples on as bytes, which the encoder only takes for 8 bits.
+    let mut encoder = PnmEncoder::new(output).with_header(header);
+    match &img {
+        DynamicImage::ImageLuma16(buffer) => encoder.encode(buffer.as_raw().as_slice(), width, height, ExtendedColorType::L16)?,
+        _ => encoder.encode(img.as_bytes(), width, height, img.color().into())?,
+    }
+    Ok(())
+}
+
+/// Decodes PBM, PGM, PPM and PAM files, in ASCII as well as binary form.
+pub struct PnmToPng;
+
+impl Converter for PnmToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNM)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
+
+/// Encodes PNGs as binary PGM if they are grayscale and binary PPM otherwise.
+pub struct PngToPnm;
+
+impl Converter for PngToPnm {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNM, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNM)
+    }
+}
@@ -0,0 +1,412 @@
+use std::path::Path;
+
+use image::{ColorType, DynamicImage, ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+use crate::{converters::image::{apply_transforms, check_dimensions, encode::write_image, open_image, ImageFileType, ImageInfo}, ConvertOptions, Converter, FileType};
+
+pub(crate) const PSD_SIGNATURE: &[u8] = b"8BPS";
+const HEADER_SIZE: usize = 26;
+/// Most channels a document can have, colour and extra ones together.
+const MAX_CHANNELS: u16 = 56;
+
+/// Colour modes of a PSD document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    Bitmap,
+    Grayscale,
+    Indexed,
+    Rgb,
+    Cmyk,
+    Duotone,
+    Lab,
+}
+
+impl ColorMode {
+    fn from_code(code: u16) -> anyhow::Result<Self> {
+        Ok(match code {
+            0 => ColorMode::Bitmap,
+            1 => ColorMode::Grayscale,
+            2 => ColorMode::Indexed,
+            3 => ColorMode::Rgb,
+            4 => ColorMode::Cmyk,
+            8 => ColorMode::Duotone,
+            9 => ColorMode::Lab,
+            code => return Err(anyhow::anyhow!("Unsupported PSD colour mode {}", code)),
+        })
+    }
+
+    /// Number of channels holding colour, the ones after them are alpha or spot channels.
+    fn color_channels(self) -> usize {
+        match self {
+            ColorMode::Bitmap | ColorMode::Grayscale | ColorMode::Indexed | ColorMode::Duotone => 1,
+            ColorMode::Rgb | ColorMode::Lab => 3,
+            ColorMode::Cmyk => 4,
+        }
+    }
+}
+
+/// The fixed size header of a PSD or PSB file.
+#[derive(Debug, Clone, Copy)]
+struct PsdHeader {
+    /// Large documents (PSB) use wider length fields.
+    large: bool,
+    channels: u16,
+    width: u32,
+    height: u32,
+    depth: u16,
+    mode: u16,
+}
+
+/// A cursor over big endian PSD data.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.offset.checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow::anyhow!("PSD file is truncated"))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A length field, 8 bytes wide in PSB files.
+    fn len(&mut self, large: bool) -> anyhow::Result<usize> {
+        Ok(if large {
+            u64::from_be_bytes(self.take(8)?.try_into().unwrap()) as usize
+        } else {
+            self.u32()? as usize
+        })
+    }
+
+    /// Reads a length prefixed section.
+    fn section(&mut self, large: bool) -> anyhow::Result<&'a [u8]> {
+        let len = self.len(large)?;
+        self.take(len)
+    }
+}
+
+fn read_header(bytes: &[u8]) -> anyhow::Result<PsdHeader> {
+    if bytes.len() < HEADER_SIZE || !bytes.starts_with(PSD_SIGNATURE) {
+        return Err(anyhow::anyhow!("Not a PSD file"));
+    }
+
+    let mut reader = Reader { bytes, offset: 4 };
+    let version = reader.u16()?;
+    if version != 1 && version != 2 {
+        return Err(anyhow::anyhow!("Unsupported PSD version {}", version));
+    }
+    reader.take(6)?;
+
+    let channels = reader.u16()?;
+    if !(1..=MAX_CHANNELS).contains(&channels) {
+        return Err(anyhow::anyhow!("Invalid PSD channel count {}, expected 1 to {}", channels, MAX_CHANNELS));
+    }
+    Ok(PsdHeader {
+        large: version == 2,
+        channels,
+        height: reader.u32()?,
+        width: reader.u32()?,
+        depth: reader.u16()?,
+        mode: reader.u16()?,
+    })
+}
+
+/// Decodes one PackBits compressed row into `output`.
+fn unpack_bits(mut input: &[u8], output: &mut Vec<u8>, row_len: usize) -> anyhow::Result<()> {
+    let start = output.len();
+    while output.len() - start < row_len {
+        let Some((&header, rest)) = input.split_first() else {
+            return Err(anyhow::anyhow!("PSD row is shorter than the image"));
+        };
+        input = rest;
+        match header as i8 {
+            // A no-op in PackBits.
+            -128 => {}
+            count @ 0.. => {
+                let len = count as usize + 1;
+                let literal = input.get(..len).ok_or_else(|| anyhow::anyhow!("PSD row is truncated"))?;
+                output.extend_from_slice(literal);
+                input = &input[len..];
+            }
+            count => {
+                let (&value, rest) = input.split_first().ok_or_else(|| anyhow::anyhow!("PSD row is truncated"))?;
+                output.extend(std::iter::repeat_n(value, (1 - count as isize) as usize));
+                input = rest;
+            }
+        }
+    }
+
+    output.truncate(start + row_len);
+    Ok(())
+}
+
+/// Converts a Photoshop Lab pixel to sRGB. Lightness is stored in `0..=1`, a and b offset by
+/// half so that `0.5` is neutral; the D50 white Photoshop uses is adapted to D65 with Bradford.
+fn lab_to_rgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let (l, a, b) = (l * 100.0, a * 255.0 - 128.0, b * 255.0 - 128.0);
+    let fy = (l + 16.0) / 116.0;
+    let (fx, fz) = (fy + a / 500.0, fy - b / 200.0);
+    let inverse = |f: f32| if f > 6.0 / 29.0 { f.powi(3) } else { 3.0 * (6.0f32 / 29.0).powi(2) * (f - 4.0 / 29.0) };
+    let (x, y, z) = (0.9642 * inverse(fx), inverse(fy), 0.8249 * inverse(fz));
+
+    let linear = [
+        3.1339 * x - 1.6169 * y - 0.4906 * z,
+        -0.9788 * x + 1.9161 * y + 0.0335 * z,
+        0.0719 * x - 0.2290 * y + 1.4052 * z,
+    ];
+    linear.map(|channel| {
+        let channel = channel.clamp(0.0, 1.0);
+        if channel <= 0.003_130_8 { 12.92 * channel } else { 1.055 * channel.powf(1.0 / 2.4) - 0.055 }
+    })
+}
+
+/// The parts of a PSD file needed to decode its composite image.
+struct Layout<'a> {
+    header: PsdHeader,
+    mode: ColorMode,
+    /// The palette of indexed documents.
+    color_mode_data: &'a [u8],
+    has_alpha: bool,
+    /// Where the image data section starts.
+    image_data: usize,
+}
+
+impl Layout<'_> {
+    /// The colour type the composite decodes to.
+    fn color_type(&self) -> ColorType {
+        let grayscale = matches!(self.mode, ColorMode::Bitmap | ColorMode::Grayscale | ColorMode::Duotone);
+        match (self.header.depth == 16, grayscale, self.has_alpha) {
+            (true, true, false) => ColorType::L16,
+            (true, true, true) => ColorType::La16,
+            (true, false, false) => ColorType::Rgb16,
+            (true, false, true) => ColorType::Rgba16,
+            (false, true, false) => ColorType::L8,
+            (false, true, true) => ColorType::La8,
+            (false, false, false) => ColorType::Rgb8,
+            (false, false, true) => ColorType::Rgba8,
+        }
+    }
+}
+
+fn read_layout(bytes: &[u8]) -> anyhow::Result<Layout<'_>> {
+    let header = read_header(bytes)?;
+    let mode = ColorMode::from_code(header.mode)?;
+    let expected_depth = if mode == ColorMode::Bitmap { header.depth == 1 } else { matches!(header.depth, 8 | 16) };
+    if !expected_depth {
+        return Err(anyhow::anyhow!("Unsupported PSD bit depth of {} for {:?} documents", header.depth, mode));
+    }
+    if (header.channels as usize) < mode.color_channels() {
+        return Err(anyhow::anyhow!("PSD document has {} channels, {:?} needs {}", header.channels, mode, mode.color_channels()));
+    }
+
+    let mut reader = Reader { bytes, offset: HEADER_SIZE };
+    let color_mode_data = reader.section(false)?;
+    let _resources = reader.section(false)?;
+    let layers = reader.section(header.large)?;
+    // A negative layer count means the first extra channel is the transparency of the composite.
+    let has_alpha = {
+        let mut layers = Reader { bytes: layers, offset: 0 };
+        layers.len(header.large).is_ok_and(|len| len > 0) && layers.u16().is_ok_and(|count| (count as i16) < 0)
+    } && header.channels as usize > mode.color_channels();
+
+    Ok(Layout { header, mode, color_mode_data, has_alpha, image_data: reader.offset })
+}
+
+/// Reads the dimensions and colour type of a PSD file without decoding it.
+pub(crate) fn probe(bytes: &[u8]) -> anyhow::Result<ImageInfo> {
+    let layout = read_layout(bytes)?;
+    Ok(ImageInfo { width: layout.header.width, height: layout.header.height, color_type: layout.color_type() })
+}
+
+/// Decodes the composite image Photoshop stores next to the layers, which is every visible
+/// layer flattened. The first extra channel is used as transparency if the document says the
+/// composite has one.
+pub(crate) fn decode(bytes: &[u8], options: &ConvertOptions) -> anyhow::Result<DynamicImage> {
+    let layout = read_layout(bytes)?;
+    let Layout { header, mode, color_mode_data, has_alpha, .. } = layout;
+    check_dimensions(header.width, header.height, options)?;
+    let mut reader = Reader { bytes, offset: layout.image_data };
+
+    let (width, height) = (header.width as usize, header.height as usize);
+    let row_len = (width * header.depth as usize).div_ceil(8);
+    let channel_count = mode.color_channels() + has_alpha as usize;
+
+    let mut planes = Vec::with_capacity(channel_count);
+    match reader.u16()? {
+        0 => {
+            for _ in 0..channel_count {
+                planes.push(reader.take(row_len * height)?.to_vec());
+            }
+        }
+        1 => {
+            // Compressed sizes of every row of every channel come first.
+            // Taken as a whole first, so a truncated table fails before anything is reserved.
+            let entry_len = if header.large { 4 } else { 2 };
+            let table = reader.take(header.channels as usize * height * entry_len)?;
+            let row_sizes: Vec<usize> = table.chunks_exact(entry_len)
+                .map(|entry| entry.iter().fold(0, |size, byte| size << 8 | *byte as usize))
+                .collect();
+            for channel in 0..channel_count {
+                let mut plane = Vec::with_capacity(row_len * height);
+                for row in 0..height {
+                    unpack_bits(reader.take(row_sizes[channel * height + row])?, &mut plane, row_len)?;
+                }
+                planes.push(plane);
+            }
+        }
+        compression => return Err(anyhow::anyhow!("Unsupported PSD compression method {}", compression)),
+    }
+
+    // Every sample normalised to 0..=1.
+    let sample = |plane: &[u8], index: usize| -> f32 {
+        match header.depth {
+            1 => {
+                // Set bits are black.
+                let (x, y) = (index % width, index / width);
+                let bit = plane[y * row_len + x / 8] >> (7 - x % 8) & 1;
+                (1 - bit) as f32
+            }
+            8 => plane[index] as f32 / 255.0,
+            _ => u16::from_be_bytes([plane[index * 2], plane[index * 2 + 1]]) as f32 / 65535.0,
+        }
+    };
+
+    let pixels = width * height;
+    let mut color = Vec::with_capacity(pixels * 3);
+    let grayscale = layout.color_type().channel_count() < 3;
+    for index in 0..pixels {
+        let channel = |channel: usize| sample(&planes[channel], index);
+        match mode {
+            ColorMode::Bitmap | ColorMode::Grayscale | ColorMode::Duotone => color.push(channel(0)),
+            ColorMode::Indexed => {
+                // The palette holds 256 reds, then 256 greens, then 256 blues.
+                let entry = planes[0][index] as usize;
+                for component in 0..3 {
+                    color.push(*color_mode_data.get(component * 256 + entry).unwrap_or(&0) as f32 / 255.0);
+                }
+            }
+            ColorMode::Rgb => color.extend((0..3).map(channel)),
+            // Photoshop stores CMYK inverted, so every channel is the amount of light left.
+            ColorMode::Cmyk => color.extend((0..3).map(|component| channel(component) * channel(3))),
+            ColorMode::Lab => color.extend(lab_to_rgb(channel(0), channel(1), channel(2))),
+        }
+    }
+    let alpha: Option<Vec<f32>> = has_alpha.then(|| (0..pixels).map(|index| sample(&planes[mode.color_channels()], index)).collect());
+
+    let (width, height) = (header.width, header.height);
+    let stride = if grayscale { 1 } else { 3 };
+    let interleave = |scale: f32| -> Vec<f32> {
+        let mut samples = Vec::with_capacity(pixels * (stride + has_alpha as usize));
+        for (index, pixel) in color.chunks_exact(stride).enumerate() {
+            samples.extend(pixel.iter().map(|value| (value * scale).round()));
+            if let Some(alpha) = &alpha {
+                samples.push((alpha[index] * scale).round());
+            }
+        }
+        samples
+    };
+    let invalid = || anyhow::anyhow!("Decoded PSD has an unexpected size");
+
+    Ok(if header.depth == 16 {
+        let samples: Vec<u16> = interleave(65535.0).into_iter().map(|value| value as u16).collect();
+        match layout.color_type() {
+            ColorType::L16 => DynamicImage::ImageLuma16(ImageBuffer::<Luma<u16>, _>::from_raw(width, height, samples).ok_or_else(invalid)?),
+            ColorType::La16 => DynamicImage::ImageLumaA16(ImageBuffer::<LumaA<u16>, _>::from_raw(width, height, samples).ok_or_else(invalid)?),
+            ColorType::Rgb16 => DynamicImage::ImageRgb16(ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, samples).ok_or_else(invalid)?),
+            _ => DynamicImage::ImageRgba16(ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, samples).ok_or_else(invalid)?),
+        }
+    } else {
+        let samples: Vec<u8> = interleave(255.0).into_iter().map(|value| value as u8).collect();
+        match layout.color_type() {
+            ColorType::L8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, samples).ok_or_else(invalid)?),
+            ColorType::La8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, samples).ok_or_else(invalid)?),
+            ColorType::Rgb8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, samples).ok_or_else(invalid)?),
+            _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, samples).ok_or_else(invalid)?),
+        }
+    })
+}
+
+/// Flattens Photoshop documents to PNG.
+pub struct PsdToPng;
+
+impl Converter for PsdToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PSD)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8-bit RGB document with empty color mode data, resources and layers, followed by
+    /// the compression method of its image data.
+    fn psd(channels: u16, width: u32, height: u32, compression: u16) -> Vec<u8> {
+        let mut bytes = PSD_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&[0; 6]);
+        bytes.extend_from_slice(&channels.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&8u16.to_be_bytes());
+        bytes.extend_from_slice(&3u16.to_be_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(&compression.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decodes_raw_image_data() {
+        // The channels are stored as planes, a red then a green pixel.
+        let mut bytes = psd(3, 2, 1, 0);
+        bytes.extend_from_slice(&[255, 0, 0, 255, 0, 0]);
+        let img = decode(&bytes, &ConvertOptions::default()).unwrap();
+        assert_eq!(img.to_rgb8().into_raw(), [255, 0, 0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn rejects_invalid_channel_counts() {
+        for channels in [0, MAX_CHANNELS + 1, u16::MAX] {
+            let error = probe(&psd(channels, 1, 1, 0)).unwrap_err();
+            assert!(error.to_string().contains("channel count"), "{}", error);
+        }
+        assert!(probe(&psd(MAX_CHANNELS, 1, 1, 0)).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        assert!(probe(&psd(3, 1, 1, 0)[..HEADER_SIZE - 1]).is_err());
+        assert!(probe(&psd(3, 1, 1, 0)[..HEADER_SIZE + 4]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_row_size_tables() {
+        // 56 channels of 16384 rows need a 1.8 MB table the file doesn't have.
+        let mut bytes = psd(MAX_CHANNELS, 1, 16_384, 1);
+        bytes.extend_from_slice(&[0, 2, 0, 2]);
+        let error = decode(&bytes, &ConvertOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("truncated"), "{}", error);
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::Path;
+
+use image::{codecs::qoi::QoiEncoder, DynamicImage};
+
+use crate::{converters::image::{apply_transforms, encode::write_image, open_image, ImageFileType}, ConvertOptions, Converter, FileType};
+
+/// Encodes `img` as a QOI, which stores 8-bit RGB or RGBA only. Grayscale images are expanded
+/// to RGB and deeper ones reduced with a warning.
+pub(crate) fn write_qoi(img: &DynamicImage, output: &mut impl std::io::Write) -> anyhow::Result<()> {
+    let color = img.color();
+    if color.bytes_per_pixel() / color.channel_count() > 1 {
+        println!("Reducing {:?} image to 8 bits per channel for QOI", color);
+    }
+
+    let img = if color.has_alpha() {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    };
+    img.write_with_encoder(QoiEncoder::new(output))?;
+    Ok(())
+}
+
+/// Decodes QOIs, a lossless format that is much faster to encode and decode than PNG.
+pub struct QoiToPng;
+
+impl Converter for QoiToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::QOI)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
+
+/// Encodes PNGs as QOIs, keeping their pixels exactly as long as they are 8-bit.
+pub struct PngToQoi;
+
+impl Converter for PngToQoi {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::QOI, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::QOI)
+    }
+}
@@ -0,0 +1,107 @@
+//! Packing frames into sprite sheets, see [`crate::FileConvertBuilder::sprite_sheet`].
+
+use std::path::{Path, PathBuf};
+
+use image::{imageops, DynamicImage, RgbaImage};
+
+use crate::{converters::image::check_dimensions, error::PathContext, json::JsonValue, ConvertOptions};
+
+/// Where a frame ended up on a sprite sheet, in pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteFrame {
+    /// File name of the frame's source.
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A written sprite sheet with the position of every frame, in the order they were given.
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    pub output: PathBuf,
+    /// Size of a cell of the grid, the largest frame's width and height.
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub columns: u32,
+    pub frames: Vec<SpriteFrame>,
+}
+
+impl SpriteSheet {
+    /// The atlas as JSON: the sheet's file name, the grid and the rectangle of every frame.
+    pub fn atlas_json(&self) -> String {
+        let frames = self.frames.iter()
+            .map(|frame| JsonValue::object([
+                ("name", frame.name.as_str().into()),
+                ("x", (frame.x as u64).into()),
+                ("y", (frame.y as u64).into()),
+                ("width", (frame.width as u64).into()),
+                ("height", (frame.height as u64).into()),
+            ]))
+            .collect();
+        let image = self.output.file_name().map(|name| name.to_string_lossy().into_owned());
+
+        JsonValue::object([
+            ("image", image.into()),
+            ("cell_width", (self.cell_width as u64).into()),
+            ("cell_height", (self.cell_height as u64).into()),
+            ("columns", (self.columns as u64).into()),
+            ("frames", JsonValue::Array(frames)),
+        ]).to_string()
+    }
+
+    /// Writes [`Self::atlas_json`] to `path`, typically next to the sheet for a game engine to
+    /// load along with it.
+    pub fn write_atlas(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        std::fs::write(path.as_ref(), self.atlas_json()).with_path("Cannot write", path.as_ref())
+    }
+}
+
+/// A sheet made by [`pack`] with the layout of its grid.
+pub(crate) struct Packed {
+    pub sheet: RgbaImage,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// At most the number of frames, so a single row isn't wider than it needs to be.
+    pub columns: u32,
+    /// Top left corner of every frame.
+    pub positions: Vec<(u32, u32)>,
+}
+
+/// Lays `frames` out left to right and top to bottom on a grid of `columns` columns. Cells are
+/// as large as the largest frame, smaller frames are placed in the top left corner of theirs
+/// with the rest left transparent. The sheet is subject to [`ConvertOptions::max_pixels`] like
+/// any decoded image.
+pub(crate) fn pack(frames: &[DynamicImage], columns: u32, options: &ConvertOptions) -> anyhow::Result<Packed> {
+    if frames.is_empty() {
+        return Err(anyhow::anyhow!("A sprite sheet needs at least one frame"));
+    }
+    if columns == 0 {
+        return Err(anyhow::anyhow!("A sprite sheet needs at least one column"));
+    }
+
+    let cell_width = frames.iter().map(DynamicImage::width).max().unwrap_or(0);
+    let cell_height = frames.iter().map(DynamicImage::height).max().unwrap_or(0);
+    if frames.iter().any(|frame| frame.width() != cell_width || frame.height() != cell_height) {
+        println!("The frames differ in size, padding them to {}x{} cells", cell_width, cell_height);
+    }
+
+    let columns = columns.min(frames.len() as u32);
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let (width, height) = (cell_width.checked_mul(columns), cell_height.checked_mul(rows));
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err(anyhow::anyhow!("A {} by {} grid of {}x{} cells is too large", columns, rows, cell_width, cell_height));
+    };
+    check_dimensions(width, height, options)?;
+
+    let mut sheet = RgbaImage::new(width, height);
+    let mut positions = Vec::with_capacity(frames.len());
+    for (index, frame) in frames.iter().enumerate() {
+        let (x, y) = (index as u32 % columns * cell_width, index as u32 / columns * cell_height);
+        imageops::replace(&mut sheet, &frame.to_rgba8(), x as i64, y as i64);
+        positions.push((x, y));
+    }
+
+    Ok(Packed { sheet, cell_width, cell_height, columns, positions })
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use image::{codecs::tga::TgaEncoder, DynamicImage, ImageFormat, ImageReader};
+
+use crate::{converters::image::{apply_transforms, check_dimensions, encode::write_image, open_image, ImageFileType}, error::PathContext, ConvertOptions, Converter, FileType};
+
+/// Decodes a TGA file. TGA has no signature to detect it by, so unlike [`open_image`] the
+/// format isn't guessed from the contents.
+fn open_tga(path: &Path, options: &ConvertOptions) -> anyhow::Result<DynamicImage> {
+    let mut reader = ImageReader::open(path).with_path("Cannot read", path)?;
+    reader.set_format(ImageFormat::Tga);
+    let (width, height) = reader.into_dimensions()?;
+    check_dimensions(width, height, options)?;
+
+    let mut reader = ImageReader::open(path).with_path("Cannot read", path)?;
+    reader.set_format(ImageFormat::Tga);
+    Ok(reader.decode()?)
+}
+
+/// Encodes `img` as a TGA, run-length encoded if `rle` is set. TGA only stores 8 bits per
+/// channel, deeper images are reduced with a warning.
+pub(crate) fn write_tga(img: &DynamicImage, output: &mut impl std::io::Write, rle: bool) -> anyhow::Result<()> {
+    let color = img.color();
+    if color.bytes_per_pixel() / color.channel_count() > 1 {
+        println!("Reducing {:?} image to 8 bits per channel for TGA", color);
+    }
+
+    let img = match (color.has_color(), color.has_alpha()) {
+        (false, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (false, true) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (true, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (true, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+    };
+
+    let encoder = TgaEncoder::new(output);
+    let encoder = if rle { encoder } else { encoder.disable_rle() };
+    img.write_with_encoder(encoder)?;
+    Ok(())
+}
+
+/// Decodes TGAs, both uncompressed and run-length encoded.
+pub struct TgaToPng;
+
+impl Converter for TgaToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_tga(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::TGA)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
+
+/// Encodes TGAs, run-length encoded unless [`ConvertOptions::tga_rle`] is turned off.
+pub struct PngToTga;
+
+impl Converter for PngToTga {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::TGA, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::TGA)
+    }
+}
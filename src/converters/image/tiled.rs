@@ -0,0 +1,139 @@
+//! Conversions that stream huge images through in strips of rows, so only one strip is held in
+//! memory at a time. PNG is the one source format that can be decoded row by row, into PNG or
+//! PNM outputs.
+
+use std::{fs::File, io::{BufReader, BufWriter, Write}, path::Path};
+
+use crate::{converters::image::{check_dimensions, ImageFileType}, error::PathContext, ConvertOptions, FileType};
+
+enum StripWriter {
+    Png(Box<png::StreamWriter<'static, BufWriter<File>>>),
+    Pnm(BufWriter<File>),
+}
+
+impl StripWriter {
+    fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        match self {
+            StripWriter::Png(writer) => writer.write_all(bytes)?,
+            StripWriter::Pnm(writer) => writer.write_all(bytes)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            StripWriter::Png(writer) => writer.finish()?,
+            StripWriter::Pnm(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Composites a sample over white, `max` being the largest value a sample can take.
+fn over_white(sample: u32, alpha: u32, max: u32) -> u32 {
+    (sample * alpha + max * (max - alpha) + max / 2) / max
+}
+
+/// Streams `input` into `output` in strips of rows. [`ConvertOptions::max_pixels`] limits the
+/// pixels of a strip instead of the whole image. Returns `false`, after saying why, when the
+/// conversion can't be streamed and has to decode the image whole.
+pub(crate) fn convert(from: FileType, input: &Path, to: FileType, output: &Path, options: &ConvertOptions) -> anyhow::Result<bool> {
+    let to = match (from, to) {
+        (FileType::Image(ImageFileType::PNG), FileType::Image(to @ (ImageFileType::PNG | ImageFileType::PNM))) => to,
+        _ => {
+            println!("Only PNG to PNG or PNM conversions can be tiled, converting {} whole", input.display());
+            return Ok(false);
+        }
+    };
+    if options.has_image_transforms() {
+        println!("Image transformations need the whole image, converting {} without tiles", input.display());
+        return Ok(false);
+    }
+
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(input).with_path("Cannot read", input)?));
+    // Palettes, low bit depths and tRNS chunks become plain 8-bit samples.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    if reader.info().interlaced {
+        println!("{} is interlaced, converting it without tiles", input.display());
+        return Ok(false);
+    }
+
+    let (width, height) = (reader.info().width, reader.info().height);
+    let (color, depth) = reader.output_color_type();
+    let sample_bytes = if depth == png::BitDepth::Sixteen { 2 } else { 1 };
+    let max = if sample_bytes == 2 { u16::MAX as u32 } else { u8::MAX as u32 };
+    let channels = color.samples();
+    let has_color = matches!(color, png::ColorType::Rgb | png::ColorType::Rgba);
+    let has_alpha = matches!(color, png::ColorType::GrayscaleAlpha | png::ColorType::Rgba);
+    let drop_alpha = has_alpha && (to == ImageFileType::PNM || options.force_alpha == Some(false));
+    let add_alpha = !has_alpha && to == ImageFileType::PNG && options.force_alpha == Some(true);
+
+    let strip_rows = (options.max_pixels / width.max(1) as u64).clamp(1, height.max(1) as u64) as u32;
+    check_dimensions(width, strip_rows, options)?;
+
+    let file = BufWriter::new(File::create(output).with_path("Cannot write", output)?);
+    let mut writer = match to {
+        ImageFileType::PNM => {
+            let mut file = file;
+            write!(file, "{}\n{} {}\n{}\n", if has_color { "P6" } else { "P5" }, width, height, max)?;
+            StripWriter::Pnm(file)
+        }
+        _ => {
+            let mut encoder = png::Encoder::new(file, width, height);
+            encoder.set_color(match (has_color, (has_alpha && !drop_alpha) || add_alpha) {
+                (false, false) => png::ColorType::Grayscale,
+                (false, true) => png::ColorType::GrayscaleAlpha,
+                (true, false) => png::ColorType::Rgb,
+                (true, true) => png::ColorType::Rgba,
+            });
+            encoder.set_depth(if sample_bytes == 2 { png::BitDepth::Sixteen } else { png::BitDepth::Eight });
+            if options.embed_srgb.unwrap_or(true) {
+                encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+            }
+            StripWriter::Png(Box::new(encoder.write_header()?.into_stream_writer()?))
+        }
+    };
+
+    let read = |bytes: &[u8]| if sample_bytes == 2 { u16::from_be_bytes([bytes[0], bytes[1]]) as u32 } else { bytes[0] as u32 };
+    let push = |strip: &mut Vec<u8>, sample: u32| if sample_bytes == 2 {
+        strip.extend_from_slice(&(sample as u16).to_be_bytes());
+    } else {
+        strip.push(sample as u8);
+    };
+
+    let row_bytes = width as usize * (channels - drop_alpha as usize + add_alpha as usize) * sample_bytes;
+    let mut strip = Vec::with_capacity(row_bytes * strip_rows as usize);
+    let mut translucent = false;
+    while let Some(row) = reader.next_row()? {
+        let row = row.data();
+        if drop_alpha {
+            for pixel in row.chunks_exact(channels * sample_bytes) {
+                let alpha = read(&pixel[(channels - 1) * sample_bytes..]);
+                translucent |= alpha < max;
+                for sample in pixel[..(channels - 1) * sample_bytes].chunks_exact(sample_bytes) {
+                    push(&mut strip, over_white(read(sample), alpha, max));
+                }
+            }
+        } else if add_alpha {
+            for pixel in row.chunks_exact(channels * sample_bytes) {
+                strip.extend_from_slice(pixel);
+                push(&mut strip, max);
+            }
+        } else {
+            strip.extend_from_slice(row);
+        }
+
+        if strip.len() >= row_bytes * strip_rows as usize {
+            writer.write(&strip)?;
+            strip.clear();
+        }
+    }
+    writer.write(&strip)?;
+    writer.finish()?;
+
+    if translucent {
+        println!("Composited the translucent pixels of {} over white", input.display());
+    }
+    Ok(true)
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use crate::{converters::image::{animation::{decode_frames, write_apng_frames, write_webp_frames}, apply_transforms, encode::write_image, open_image, ImageFileType}, ConvertOptions, Converter, FileType};
+
+/// Encodes PNGs as lossless WebP.
+pub struct PngToWebp;
+
+impl Converter for PngToWebp {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::WEBP, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::WEBP)
+    }
+}
+
+/// Re-encodes JPEGs as WebP, losslessly so no loss is added on top of the JPEG's own. Use
+/// [`crate::options::WebpEncodeOptions::near_lossless`] for much smaller files.
+pub struct JpegToWebp;
+
+impl Converter for JpegToWebp {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::WEBP, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::JPEG)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::WEBP)
+    }
+}
+
+/// Converts GIFs to lossless WebP, keeping every frame of animated ones along with their delays.
+pub struct GifToWebp;
+
+impl Converter for GifToWebp {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut frames = decode_frames(input_path, ImageFileType::GIF, options)?;
+        if frames.len() == 1 {
+            let frame = frames.remove(0);
+            return write_image(&image::DynamicImage::ImageRgba8(frame.image), ImageFileType::WEBP, output_path, options);
+        }
+        write_webp_frames(&frames, output_path)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::GIF)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::WEBP)
+    }
+}
+
+/// Decodes WebPs to PNG. Animated ones give their first frame, or an animated PNG with every
+/// frame if [`crate::FileConvertBuilder::all_frames`] is enabled.
+pub struct WebpToPng;
+
+impl Converter for WebpToPng {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        if options.all_frames {
+            let frames = decode_frames(input_path, ImageFileType::WEBP, options)?;
+            if frames.len() > 1 {
+                return write_apng_frames(&frames, output_path, options);
+            }
+        }
+
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        write_image(&img, ImageFileType::PNG, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(ImageFileType::WEBP)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::PNG)
+    }
+}
@@ -1,2 +1,6 @@
 pub mod audio;
-pub mod image;
\ No newline at end of file
+pub mod command;
+pub mod document;
+pub mod image;
+pub mod text;
+pub mod video;
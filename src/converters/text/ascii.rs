@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use image::imageops::FilterType;
+
+use crate::{converters::{image::{apply_transforms, open_image, ImageFileType}, text::TextFileType}, error::PathContext, ConvertOptions, Converter, FileType};
+
+/// Default number of characters per line.
+pub const DEFAULT_ASCII_WIDTH: u32 = 80;
+/// Default characters from lightest to darkest.
+pub const DEFAULT_ASCII_RAMP: &str = " .:-=+*#%@";
+/// Characters are about twice as high as they are wide, so a row covers two pixels worth of
+/// height for every pixel of width.
+const CHARACTER_ASPECT: f32 = 2.0;
+
+/// Draws an image as text, picking a character from a ramp by the luminance of every cell.
+/// Transparent areas are drawn as if on a white background.
+pub struct ImageToAscii {
+    from: ImageFileType,
+    width: u32,
+    ramp: Vec<char>,
+}
+
+impl Default for ImageToAscii {
+    fn default() -> Self {
+        Self::new(ImageFileType::PNG)
+    }
+}
+
+impl ImageToAscii {
+    /// Draws images of type `from` with the default width and ramp.
+    pub fn new(from: ImageFileType) -> Self {
+        Self {
+            from,
+            width: DEFAULT_ASCII_WIDTH,
+            ramp: DEFAULT_ASCII_RAMP.chars().collect(),
+        }
+    }
+
+    /// Sets the number of characters per line.
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.width = width.max(1);
+        self
+    }
+
+    /// Sets the characters to draw with, ordered from lightest to darkest. An empty ramp keeps
+    /// the default one.
+    pub fn with_ramp(mut self, ramp: &str) -> Self {
+        if !ramp.is_empty() {
+            self.ramp = ramp.chars().collect();
+        }
+        self
+    }
+
+    /// Renders `img` as lines of exactly [`Self::with_width`] characters.
+    pub fn render(&self, img: &image::DynamicImage) -> String {
+        let rows = (img.height() as f32 * self.width as f32 / img.width().max(1) as f32 / CHARACTER_ASPECT)
+            .round()
+            .max(1.0) as u32;
+        let cells = img.resize_exact(self.width, rows, FilterType::Triangle).to_rgba8();
+
+        let mut text = String::with_capacity(((self.width + 1) * rows) as usize);
+        for row in cells.rows() {
+            for pixel in row {
+                let [r, g, b, a] = pixel.0.map(|channel| channel as f32 / 255.0);
+                let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                let luminance = luminance * a + (1.0 - a);
+                let index = ((1.0 - luminance) * (self.ramp.len() - 1) as f32).round() as usize;
+                text.push(self.ramp[index.min(self.ramp.len() - 1)]);
+            }
+            text.push('\n');
+        }
+
+        text
+    }
+}
+
+impl Converter for ImageToAscii {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut img = open_image(input_path, options)?;
+        apply_transforms(&mut img, options);
+        std::fs::write(output_path, self.render(&img)).with_path("Cannot write", output_path)?;
+        Ok(())
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Image(self.from)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Text(TextFileType::Ascii)
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
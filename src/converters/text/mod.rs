@@ -0,0 +1,12 @@
+pub mod ascii;
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub enum TextFileType {
+    /// Plain text art drawn with ASCII characters.
+    Ascii,
+}
+
+impl TextFileType {
+    /// Every text type, in declaration order.
+    pub const ALL: [TextFileType; 1] = [TextFileType::Ascii];
+}
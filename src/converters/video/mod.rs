@@ -0,0 +1,22 @@
+#[cfg(feature = "video")]
+pub mod mp4;
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub enum VideoFileType {
+    MP4,
+}
+
+/// Major brands of the `ftyp` box that mark MP4 video, others like `avif` or `M4A ` being
+/// used for images and audio in the same container.
+const MP4_BRANDS: &[&[u8; 4]] = &[b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"dash", b"MSNV"];
+
+impl VideoFileType {
+    /// Every video type, in declaration order.
+    pub const ALL: [VideoFileType; 1] = [VideoFileType::MP4];
+
+    /// Guesses the video format from the first bytes of a file.
+    pub(crate) fn detect(magic: &[u8]) -> Option<VideoFileType> {
+        let brand = magic.get(8..12)?;
+        (magic.get(4..8) == Some(b"ftyp") && MP4_BRANDS.iter().any(|known| known.as_slice() == brand)).then_some(VideoFileType::MP4)
+    }
+}
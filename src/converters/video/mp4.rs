@@ -0,0 +1,140 @@
+//! Turning videos into animations, with the frames extracted by ffmpeg.
+
+use std::{path::Path, process::Command, time::Duration};
+
+use image::DynamicImage;
+
+use crate::{converters::{command::find_program, image::{animation::{write_gif_frames, write_webp_frames, AnimationFrame}, apply_transforms, encode::write_image, open_image, ImageFileType}, video::VideoFileType}, error::PathContext, options::DEFAULT_VIDEO_FPS, temp_path, ConvertOptions, Converter, FileType};
+
+const FFMPEG: &str = "ffmpeg";
+
+/// Extracts the frames of `input_path` at [`ConvertOptions::video_fps`] frames per second, up
+/// to [`ConvertOptions::video_max_duration`], then decodes them in order with the image
+/// transformations applied. Fails if there are none.
+fn extract_frames(input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<Vec<AnimationFrame>> {
+    let fps = options.video_fps.unwrap_or(DEFAULT_VIDEO_FPS);
+    if !(fps > 0.0 && fps.is_finite()) {
+        return Err(anyhow::anyhow!("Invalid frame rate {}, expected a positive number", fps));
+    }
+
+    let dir = temp_path(output_path, "temp", "frames", &FileType::Video(VideoFileType::MP4)).with_extension("");
+    std::fs::create_dir(&dir).with_path("Cannot create", &dir)?;
+    let frames = run_ffmpeg(input_path, &dir, fps, options.video_max_duration).and_then(|_| decode_frames(&dir, fps, options));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut frames = frames?;
+
+    // ffmpeg may give a frame more than the duration holds.
+    if let Some(max_duration) = options.video_max_duration {
+        frames.truncate(((max_duration.as_secs_f64() * fps as f64).ceil() as usize).max(1));
+    }
+    if frames.is_empty() {
+        return Err(anyhow::anyhow!("{} has no video frames", input_path.display()));
+    }
+    Ok(frames)
+}
+
+fn run_ffmpeg(input_path: &Path, dir: &Path, fps: f32, max_duration: Option<Duration>) -> anyhow::Result<()> {
+    let mut command = Command::new(FFMPEG);
+    command.args(["-nostdin", "-v", "error"]);
+    if let Some(max_duration) = max_duration {
+        // Before the input, so ffmpeg stops reading it there.
+        command.arg("-t").arg(max_duration.as_secs_f64().to_string());
+    }
+    let result = command.arg("-i")
+        .arg(input_path)
+        .args(["-an", "-vf", &format!("fps={}", fps)])
+        .arg(dir.join("%06d.png"))
+        .output()
+        .map_err(|error| anyhow::anyhow!("Cannot run {}: {}", FFMPEG, error))?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(anyhow::anyhow!("{} failed to extract the frames of {} with {}: {}", FFMPEG, input_path.display(), result.status, stderr.trim()));
+    }
+    Ok(())
+}
+
+fn decode_frames(dir: &Path, fps: f32, options: &ConvertOptions) -> anyhow::Result<Vec<AnimationFrame>> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir).with_path("Cannot list", dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()
+        .with_path("Cannot list", dir)?;
+    // The names are zero padded, so they sort in the order of the frames.
+    paths.sort();
+
+    let delay = Duration::from_secs_f64(1.0 / fps as f64);
+    let mut frames: Vec<AnimationFrame> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let mut img = open_image(&path, options)?;
+        apply_transforms(&mut img, options);
+        if frames.first().is_some_and(|first| first.image.dimensions() != (img.width(), img.height())) {
+            return Err(anyhow::anyhow!("The frames of the video don't all have the same size"));
+        }
+        frames.push(AnimationFrame { image: img.into_rgba8(), delay });
+    }
+    Ok(frames)
+}
+
+/// Turns MP4 videos into looping animated WebPs, sampling their frames at
+/// [`ConvertOptions::video_fps`] up to [`ConvertOptions::video_max_duration`]. The frames
+/// are decoded by ffmpeg, which has to be on the `PATH`, and kept losslessly, so the output
+/// grows quickly with the length of the video. The image transformations apply to every
+/// frame, the audio is dropped.
+pub struct Mp4ToWebp;
+
+impl Converter for Mp4ToWebp {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut frames = extract_frames(input_path, output_path, options)?;
+        if frames.len() == 1 {
+            return write_image(&DynamicImage::ImageRgba8(frames.remove(0).image), ImageFileType::WEBP, output_path, options);
+        }
+        write_webp_frames(&frames, output_path)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Video(VideoFileType::MP4)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::WEBP)
+    }
+
+    fn is_available(&self) -> bool {
+        find_program(FFMPEG).is_some()
+    }
+
+    /// Only the sampled frames are kept.
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
+
+/// Like [`Mp4ToWebp`], writing GIFs that loop and share a palette as
+/// [`ConvertOptions::gif_loop`] and [`ConvertOptions::gif_palette`] say. A shared palette
+/// usually looks better for videos and is smaller.
+pub struct Mp4ToGif;
+
+impl Converter for Mp4ToGif {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut frames = extract_frames(input_path, output_path, options)?;
+        if frames.len() == 1 {
+            return write_image(&DynamicImage::ImageRgba8(frames.remove(0).image), ImageFileType::GIF, output_path, options);
+        }
+        write_gif_frames(&frames, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        FileType::Video(VideoFileType::MP4)
+    }
+
+    fn to_type(&self) -> FileType {
+        FileType::Image(ImageFileType::GIF)
+    }
+
+    fn is_available(&self) -> bool {
+        find_program(FFMPEG).is_some()
+    }
+
+    fn is_lossy(&self) -> bool {
+        true
+    }
+}
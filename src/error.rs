@@ -0,0 +1,62 @@
+use thiserror::Error;
+
+use std::path::{Path, PathBuf};
+
+use crate::FileType;
+
+/// Errors with a meaning callers may want to act on. They are returned wrapped in an
+/// [`anyhow::Error`], so use `downcast_ref::<ConvertError>()` to match on them.
+#[derive(Error, Debug)]
+pub enum ConvertError {
+    #[error("image is {width}x{height} ({} pixels), which exceeds the limit of {max_pixels} pixels", *width as u64 * *height as u64)]
+    TooLarge { width: u32, height: u32, max_pixels: u64 },
+    #[error("cannot convert from {from:?} to {to:?}, the following converters are unavailable: {}", format_steps(missing))]
+    Unavailable { from: FileType, to: FileType, missing: Vec<(FileType, FileType)> },
+    #[error("converting from {from:?} to {to:?} requires lossy steps: {}", format_steps(lossy_steps))]
+    WouldLoseData { from: FileType, to: FileType, lossy_steps: Vec<(FileType, FileType)> },
+    #[error("converting from {from:?} to {to:?} takes {steps} steps, more than the limit of {max_hops}")]
+    TooManySteps { from: FileType, to: FileType, steps: usize, max_hops: usize },
+    #[error("converting from {from:?} to {to:?} is not allowed by the converter allowlist")]
+    NotAllowed { from: FileType, to: FileType },
+    #[error("{} has a PSNR of {psnr:.1} dB against its source, below the minimum of {min_psnr} dB", output.display())]
+    QualityTooLow { output: PathBuf, psnr: f64, min_psnr: f64 },
+    #[error("{} failed verification: {reason}{}", output.display(), corrupt.as_ref().map(|corrupt| format!(", kept as {}", corrupt.display())).unwrap_or_default())]
+    VerificationFailed { output: PathBuf, reason: String, corrupt: Option<PathBuf> },
+    #[error("{} was declared as {declared:?} but its contents are {sniffed:?}", path.display())]
+    TypeMismatch { path: PathBuf, declared: FileType, sniffed: FileType },
+    #[error("no conversion path available from {from:?} to {to:?}")]
+    NoConversionPath { from: FileType, to: FileType },
+    #[error("the source {} cannot be read: {reason}", path.display())]
+    SourceUnavailable { path: PathBuf, reason: String },
+    #[error("the {} file type is not specified", if *target { "target" } else { "source" })]
+    TypeNotSpecified { target: bool },
+    #[error("{} already exists and overwriting is off", path.display())]
+    OutputExists { path: PathBuf },
+    #[error("{} cannot be written: {reason}", path.display())]
+    OutputNotWritable { path: PathBuf, reason: String },
+    #[error("invalid options: {reason}")]
+    InvalidOptions { reason: String },
+}
+
+fn format_steps(steps: &[(FileType, FileType)]) -> String {
+    steps.iter()
+        .map(|(from, to)| format!("{:?} -> {:?}", from, to))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Adds the path a filesystem operation failed on to its error, which `std::io::Error` leaves
+/// out. The original error stays the source, so it can still be downcast to.
+pub(crate) trait PathContext<T> {
+    /// `action` starts the message, like `Cannot read`.
+    fn with_path(self, action: &str, path: &Path) -> anyhow::Result<T>;
+}
+
+impl<T, E: std::error::Error + Send + Sync + 'static> PathContext<T> for Result<T, E> {
+    fn with_path(self, action: &str, path: &Path) -> anyhow::Result<T> {
+        self.map_err(|error| {
+            let message = format!("{} {}: {}", action, path.display(), error);
+            anyhow::Error::new(error).context(message)
+        })
+    }
+}
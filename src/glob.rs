@@ -0,0 +1,117 @@
+//! Shell style wildcard matching for batch conversions. `*` matches any run of characters
+//! within a path component, `?` a single character and `**` any number of whole components.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::PathContext;
+
+fn matches_component(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| matches_component(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && matches_component(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && matches_component(rest, &text[1..]),
+    }
+}
+
+fn matches_components(pattern: &[&str], components: &[String]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((&"**", rest)) => (0..=components.len()).any(|skip| matches_components(rest, &components[skip..])),
+        Some((part, rest)) => {
+            let Some((first, remaining)) = components.split_first() else {
+                return false;
+            };
+            let part: Vec<char> = part.chars().collect();
+            let first: Vec<char> = first.chars().collect();
+            matches_component(&part, &first) && matches_components(rest, remaining)
+        }
+    }
+}
+
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir | Component::RootDir))
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect()
+}
+
+fn pattern_parts(pattern: &str) -> Vec<&str> {
+    pattern.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
+}
+
+fn has_wildcard(part: &str) -> bool {
+    part.contains(['*', '?'])
+}
+
+/// Whether `path` matches `pattern`. A pattern without a path separator only has to match
+/// the file name, so `*_thumb.png` matches `assets/logo_thumb.png`.
+pub(crate) fn matches(pattern: &str, path: &Path) -> bool {
+    if !pattern.contains(['/', '\\']) {
+        let name: Vec<char> = path.file_name().map(|name| name.to_string_lossy().chars().collect()).unwrap_or_default();
+        return matches_component(&pattern.chars().collect::<Vec<_>>(), &name);
+    }
+
+    matches_components(&pattern_parts(pattern), &components(path))
+}
+
+/// Lists the files matching `pattern`, together with their path relative to the part of the
+/// pattern before the first wildcard.
+pub(crate) fn expand(pattern: &str) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let parts = pattern_parts(pattern);
+    let literal = parts.iter().take_while(|part| !has_wildcard(part)).count();
+
+    let mut base = PathBuf::new();
+    if pattern.starts_with(['/', '\\']) {
+        base.push(std::path::MAIN_SEPARATOR_STR);
+    }
+    for part in &parts[..literal] {
+        base.push(part);
+    }
+
+    if literal == parts.len() {
+        let relative = PathBuf::from(base.file_name().unwrap_or_default());
+        return Ok(if base.is_file() { vec![(base, relative)] } else { Vec::new() });
+    }
+
+    let remaining = &parts[literal..];
+    let depth = if remaining.contains(&"**") { usize::MAX } else { remaining.len() };
+    let walk_from = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base.clone() };
+
+    let mut found = Vec::new();
+    for relative in walk(&walk_from, depth)? {
+        if matches_components(remaining, &components(&relative)) {
+            found.push((base.join(&relative), relative));
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Lists the files below `dir` at most `depth` levels deep, relative to `dir`.
+pub(crate) fn walk(dir: &Path, depth: usize) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![(PathBuf::new(), 1)];
+
+    while let Some((relative, level)) = pending.pop() {
+        let listed = dir.join(&relative);
+        for entry in std::fs::read_dir(&listed).with_path("Cannot list", &listed)? {
+            let entry = entry.with_path("Cannot list", &listed)?;
+            let path = relative.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if level < depth {
+                    pending.push((path, level + 1));
+                }
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
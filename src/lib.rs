@@ -1,207 +1,2253 @@
 //! This crate aims to convert from one type of file to another. It is cheaper and easier (and local) to 
 //! make the transformation locally than over the web. 
 
+#[cfg(feature = "async")]
+mod background;
+pub(crate) mod base64;
+pub mod batch;
 pub mod converters;
+pub mod error;
+pub(crate) mod glob;
+mod json;
+mod manifest;
+pub mod options;
+pub(crate) mod progress;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod report;
+mod sha256;
+#[cfg(feature = "watch")]
+pub mod watch;
 
-use std::{collections::{HashMap, HashSet, VecDeque}, path::PathBuf};
+use std::{collections::{HashMap, HashSet, VecDeque}, path::{Path, PathBuf}, sync::Arc, time::Duration};
 
-use crate::converters::{audio::AudioFileType, image::{png::PngToJpeg, ImageFileType}};
+use image::{imageops::FilterType, DynamicImage};
 
-#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+use crate::{converters::{audio::{AudioBuffer, AudioFileType, AudioInfo}, document::DocumentFileType, audio::{aiff::{AiffToWav, WavToAiff}, flac::FlacToMp3, mp3::{Mp3ToMp3, Mp3ToPng}, spectrogram::WavToPng, wav::WavToWav}, image::{avif::{AvifToPng, PngToAvif}, gif::{PngToGif, WebpToGif}, hdr::{HdrToPng, PngToHdr}, ico::IcoToPng, jpeg::{JpegToJpeg, JpegToPng}, png::{PngToJpeg, PngToPng}, pnm::{PngToPnm, PnmToPng}, qoi::{PngToQoi, QoiToPng}, sprite::{SpriteFrame, SpriteSheet}, tga::{PngToTga, TgaToPng}, webp::{GifToWebp, JpegToWebp, PngToWebp, WebpToPng}, ImageFileType, ImageInfo}, text::{ascii::ImageToAscii, TextFileType}, video::VideoFileType}, error::PathContext};
+
+pub use crate::{batch::{BatchConvertBuilder, BatchReport, ErrorPolicy}, error::ConvertError, options::{Colormap, ConvertOptions, Endian, GifLoop, GifPalette, ImageFilter, PcmFormat, SpectrogramOptions, ToneMapping, WavEncodeOptions, WebpEncodeOptions}, progress::ProgressFn, report::{AnimationInfo, ConversionReport}};
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy, Default)]
 pub enum FileType {
+    #[default]
     Unknown,
     Image(ImageFileType),
     Audio(AudioFileType),
+    Text(TextFileType),
+    Document(DocumentFileType),
+    Video(VideoFileType),
+}
+
+/// The kinds of [`FileType`], for converters handling every type of a kind, see
+/// [`CategoryConverter`].
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub enum FileCategory {
+    Image,
+    Audio,
+    Text,
+    Document,
+    Video,
+}
+
+impl FileCategory {
+    /// Every file type of this category.
+    pub fn types(self) -> Vec<FileType> {
+        match self {
+            FileCategory::Image => ImageFileType::ALL.into_iter().map(FileType::Image).collect(),
+            FileCategory::Audio => AudioFileType::ALL.into_iter().map(FileType::Audio).collect(),
+            FileCategory::Text => TextFileType::ALL.into_iter().map(FileType::Text).collect(),
+            FileCategory::Document => DocumentFileType::ALL.into_iter().map(FileType::Document).collect(),
+            FileCategory::Video => VideoFileType::ALL.into_iter().map(FileType::Video).collect(),
+        }
+    }
+}
+
+impl FileType {
+    /// The category of the type, `None` for [`FileType::Unknown`].
+    pub fn category(self) -> Option<FileCategory> {
+        match self {
+            FileType::Image(_) => Some(FileCategory::Image),
+            FileType::Audio(_) => Some(FileCategory::Audio),
+            FileType::Text(_) => Some(FileCategory::Text),
+            FileType::Document(_) => Some(FileCategory::Document),
+            FileType::Video(_) => Some(FileCategory::Video),
+            FileType::Unknown => None,
+        }
+    }
+
+    /// Guesses the file type from a file extension, ignoring case.
+    pub fn from_extension(extension: &str) -> Option<FileType> {
+        Some(match extension.to_ascii_lowercase().as_str() {
+            "png" => FileType::Image(ImageFileType::PNG),
+            "jpg" | "jpeg" => FileType::Image(ImageFileType::JPEG),
+            "ico" => FileType::Image(ImageFileType::ICO),
+            "webp" => FileType::Image(ImageFileType::WEBP),
+            "avif" => FileType::Image(ImageFileType::AVIF),
+            "gif" => FileType::Image(ImageFileType::GIF),
+            "psd" => FileType::Image(ImageFileType::PSD),
+            "tga" => FileType::Image(ImageFileType::TGA),
+            "qoi" => FileType::Image(ImageFileType::QOI),
+            "hdr" => FileType::Image(ImageFileType::HDR),
+            "exr" => FileType::Image(ImageFileType::EXR),
+            "pnm" | "ppm" | "pgm" | "pbm" | "pam" => FileType::Image(ImageFileType::PNM),
+            "mp3" => FileType::Audio(AudioFileType::MP3),
+            "wav" => FileType::Audio(AudioFileType::WAV),
+            "aiff" | "aif" => FileType::Audio(AudioFileType::AIFF),
+            "flac" => FileType::Audio(AudioFileType::FLAC),
+            "txt" => FileType::Text(TextFileType::Ascii),
+            "pdf" => FileType::Document(DocumentFileType::PDF),
+            "mp4" | "m4v" => FileType::Video(VideoFileType::MP4),
+            _ => return None,
+        })
+    }
+
+    /// Looks up the file type of a MIME type like `image/webp`, ignoring case and parameters such
+    /// as `; q=0.9`.
+    pub fn from_mime(mime: &str) -> Option<FileType> {
+        let essence = mime.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        Some(match essence.as_str() {
+            "image/png" => FileType::Image(ImageFileType::PNG),
+            "image/jpeg" | "image/jpg" => FileType::Image(ImageFileType::JPEG),
+            "image/x-icon" | "image/vnd.microsoft.icon" => FileType::Image(ImageFileType::ICO),
+            "image/webp" => FileType::Image(ImageFileType::WEBP),
+            "image/avif" => FileType::Image(ImageFileType::AVIF),
+            "image/gif" => FileType::Image(ImageFileType::GIF),
+            "image/vnd.adobe.photoshop" => FileType::Image(ImageFileType::PSD),
+            "image/x-tga" | "image/x-targa" => FileType::Image(ImageFileType::TGA),
+            "image/qoi" => FileType::Image(ImageFileType::QOI),
+            "image/vnd.radiance" => FileType::Image(ImageFileType::HDR),
+            "image/x-exr" => FileType::Image(ImageFileType::EXR),
+            "image/x-portable-anymap" | "image/x-portable-pixmap" | "image/x-portable-graymap" | "image/x-portable-bitmap" => FileType::Image(ImageFileType::PNM),
+            "audio/mpeg" | "audio/mp3" => FileType::Audio(AudioFileType::MP3),
+            "audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" => FileType::Audio(AudioFileType::WAV),
+            "audio/aiff" | "audio/x-aiff" => FileType::Audio(AudioFileType::AIFF),
+            "audio/flac" | "audio/x-flac" => FileType::Audio(AudioFileType::FLAC),
+            "text/plain" => FileType::Text(TextFileType::Ascii),
+            "application/pdf" => FileType::Document(DocumentFileType::PDF),
+            "video/mp4" => FileType::Video(VideoFileType::MP4),
+            _ => return None,
+        })
+    }
+
+    /// Guesses the file type from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<FileType> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
+    /// Guesses the file type from the first bytes of a file. Text files can't be told apart
+    /// from any other data and are never recognised.
+    pub fn from_magic(magic: &[u8]) -> Option<FileType> {
+        if let Some(audio_file_type) = AudioFileType::detect(magic) {
+            return Some(FileType::Audio(audio_file_type));
+        }
+        if magic.starts_with(b"8BPS") {
+            return Some(FileType::Image(ImageFileType::PSD));
+        }
+        if magic.starts_with(b"%PDF-") {
+            return Some(FileType::Document(DocumentFileType::PDF));
+        }
+        if let Some(video_file_type) = VideoFileType::detect(magic) {
+            return Some(FileType::Video(video_file_type));
+        }
+
+        let format = image::guess_format(magic).ok()?;
+        ImageFileType::from_image_format(format).map(FileType::Image)
+    }
+}
+
+/// A decoded source file, see [`FileConvertBuilder::decode_only`].
+#[derive(Debug, Clone)]
+pub enum DecodedMedia {
+    Image(DynamicImage),
+    Audio(AudioBuffer),
+}
+
+/// What [`probe`] found out about a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaInfo {
+    Image { format: ImageFileType, info: ImageInfo },
+    Audio { format: AudioFileType, info: AudioInfo },
+}
+
+/// Inspects the image or audio file at `path` without converting or fully decoding it. The
+/// format is detected from the file contents, the extension is ignored.
+pub fn probe(path: &Path) -> anyhow::Result<MediaInfo> {
+    let mut magic = [0u8; 32];
+    let read = std::io::Read::read(&mut std::fs::File::open(path).with_path("Cannot read", path)?, &mut magic).with_path("Cannot read", path)?;
+
+    match FileType::from_magic(&magic[..read]) {
+        Some(FileType::Audio(format)) => Ok(MediaInfo::Audio { format, info: AudioFileType::probe(path)? }),
+        Some(FileType::Image(_)) => {
+            let (format, info) = ImageFileType::probe(path)?;
+            Ok(MediaInfo::Image { format, info })
+        }
+        _ => Err(anyhow::anyhow!("Cannot tell the file type of {}", path.display())),
+    }
+}
+
+/// How alike two images look, as their structural similarity (SSIM): 1.0 for identical images
+/// and lower the more they differ, around 0.95 and up for good lossy encodes. Meant to check a
+/// lossy output against its source, e.g. in CI. Both must have the same dimensions, colour is
+/// compared through the luma only and transparency is ignored.
+pub fn perceptual_diff(a: &Path, b: &Path) -> anyhow::Result<f64> {
+    let options = ConvertOptions::default();
+    converters::image::ssim(&converters::image::open_image(a, &options)?, &converters::image::open_image(b, &options)?)
+}
+
+/// Counts the pixels of the image at `path` taking every value, per channel.
+pub fn histogram(path: &Path) -> anyhow::Result<converters::image::histogram::Histogram> {
+    let img = converters::image::open_image(path, &ConvertOptions::default())?;
+    Ok(converters::image::histogram::Histogram::of(&img))
+}
+
+/// The most common colour of the image at `path`, e.g. for the background of a placeholder
+/// shown while the image loads. Similar shades are counted together and translucent pixels
+/// count less, fully transparent images have no dominant colour and are an error.
+pub fn dominant_color(path: &Path) -> anyhow::Result<[u8; 3]> {
+    let img = converters::image::open_image(path, &ConvertOptions::default())?;
+    converters::image::histogram::dominant_color(&img)
+        .ok_or_else(|| anyhow::anyhow!("{} is fully transparent, it has no dominant colour", path.display()))
+}
+
+/// Merges the images at `inputs` into a PDF at `output` with a page for each, with the
+/// default settings. Use [`FileConvertBuilder::images_to_pdf`] for anything more.
+#[cfg(feature = "pdf")]
+pub fn images_to_pdf(inputs: &[PathBuf], output: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+    let mut builder = FileConvertBuilder::new();
+    builder.to_file(FileType::Document(DocumentFileType::PDF), Some(output.as_ref().to_path_buf()));
+    builder.images_to_pdf(inputs)
+}
+
+/// Converts `input` to `output` with the default settings, telling both types from their
+/// extensions, and returns the output path. Use a [`FileConvertBuilder`] for anything more.
+pub fn convert(input: impl AsRef<Path>, output: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    let file_type = |path: &Path| FileType::from_path(path)
+        .ok_or_else(|| anyhow::anyhow!("Cannot tell the file type of {} from its extension", path.display()));
+
+    let mut builder = FileConvertBuilder::new();
+    builder.from_file(file_type(input)?, input.to_path_buf())
+        .to_file(file_type(output)?, Some(output.to_path_buf()));
+    Ok(builder.convert()?.output)
+}
+
+#[derive(Default)]
+pub struct FileConvertBuilder {
+    from: (FileType, PathBuf),
+    to: (FileType, Option<PathBuf>),
+    registry: Option<Arc<ConverterRegistry>>,
+    custom_converters: Vec<Box<dyn Converter>>,
+    custom_category_converters: Vec<Box<dyn CategoryConverter>>,
+    options: ConvertOptions,
+    try_alternate_paths: bool,
+    max_hops: Option<usize>,
+    lossless_only: bool,
+    best_effort: bool,
+    best_effort_min_psnr: Option<f64>,
+    min_psnr: Option<f64>,
+    verify_output: bool,
+    on_verify_failure: VerifyFailurePolicy,
+    retries: u32,
+    preserve_mtime: bool,
+    deterministic: bool,
+    write_sidecar: bool,
+    comment: Option<String>,
+    keep_intermediates: bool,
+    temp_prefix: Option<String>,
+    temp_dir: Option<PathBuf>,
+    overwrite: Option<bool>,
+    split_channels: bool,
+    tiled: bool,
+    via: Option<Vec<FileType>>,
+    raw_options: HashMap<String, String>,
+    progress: progress::Progress,
+    create_dirs: bool,
+    #[cfg(unix)]
+    dir_mode: Option<u32>,
+    #[cfg(feature = "remote")]
+    url: Option<String>,
+    #[cfg(feature = "remote")]
+    max_download_bytes: Option<u64>,
+    on_type_mismatch: TypeMismatchPolicy,
 }
 
-impl Default for FileType {
-    fn default() -> Self {
-        Self::Unknown
+/// What a conversion does when the contents of the source say it is of another type than the
+/// declared one, see [`FileConvertBuilder::on_type_mismatch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TypeMismatchPolicy {
+    /// Converts the source as the declared type.
+    TrustDeclared,
+    /// Converts the source as the type detected from its contents.
+    TrustSniffed,
+    /// Fails with [`ConvertError::TypeMismatch`].
+    #[default]
+    Error,
+}
+
+/// What happens to an output failing [`FileConvertBuilder::verify_output`], see
+/// [`FileConvertBuilder::on_verify_failure`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyFailurePolicy {
+    /// Deletes the output.
+    #[default]
+    DeleteOutput,
+    /// Renames the output by appending `.corrupt` to its name, to inspect it later.
+    KeepAsCorrupt,
+}
+
+/// App-wide settings applied to builders with [`FileConvertBuilder::with_defaults`], so they
+/// don't have to be repeated for every conversion. `None` leaves a setting alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConvertDefaults {
+    /// See [`FileConvertBuilder::quality_percent`].
+    pub quality: Option<u8>,
+    /// See [`FileConvertBuilder::overwrite`].
+    pub overwrite: Option<bool>,
+    /// See [`FileConvertBuilder::temp_dir`].
+    pub temp_dir: Option<PathBuf>,
+    /// See [`FileConvertBuilder::temp_prefix`].
+    pub temp_prefix: Option<String>,
+    /// See [`FileConvertBuilder::max_hops`].
+    pub max_hops: Option<usize>,
+    /// See [`FileConvertBuilder::embed_srgb`].
+    pub embed_srgb: Option<bool>,
+}
+
+/// The formats [`FileConvertBuilder::best_effort`] chooses from.
+pub const BEST_EFFORT_CANDIDATES: [ImageFileType; 3] = [ImageFileType::JPEG, ImageFileType::WEBP, ImageFileType::AVIF];
+
+/// Default PSNR a [`FileConvertBuilder::best_effort`] candidate has to reach, in decibels.
+pub const DEFAULT_BEST_EFFORT_MIN_PSNR: f64 = 30.0;
+
+/// Delay before the first retry of a failed converter, doubled for every further attempt.
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// How many conversion paths [`FileConvertBuilder::try_alternate_paths`] tries before giving up.
+pub const MAX_PATH_ATTEMPTS: usize = 3;
+
+/// Default for [`FileConvertBuilder::max_hops`], more than the longest path between the
+/// default converters needs.
+pub const DEFAULT_MAX_HOPS: usize = 4;
+
+static DATA_URI_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+impl FileConvertBuilder {
+    pub fn new() -> Self {
+        Self {
+            registry: Some(Arc::new(ConverterRegistry::new())),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_file(&mut self, file_type: FileType, file_location: PathBuf) -> &mut Self {
+        self.from = (file_type, file_location);
+        self
+    }
+
+    /// Applies the app-wide `defaults` to the settings not made on this builder yet. Settings
+    /// made on the builder take precedence, before or after this call.
+    pub fn with_defaults(&mut self, defaults: &ConvertDefaults) -> &mut Self {
+        self.options.quality = self.options.quality.or(defaults.quality.map(|percent| percent.min(100)));
+        self.overwrite = self.overwrite.or(defaults.overwrite);
+        if self.temp_dir.is_none() {
+            self.temp_dir.clone_from(&defaults.temp_dir);
+        }
+        if self.temp_prefix.is_none() && let Some(prefix) = &defaults.temp_prefix {
+            self.temp_prefix(prefix.clone());
+        }
+        self.max_hops = self.max_hops.or(defaults.max_hops);
+        self.options.embed_srgb = self.options.embed_srgb.or(defaults.embed_srgb);
+        self
+    }
+
+    /// Downloads the source from `url` when converting, instead of reading a local file. This
+    /// needs the `remote` feature and network access, only plain `http://` URLs are supported.
+    /// The type of the download is detected from its contents, and it is kept in the temporary
+    /// directory until the conversion is done. Without an output path the output is named
+    /// after the last segment of the URL and written to the current directory.
+    #[cfg(feature = "remote")]
+    pub fn from_url(&mut self, url: &str) -> &mut Self {
+        self.url = Some(url.to_string());
+        self
+    }
+
+    /// Caps the size of a [`Self::from_url`] download, [`remote::DEFAULT_MAX_DOWNLOAD_BYTES`]
+    /// by default. Larger responses fail the conversion.
+    #[cfg(feature = "remote")]
+    pub fn max_download_bytes(&mut self, max_bytes: u64) -> &mut Self {
+        self.max_download_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn to_file(&mut self, file_type: FileType, file_location: Option<PathBuf>) -> &mut Self {
+        self.to = (file_type, file_location);
+        self
+    }
+
+    /// Sets the target type from a MIME type, e.g. one taken from an `Accept` header, keeping
+    /// the output location. Fails for MIME types [`FileType::from_mime`] doesn't know.
+    pub fn to_mime(&mut self, mime: &str) -> anyhow::Result<&mut Self> {
+        let file_type = FileType::from_mime(mime).ok_or_else(|| anyhow::anyhow!("Unknown MIME type {:?}", mime))?;
+        self.to.0 = file_type;
+        Ok(self)
+    }
+
+    pub fn with_converter<C>(&mut self, converter: C) -> &mut Self 
+    where
+        C: Converter + 'static,
+    {
+        self.custom_converters.push(Box::new(converter));
+        self
+    }
+
+    pub fn with_converters(&mut self, converters: Vec<Box<dyn Converter>>) -> &mut Self {
+        self.custom_converters.extend(converters);
+        self
+    }
+
+    /// Adds a converter for every pair of types of its category, see
+    /// [`ConverterRegistry::register_category`].
+    pub fn with_category_converter<C>(&mut self, converter: C) -> &mut Self
+    where
+        C: CategoryConverter + 'static,
+    {
+        self.custom_category_converters.push(Box::new(converter));
+        self
+    }
+
+    /// Replaces the default registry, e.g. with one restricted by
+    /// [`ConverterRegistry::set_allowlist`]. Converters added with [`Self::with_converter`] are
+    /// still registered on top of it.
+    pub fn with_registry(&mut self, registry: ConverterRegistry) -> &mut Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
+
+    /// Uses a registry shared with other builders, e.g. by a server handling every request on
+    /// its own thread, instead of building the default registry for each of them. Converters
+    /// added with [`Self::with_converter`] go to a [`ConverterRegistry::snapshot`] of it, so
+    /// the other builders don't see them.
+    pub fn with_shared_registry(&mut self, registry: Arc<ConverterRegistry>) -> &mut Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Adds a filter that runs on the decoded image before it is encoded, e.g. to adjust
+    /// brightness or stamp a watermark. Filters run in the order they are added.
+    pub fn with_image_filter<F>(&mut self, filter: F) -> &mut Self
+    where
+        F: Fn(&mut DynamicImage) + Send + Sync + 'static,
+    {
+        self.options.image_filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Crops black borders, such as those around scans, before the image filters run and the
+    /// image is encoded. Edge rows and columns are cropped while none of the channels of their
+    /// pixels exceed `threshold`. Only applies to image conversions.
+    pub fn auto_crop_borders(&mut self, threshold: u8) -> &mut Self {
+        self.options.auto_crop_threshold = Some(threshold);
+        self
+    }
+
+    /// Rotates images clockwise by `degrees`, after cropping borders and before the image
+    /// filters. Converting a JPEG to JPEG with no other change rotates its DCT blocks instead of
+    /// re-encoding it, which loses nothing, as long as the dimensions are a multiple of the MCU
+    /// size (8 or 16 pixels depending on the chroma subsampling); other JPEGs are decoded and
+    /// encoded again, with a warning. Degrees that aren't a multiple of 90 are rounded to one.
+    pub fn jpeg_lossless_rotate(&mut self, degrees: u32) -> &mut Self {
+        if !degrees.is_multiple_of(90) {
+            println!("Rotations go by quarter turns, rounding {} degrees to {}", degrees, (degrees + 45) / 90 * 90);
+        }
+        self.options.quarter_turns = ((degrees + 45) / 90 % 4) as u8;
+        self
+    }
+
+    /// Resizes images to exactly `width` by `height` pixels, stretching them if the aspect ratio
+    /// differs, after rotating them and before the image filters. A zero side is taken as 1.
+    pub fn resize(&mut self, width: u32, height: u32) -> &mut Self {
+        self.options.resize = Some((width.max(1), height.max(1)));
+        self
+    }
+
+    /// Scales images down to fit within `width` by `height` pixels keeping their aspect ratio,
+    /// e.g. for thumbnails: a 1000x500 image fit in 300x300 becomes 300x150. Images that already
+    /// fit are never scaled up. Runs after [`Self::resize`] with the downscaling filter. A zero
+    /// side is taken as 1.
+    pub fn max_dimensions(&mut self, width: u32, height: u32) -> &mut Self {
+        self.options.max_dimensions = Some((width.max(1), height.max(1)));
+        self
+    }
+
+    /// The filter resizes use when they make an image larger, [`FilterType::CatmullRom`] by
+    /// default, which stays sharp without the ringing of Lanczos.
+    pub fn resize_up_filter(&mut self, filter: FilterType) -> &mut Self {
+        self.options.resize_up_filter = filter;
+        self
+    }
+
+    /// The filter resizes use when they make an image smaller, [`FilterType::Lanczos3`] by
+    /// default, which keeps fine detail without aliasing. An image counts as made smaller when
+    /// it loses pixels overall.
+    pub fn resize_down_filter(&mut self, filter: FilterType) -> &mut Self {
+        self.options.resize_down_filter = filter;
+        self
+    }
+
+    /// Rejects images whose header declares more than `max_pixels` pixels before decoding
+    /// them, protecting against decompression bombs. Defaults to [`options::DEFAULT_MAX_PIXELS`].
+    pub fn max_pixels(&mut self, max_pixels: u64) -> &mut Self {
+        self.options.max_pixels = max_pixels;
+        self
+    }
+
+    /// When a step of the chosen conversion path fails, look for another path that avoids the
+    /// failing converter and retry along it, up to [`MAX_PATH_ATTEMPTS`] paths in total.
+    pub fn try_alternate_paths(&mut self, enabled: bool) -> &mut Self {
+        self.try_alternate_paths = enabled;
+        self
+    }
+
+    /// The most converters a conversion path may chain, [`DEFAULT_MAX_HOPS`] by default. Every
+    /// step can lose quality, so longer paths fail with [`ConvertError::TooManySteps`] instead
+    /// of producing a poor output. Doesn't apply to the steps given with [`Self::via`].
+    pub fn max_hops(&mut self, max_hops: usize) -> &mut Self {
+        self.max_hops = Some(max_hops);
+        self
+    }
+
+    /// Refuses to convert if every available path contains a lossy step, returning
+    /// [`ConvertError::WouldLoseData`] before anything is written.
+    pub fn lossless_only(&mut self, enabled: bool) -> &mut Self {
+        self.lossless_only = enabled;
+        self
+    }
+
+    /// Instead of only producing the requested format, encode the image with every candidate
+    /// in [`BEST_EFFORT_CANDIDATES`] and keep the smallest output whose PSNR against the source
+    /// is at least [`FileConvertBuilder::best_effort_min_psnr`]. The output path's extension
+    /// is changed to match the winning format, which is listed in the report.
+    pub fn best_effort(&mut self, enabled: bool) -> &mut Self {
+        self.best_effort = enabled;
+        self
+    }
+
+    /// The quality a [`FileConvertBuilder::best_effort`] candidate must reach, in decibels.
+    /// Defaults to [`DEFAULT_BEST_EFFORT_MIN_PSNR`].
+    pub fn best_effort_min_psnr(&mut self, min_psnr: f64) -> &mut Self {
+        self.best_effort_min_psnr = Some(min_psnr);
+        self
+    }
+
+    /// Fails image conversions whose output has a PSNR against the source below `min_psnr`
+    /// decibels with [`ConvertError::QualityTooLow`], e.g. to catch quality settings that are
+    /// too aggressive in CI. Around 30 dB is acceptable and 40 dB hard to tell from the source.
+    /// The output of a failed check is deleted. The PSNR is recorded in
+    /// [`ConversionReport::psnr`], outputs that can't be decoded again are not checked.
+    pub fn min_psnr(&mut self, min_psnr: f64) -> &mut Self {
+        self.min_psnr = Some(min_psnr);
+        self
+    }
+
+    /// Reads every output back once it is written, failing with
+    /// [`ConvertError::VerificationFailed`] if its contents aren't of the target type or, for
+    /// images, WAV and AIFF, don't decode. The failed output is handled as set by
+    /// [`Self::on_verify_failure`], the source is never touched.
+    pub fn verify_output(&mut self, enabled: bool) -> &mut Self {
+        self.verify_output = enabled;
+        self
+    }
+
+    /// Decides what happens to outputs failing [`Self::verify_output`], so a failed conversion
+    /// doesn't leave a file that looks fine. Deletes them by default.
+    pub fn on_verify_failure(&mut self, policy: VerifyFailurePolicy) -> &mut Self {
+        self.on_verify_failure = policy;
+        self
+    }
+
+    /// Renames a file whose extension doesn't match its contents, e.g. a PNG saved as `.jpg`,
+    /// without touching its bytes. Returns the new path, or `path` itself if the extension was
+    /// already right. Fails if the type can't be detected or the new name is taken.
+    pub fn fix_extension(path: &Path) -> anyhow::Result<PathBuf> {
+        let mut magic = [0u8; 32];
+        let read = std::io::Read::read(&mut std::fs::File::open(path).with_path("Cannot read", path)?, &mut magic).with_path("Cannot read", path)?;
+        let file_type = FileType::from_magic(&magic[..read])
+            .ok_or_else(|| anyhow::anyhow!("Cannot tell the file type of {}", path.display()))?;
+
+        if FileType::from_path(path) == Some(file_type) {
+            return Ok(path.to_path_buf());
+        }
+
+        let fixed = path.with_extension(get_extension_for_type(&file_type));
+        if fixed.exists() {
+            return Err(anyhow::anyhow!("Cannot rename {} to {}, the file already exists", path.display(), fixed.display()));
+        }
+        std::fs::rename(path, &fixed).with_path(&format!("Cannot rename {} to", path.display()), &fixed)?;
+        Ok(fixed)
+    }
+
+    /// Resolves the converter from `from` to `to` once, for callers converting many images in a
+    /// loop who don't want a lookup and path search for each of them. Only direct conversions
+    /// are returned, `None` means there is no single available converter between the two types.
+    /// Filters and other options aren't bound to the converter, pass them to it explicitly.
+    pub fn image_converter(&self, from: ImageFileType, to: ImageFileType) -> Option<&dyn Converter> {
+        let (from, to) = (FileType::Image(from), FileType::Image(to));
+        // Custom converters take precedence over the registry, the last one added winning.
+        self.custom_converters.iter().rev()
+            .find(|converter| converter.from_type() == from && converter.to_type() == to)
+            .map(|converter| converter.as_ref())
+            .or_else(|| self.registry.as_ref()?.get(from, to))
+            .filter(|converter| converter.is_available() && self.registry.as_ref().is_none_or(|registry| registry.is_allowed(from, to)))
+    }
+
+    /// Decodes the source file without converting it, for callers who want to process the
+    /// pixels or samples themselves. Image filters are applied to the decoded image.
+    pub fn decode_only(self) -> anyhow::Result<DecodedMedia> {
+        match self.from.0 {
+            FileType::Image(_) => {
+                let mut img = converters::image::open_image(&self.from.1, &self.options)?;
+                converters::image::apply_transforms(&mut img, &self.options);
+                Ok(DecodedMedia::Image(img))
+            }
+            FileType::Audio(audio_file_type) => Ok(DecodedMedia::Audio(audio_file_type.decode(&self.from.1)?)),
+            FileType::Text(_) => Err(anyhow::anyhow!("Decoding text files is not supported")),
+            FileType::Document(_) => Err(anyhow::anyhow!("Decoding documents is not supported")),
+            FileType::Video(_) => Err(anyhow::anyhow!("Decoding videos is not supported")),
+            FileType::Unknown => Err(anyhow::anyhow!("Source file type not specified")),
+        }
+    }
+
+    /// Computes a [BlurHash](https://blurha.sh) of the source image, a short string apps can
+    /// turn into a blurred placeholder while the image loads. `components` is the number of
+    /// horizontal and vertical components, each from 1 to 9; more keep more detail in a longer
+    /// hash, `(4, 3)` is a common choice. Image filters are applied first, the output is ignored.
+    pub fn to_blurhash(self, components: (u32, u32)) -> anyhow::Result<String> {
+        match self.decode_only()? {
+            DecodedMedia::Image(img) => converters::image::blurhash::encode(&img, components),
+            DecodedMedia::Audio(_) => Err(anyhow::anyhow!("BlurHashes can only be made of images")),
+        }
+    }
+
+    /// Writes a complete favicon set made of the source image into `out_dir`: a `favicon.ico`
+    /// with 16, 32 and 48px icons, `favicon-16.png`, `favicon-32.png`, a 180px
+    /// `apple-touch-icon.png` and a `site.webmanifest` listing the PNGs. Non-square images are
+    /// cropped around their centre and image filters are applied first. Returns the written
+    /// files, the output path is ignored.
+    pub fn to_favicon_bundle(self, out_dir: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+        let out_dir = out_dir.as_ref();
+        self.ensure_output_dir(&out_dir.join("favicon.ico"))?;
+        let options = self.options.without_image_transforms();
+
+        match self.decode_only()? {
+            DecodedMedia::Image(img) => converters::image::favicon::write_bundle(&img, out_dir, &options),
+            DecodedMedia::Audio(_) => Err(anyhow::anyhow!("Favicons can only be made of images")),
+        }
+    }
+
+    /// Packs the images at `inputs` into a sprite sheet on a grid `columns` wide, filled row by
+    /// row, and writes it to the output path as the target type. Frames of different sizes are
+    /// padded to the largest one with a message. Image transformations apply to every frame
+    /// and the source is ignored. The returned sheet has the rectangle of every frame and can
+    /// write them as a JSON atlas with [`SpriteSheet::write_atlas`].
+    pub fn sprite_sheet(self, inputs: &[PathBuf], columns: u32) -> anyhow::Result<SpriteSheet> {
+        let FileType::Image(format) = self.to.0 else {
+            return Err(anyhow::anyhow!("Sprite sheets can only be written as images"));
+        };
+        let output = self.to.1.clone().ok_or_else(|| anyhow::anyhow!("Output path not specified"))?;
+
+        let frames = inputs.iter()
+            .map(|input| {
+                let mut img = converters::image::open_image(input, &self.options)?;
+                converters::image::apply_transforms(&mut img, &self.options);
+                Ok(img)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let packed = converters::image::sprite::pack(&frames, columns, &self.options)?;
+
+        self.ensure_output_dir(&output)?;
+        converters::image::encode::write_image(&DynamicImage::ImageRgba8(packed.sheet), format, &output, &self.options)?;
+
+        let frames = inputs.iter().zip(&frames).zip(packed.positions)
+            .map(|((input, frame), (x, y))| SpriteFrame {
+                name: input.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+                x,
+                y,
+                width: frame.width(),
+                height: frame.height(),
+            })
+            .collect();
+        Ok(SpriteSheet { output, cell_width: packed.cell_width, cell_height: packed.cell_height, columns: packed.columns, frames })
+    }
+
+    /// Writes the images at `inputs` to the output path as a PDF with a page for each of them,
+    /// in order, e.g. to merge scans into one document. Every page is sized to its image at the
+    /// resolution the image declares unless [`Self::pdf_dpi`] sets one for all of them. Image
+    /// transformations apply to every image and the source is ignored. Needs the `pdf` feature.
+    #[cfg(feature = "pdf")]
+    pub fn images_to_pdf(self, inputs: &[PathBuf]) -> anyhow::Result<PathBuf> {
+        let output = self.to.1.clone().ok_or_else(|| anyhow::anyhow!("Output path not specified"))?;
+        let pages = inputs.iter()
+            .map(|input| converters::document::pdf::open_page(input, &self.options))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.ensure_output_dir(&output)?;
+        converters::document::pdf::write_pdf(&pages, &output)?;
+        Ok(output)
+    }
+
+    /// Converts `input` along the path set with [`Self::via`].
+    #[allow(clippy::too_many_arguments)]
+    fn convert_via(&self, registry: &ConverterRegistry, from: FileType, input: &Path, via: &[FileType], to: FileType, output_path: &Path, options: &ConvertOptions, intermediates: &mut Vec<PathBuf>) -> anyhow::Result<Vec<FileType>> {
+        let path: Vec<FileType> = std::iter::once(from).chain(via.iter().copied()).chain(std::iter::once(to)).collect();
+        let steps: Vec<(FileType, FileType)> = path.windows(2).map(|step| (step[0], step[1])).collect();
+
+        let unregistered: Vec<_> = steps.iter().filter(|(from, to)| registry.get(*from, *to).is_none()).collect();
+        if !unregistered.is_empty() {
+            let unregistered = unregistered.iter().map(|(from, to)| format!("{:?} -> {:?}", from, to)).collect::<Vec<_>>();
+            return Err(anyhow::anyhow!("No converter registered for the forced steps {}", unregistered.join(", ")));
+        }
+
+        if let Some((from, to)) = steps.iter().find(|(from, to)| !registry.is_allowed(*from, *to)) {
+            return Err(ConvertError::NotAllowed { from: *from, to: *to }.into());
+        }
+
+        let missing: Vec<_> = steps.iter().copied().filter(|(from, to)| !registry.has_direct(*from, *to)).collect();
+        if !missing.is_empty() {
+            return Err(ConvertError::Unavailable { from, to, missing }.into());
+        }
+
+        let lossy_steps = registry.lossy_steps(&path);
+        if self.lossless_only && !lossy_steps.is_empty() {
+            return Err(ConvertError::WouldLoseData { from, to, lossy_steps }.into());
+        }
+
+        self.run_path(registry, input, &path, output_path, options, intermediates).map_err(|(_, error)| error)?;
+        Ok(path)
+    }
+
+    /// Writes every channel of an audio source to a separate mono file instead of converting
+    /// it as a whole. A stereo `song.wav` becomes `song_L.wav` and `song_R.wav`, other sources
+    /// get numbered files starting at `song_1.wav`. They are listed in
+    /// [`ConversionReport::channel_outputs`]. Channels are split after any remixing.
+    pub fn split_channels(&mut self, enabled: bool) -> &mut Self {
+        self.split_channels = enabled;
+        self
+    }
+
+    /// Streams huge images through in strips of rows instead of decoding them whole, so
+    /// [`Self::max_pixels`] limits the pixels held in memory at once rather than the size of the
+    /// image. Only direct PNG to PNG or PNM conversions without image transformations can be
+    /// tiled, others are converted as usual with a message.
+    pub fn tiled(&mut self, enabled: bool) -> &mut Self {
+        self.tiled = enabled;
+        self
+    }
+
+    /// Remixes audio to `channels` channels. Sources with more channels are downmixed with the
+    /// standard ITU-R BS.775 coefficients unless a matrix is given with
+    /// [`FileConvertBuilder::channel_matrix`].
+    pub fn channels(&mut self, channels: u16) -> &mut Self {
+        self.options.channels = Some(channels);
+        self
+    }
+
+    /// Remixes audio with a custom matrix, with one row of input channel gains per output
+    /// channel. Takes precedence over [`FileConvertBuilder::channels`].
+    pub fn channel_matrix(&mut self, matrix: Vec<Vec<f32>>) -> &mut Self {
+        self.options.channel_matrix = Some(matrix);
+        self
+    }
+
+    /// Normalizes audio to an integrated loudness of `target_lufs`, measured as EBU R128 and
+    /// ITU-R BS.1770 specify, -23 LUFS being the broadcast target and around -16 LUFS the usual
+    /// one for podcasts. Unlike peak normalization this follows how loud the audio sounds, so
+    /// the gain can be positive enough to clip integer outputs, which is reported.
+    pub fn loudness_normalize(&mut self, target_lufs: f32) -> &mut Self {
+        self.options.loudness_target = Some(target_lufs);
+        self
+    }
+
+    /// Sets the ID3v2 text frame `frame_id`, like `TIT2` for the title or `TPE1` for the
+    /// artist, of MP3 outputs. The audio frames are copied as they are.
+    pub fn id3_text(&mut self, frame_id: &str, text: &str) -> &mut Self {
+        self.options.id3_text.insert(frame_id.to_string(), Some(text.to_string()));
+        self
+    }
+
+    /// Removes the ID3v2 text frame `frame_id` from MP3 outputs.
+    pub fn remove_id3_text(&mut self, frame_id: &str) -> &mut Self {
+        self.options.id3_text.insert(frame_id.to_string(), None);
+        self
+    }
+
+    /// Trims MP3 outputs to the audio from `start` to `end`, or to the end of the source if
+    /// `None`, without re-encoding it. The times are rounded to the nearest frame boundary, at
+    /// most 13 ms away at 44.1 kHz.
+    pub fn mp3_trim(&mut self, start: Duration, end: Option<Duration>) -> &mut Self {
+        self.options.mp3_trim = Some((start, end));
+        self
+    }
+
+    /// Embeds an sRGB color profile in encoded images so every viewer renders them the same.
+    /// Enabled by default for web formats.
+    pub fn embed_srgb(&mut self, enabled: bool) -> &mut Self {
+        self.options.embed_srgb = Some(enabled);
+        self
+    }
+
+    /// Tells whether the source stores premultiplied alpha, with its colour channels already
+    /// multiplied by the alpha like some renderers and compositors write them. Such sources
+    /// are un-premultiplied after decoding, their translucent edges would come out dark when
+    /// composited or re-encoded otherwise. By default only EXRs are, as their format specifies.
+    pub fn premultiplied(&mut self, enabled: bool) -> &mut Self {
+        self.options.premultiplied = Some(enabled);
+        self
+    }
+
+    /// Sets how HDR images are brought into the range of 8-bit outputs, Reinhard by default.
+    pub fn tone_mapping(&mut self, operator: ToneMapping) -> &mut Self {
+        self.options.tone_mapping = operator;
+        self
+    }
+
+    /// Brightens HDR images by `stops` before they are tone-mapped to 8-bit outputs, or
+    /// darkens them if negative. Every stop doubles the light.
+    pub fn exposure(&mut self, stops: f32) -> &mut Self {
+        self.options.exposure = stops;
+        self
+    }
+
+    /// Sets the output quality of lossy encoders as a percentage, mapped onto each codec's own
+    /// scale: the JPEG quality and the AVIF quality. Lossless outputs ignore it. Values above
+    /// 100 are clamped.
+    pub fn quality_percent(&mut self, percent: u8) -> &mut Self {
+        self.options.quality = Some(percent.min(100));
+        self
+    }
+
+    /// Sets encoder options by name, for front ends passing them through without a flag per
+    /// option. Keys are the target format's extension and the option, like `jpeg.quality`;
+    /// options for other formats than the target are ignored so one map can serve several
+    /// conversions. Unknown keys are warned about, invalid values and options the encoders
+    /// can't honour fail the conversion. See [`options::RAW_OPTIONS`] for the recognised keys.
+    pub fn raw_options(&mut self, raw: HashMap<String, String>) -> &mut Self {
+        self.raw_options.extend(raw);
+        self
+    }
+
+    /// Sets how WebP outputs are encoded, losslessly by default.
+    pub fn webp_options(&mut self, webp: WebpEncodeOptions) -> &mut Self {
+        self.options.webp = webp;
+        self
+    }
+
+    /// Sets the sample format of WAV outputs. By default they keep the format of the source,
+    /// or of the WAV file it was decoded to.
+    pub fn wav_options(&mut self, wav: WavEncodeOptions) -> &mut Self {
+        self.options.wav = Some(wav);
+        self
+    }
+
+    /// Sets the resolution images are placed on PDF pages at, instead of the one each image
+    /// declares, or [`options::DEFAULT_PDF_DPI`] for images declaring none. Pages are sized to
+    /// their image, a 2550x3300 scan at 300 DPI making a US Letter page.
+    pub fn pdf_dpi(&mut self, dpi: f32) -> &mut Self {
+        self.options.pdf_dpi = Some(dpi);
+        self
+    }
+
+    /// Sets how many frames per second of a video are kept when turning it into an animation,
+    /// [`options::DEFAULT_VIDEO_FPS`] by default.
+    pub fn video_fps(&mut self, fps: f32) -> &mut Self {
+        self.options.video_fps = Some(fps);
+        self
+    }
+
+    /// Only turns the first `duration` of videos into an animation, to keep GIFs and WebPs of
+    /// long videos to a reasonable size.
+    pub fn video_max_duration(&mut self, duration: Duration) -> &mut Self {
+        self.options.video_max_duration = Some(duration);
+        self
+    }
+
+    /// Sets the byte order of WAV outputs, [`Endian::Little`] by default as the format
+    /// specifies. Big endian outputs are written as RIFX files, for the embedded players that
+    /// expect them.
+    pub fn wav_endian(&mut self, endian: Endian) -> &mut Self {
+        self.options.wav_endian = endian;
+        self
+    }
+
+    /// Sets the FFT size and colormap of the spectrograms WAV files are rendered as when
+    /// converted to an image, 1024 samples in [`Colormap::Viridis`] by default.
+    pub fn spectrogram_options(&mut self, spectrogram: SpectrogramOptions) -> &mut Self {
+        self.options.spectrogram = spectrogram;
+        self
+    }
+
+    /// Adds or drops the alpha channel of image outputs: `Some(true)` adds an opaque alpha
+    /// channel, `Some(false)` composites translucent pixels over white and drops it, and `None`,
+    /// the default, keeps whatever the source has. Formats without transparency like JPEG are
+    /// unaffected. Converting a PNG to PNG re-encodes it when this is set.
+    pub fn force_alpha(&mut self, alpha: Option<bool>) -> &mut Self {
+        self.options.force_alpha = alpha;
+        self
+    }
+
+    /// Converts animated WebPs to animated PNGs (APNG) with every frame and its delay, rather
+    /// than to a PNG of the first frame.
+    pub fn all_frames(&mut self, enabled: bool) -> &mut Self {
+        self.options.all_frames = enabled;
+        self
+    }
+
+    /// Whether to dither images when reducing them to the 256 colours of a GIF. Dithering hides
+    /// banding in gradients at the cost of a noisier, larger file.
+    pub fn gif_dither(&mut self, enabled: bool) -> &mut Self {
+        self.options.gif_dither = enabled;
+        self
+    }
+
+    /// Sets how often animated GIF outputs play, forever by default.
+    pub fn gif_loop(&mut self, gif_loop: GifLoop) -> &mut Self {
+        self.options.gif_loop = gif_loop;
+        self
+    }
+
+    /// Sets whether the frames of animated GIF outputs get palettes of their own, the default,
+    /// or share one. A shared palette suits videos, whose frames have much the same colours.
+    pub fn gif_palette(&mut self, palette: GifPalette) -> &mut Self {
+        self.options.gif_palette = palette;
+        self
+    }
+
+    /// Whether to run-length encode TGAs, which shrinks images with flat areas. On by default,
+    /// some older tools only read uncompressed TGAs.
+    pub fn tga_rle(&mut self, enabled: bool) -> &mut Self {
+        self.options.tga_rle = enabled;
+        self
+    }
+
+    /// Whether to write progressive JPEGs, which show a coarse preview while loading and are
+    /// often a little smaller. `None`, the default, writes baseline JPEGs except when re-encoding
+    /// a JPEG, which keeps the mode of the source.
+    pub fn jpeg_progressive(&mut self, progressive: Option<bool>) -> &mut Self {
+        self.options.jpeg_progressive = progressive;
+        self
+    }
+
+    /// Sets the bitrate of MP3 outputs in kbit/s, one of the MPEG-1 Layer III bitrates from 32
+    /// to 320. Defaults to [`converters::audio::flac::DEFAULT_MP3_BITRATE`].
+    pub fn mp3_bitrate(&mut self, kbps: u32) -> &mut Self {
+        self.options.mp3_bitrate = Some(kbps);
+        self
+    }
+
+    /// Decides what happens when the source's contents don't match its declared type, e.g. a
+    /// PNG passed as a JPEG. Types that can't be detected from their contents, like TGA, are
+    /// always trusted. Fails the conversion by default, to catch mislabelled files early.
+    pub fn on_type_mismatch(&mut self, policy: TypeMismatchPolicy) -> &mut Self {
+        self.on_type_mismatch = policy;
+        self
+    }
+
+    /// Creates the missing parent directories of the output. Without it a missing output
+    /// directory fails the conversion before anything is converted.
+    pub fn create_dirs(&mut self, enabled: bool) -> &mut Self {
+        self.create_dirs = enabled;
+        self
+    }
+
+    /// Whether to replace an output that already exists, on by default. When off, converting to
+    /// an existing file fails with [`ConvertError::OutputExists`] before anything is converted.
+    pub fn overwrite(&mut self, enabled: bool) -> &mut Self {
+        self.overwrite = Some(enabled);
+        self
+    }
+
+    /// Sets the permissions of the directories [`Self::create_dirs`] creates, e.g. `0o750`.
+    /// Defaults to the process umask.
+    #[cfg(unix)]
+    pub fn dir_mode(&mut self, mode: u32) -> &mut Self {
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    /// Forces the conversion through `steps`, in order, instead of searching for the shortest
+    /// path. `steps` only lists the intermediate types, the source and target are implied, so
+    /// `via(vec![WEBP])` converts PNG to JPEG as PNG -> WEBP -> JPEG even though a direct
+    /// converter exists. Every consecutive pair needs a registered converter, the conversion
+    /// fails otherwise.
+    pub fn via(&mut self, steps: Vec<FileType>) -> &mut Self {
+        self.via = Some(steps);
+        self
+    }
+
+    /// Retries a failing converter up to `retries` more times, waiting [`RETRY_BASE_DELAY`]
+    /// before the first retry and twice as long before each following one. Meant for converters
+    /// relying on flaky external tools, errors about the request itself are never retried.
+    pub fn retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Strips timestamps from the output, PNG `tIME` chunks and JPEG EXIF dates, so converting
+    /// the same input always produces byte-identical files, e.g. for reproducible builds. The
+    /// encoders don't write timestamps themselves, but files copied unchanged may carry them.
+    pub fn deterministic(&mut self, enabled: bool) -> &mut Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Writes a sidecar JSON file next to the output, `photo.png.json` for `photo.png`, with the
+    /// source path and SHA-256, the conversion steps, the options and a UTC timestamp, for
+    /// archives that need to keep track of provenance. [`Self::deterministic`] conversions
+    /// leave the timestamp out.
+    pub fn write_sidecar(&mut self, enabled: bool) -> &mut Self {
+        self.write_sidecar = enabled;
+        self
+    }
+
+    /// Embeds a comment, such as the generator or a copyright notice, in the output: a COM
+    /// segment in JPEGs and the XMP description in WebP files. Other formats are written
+    /// without it, with a warning.
+    pub fn set_comment(&mut self, comment: String) -> &mut Self {
+        self.comment = Some(comment);
+        self
+    }
+
+    /// Keeps the intermediate files of multi-step conversions instead of deleting them, and
+    /// lists them in [`ConversionReport::intermediates`], to inspect what each step produced.
+    /// Off by default.
+    pub fn keep_intermediates(&mut self, enabled: bool) -> &mut Self {
+        self.keep_intermediates = enabled;
+        self
+    }
+
+    /// Replaces the `temp` that the names of intermediate files start with, e.g. to tell the
+    /// files of several jobs apart. Path separators are replaced with underscores.
+    pub fn temp_prefix(&mut self, prefix: String) -> &mut Self {
+        self.temp_prefix = Some(prefix.replace(['/', '\\'], "_"));
+        self
+    }
+
+    /// Writes the intermediate files of multi-step and split channel conversions to `dir`,
+    /// which must exist, instead of next to the output, e.g. to keep them off a network share.
+    /// Best effort candidates still go next to the output, they are renamed to it.
+    pub fn temp_dir(&mut self, dir: PathBuf) -> &mut Self {
+        self.temp_dir = Some(dir);
+        self
+    }
+
+    /// Gives the output the modification time of the source, so sync and backup tools don't
+    /// take it for a newer file. Intermediate files are left alone.
+    pub fn preserve_mtime(&mut self, enabled: bool) -> &mut Self {
+        self.preserve_mtime = enabled;
+        self
+    }
+
+    /// Calls `callback` with the fraction of the conversion done, from 0.0 to 1.0, after each
+    /// intermediate step of the conversion path and once the output is complete. The fraction
+    /// never goes down, also when another path is tried. Best-effort and split conversions
+    /// only report completion.
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(f32) + Send + Sync + 'static,
+    {
+        self.progress.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Checks the settings without converting anything and returns every problem found, where
+    /// [`Self::convert`] stops at the first one, so a UI can point them all out at once. It
+    /// checks that the source exists and has the declared type, that both types are set and
+    /// a conversion path joins them, that the output can be written, and that the options are
+    /// valid and don't conflict. Passing doesn't guarantee the conversion succeeds, decoding
+    /// the source can still fail. Paths through custom converters aren't checked, and neither
+    /// is the source of [`Self::from_url`].
+    pub fn validate(&self) -> Result<(), Vec<ConvertError>> {
+        let mut errors = Vec::new();
+        let (from, to) = (self.from.0, self.to.0);
+        #[cfg(feature = "remote")]
+        let remote = self.url.is_some();
+        #[cfg(not(feature = "remote"))]
+        let remote = false;
+
+        if !remote {
+            if from == FileType::Unknown {
+                errors.push(ConvertError::TypeNotSpecified { target: false });
+            }
+            if !self.from.1.is_file() {
+                errors.push(ConvertError::SourceUnavailable { path: self.from.1.clone(), reason: "it is not an existing file".to_string() });
+            } else if from != FileType::Unknown && self.on_type_mismatch == TypeMismatchPolicy::Error {
+                // The other policies only print a message.
+                if let Err(error) = self.check_source_type(from, &self.from.1) {
+                    errors.push(error.downcast::<ConvertError>().unwrap_or_else(|error| {
+                        ConvertError::SourceUnavailable { path: self.from.1.clone(), reason: error.root_cause().to_string() }
+                    }));
+                }
+            }
+        }
+        if to == FileType::Unknown {
+            errors.push(ConvertError::TypeNotSpecified { target: true });
+        }
+
+        if !remote && from != FileType::Unknown && to != FileType::Unknown && self.via.is_none() && !self.best_effort && !self.split_channels
+            && self.custom_converters.is_empty() && self.custom_category_converters.is_empty()
+            && let Some(registry) = &self.registry
+            && registry.find_conversion_path(from, to).is_none()
+        {
+            let missing = registry.missing_converters(from, to);
+            errors.push(if !missing.is_empty() {
+                ConvertError::Unavailable { from, to, missing }
+            } else if registry.blocked_by_allowlist(from, to) {
+                ConvertError::NotAllowed { from, to }
+            } else {
+                ConvertError::NoConversionPath { from, to }
+            });
+        }
+
+        let output_path = match &self.to.1 {
+            Some(path) => Some(path.clone()),
+            None if remote => None,
+            None => Some(self.from.1.with_extension(get_extension_for_type(&to))),
+        };
+        if let Some(output_path) = output_path && let Err(reason) = self.check_writable(&output_path) {
+            errors.push(ConvertError::OutputNotWritable { path: output_path, reason });
+        }
+
+        let mut options = self.options.clone();
+        let mut problems = options.problems();
+        if let Err(error) = options::apply_raw_options(&mut options, to, &self.raw_options) {
+            problems.push(error.to_string());
+        }
+        if let Some(dir) = &self.temp_dir && !dir.is_dir() {
+            problems.push(format!("The temp_dir {} is not a directory", dir.display()));
+        }
+        if self.best_effort && self.split_channels {
+            problems.push("best_effort and split_channels can't be combined, only best effort conversion would run".to_string());
+        }
+        if self.via.is_some() && (self.best_effort || self.split_channels) {
+            problems.push("via is ignored by best effort and split channel conversions".to_string());
+        }
+        if self.best_effort && from != FileType::Unknown && !matches!(from, FileType::Image(_)) {
+            problems.push(format!("Best effort conversion needs an image source, got {:?}", from));
+        }
+        if self.split_channels && from != FileType::Unknown && !matches!(from, FileType::Audio(_)) {
+            problems.push(format!("Splitting channels needs an audio source, got {:?}", from));
+        }
+        errors.extend(problems.into_iter().map(|reason| ConvertError::InvalidOptions { reason }));
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Checks that `output_path` can be written by creating and deleting a file next to it, or
+    /// in the closest existing directory if [`Self::create_dirs`] would create its parent.
+    fn check_writable(&self, output_path: &Path) -> Result<(), String> {
+        if output_path.is_dir() {
+            return Err("it is a directory".to_string());
+        }
+        if self.overwrite == Some(false) && output_path.exists() {
+            return Err("it already exists and overwriting is off".to_string());
+        }
+        if std::fs::metadata(output_path).is_ok_and(|metadata| metadata.permissions().readonly()) {
+            return Err("it is read-only".to_string());
+        }
+
+        let parent = output_path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let dir = if parent.is_dir() {
+            parent
+        } else if !self.create_dirs {
+            return Err(format!("the directory {} does not exist, use create_dirs to create it", parent.display()));
+        } else {
+            match parent.ancestors().skip(1).find(|ancestor| ancestor.exists()) {
+                Some(ancestor) if ancestor.is_dir() => ancestor,
+                Some(ancestor) => return Err(format!("{} is not a directory", ancestor.display())),
+                None => return Ok(()),
+            }
+        };
+
+        let probe = self.temp_path(&dir.join(output_path.file_name().unwrap_or_default()), "validate", &self.to.0);
+        std::fs::OpenOptions::new().write(true).create_new(true).open(&probe)
+            .map_err(|error| format!("cannot create files in {}: {}", dir.display(), error))?;
+        let _ = std::fs::remove_file(&probe);
+        Ok(())
+    }
+
+    pub fn convert(mut self) -> anyhow::Result<ConversionReport> {
+        let registry = self.prepare_registry()?;
+
+        // Kept alive until the conversion is done, dropping it deletes the download.
+        #[cfg(feature = "remote")]
+        let _download = match self.url.take() {
+            Some(url) => {
+                let download = remote::download(&url, self.max_download_bytes.unwrap_or(remote::DEFAULT_MAX_DOWNLOAD_BYTES))?;
+                self.from = (download.file_type, download.path.clone());
+                if self.to.1.is_none() {
+                    self.to.1 = Some(PathBuf::from(&download.file_name).with_extension(get_extension_for_type(&self.to.0)));
+                }
+                Some(download)
+            }
+            None => None,
+        };
+
+        if self.from.0 == FileType::Unknown {
+            return Err(anyhow::anyhow!("Source file type not specified"));
+        }
+        
+        if self.to.0 == FileType::Unknown {
+            return Err(anyhow::anyhow!("Target file type not specified"));
+        }
+        
+        let output_path = match self.to.1.clone() {
+            Some(path) => path,
+            None => {
+                let mut output = self.from.1.clone();
+                output.set_extension(get_extension_for_type(&self.to.0));
+                output
+            }
+        };
+        
+        self.convert_file(&registry, self.from.0, &self.from.1, &output_path)
+    }
+
+    /// Like [`Self::convert`] for async code such as request handlers: the conversion runs on
+    /// a thread of its own and the returned future resolves with its report once it is done,
+    /// without blocking the executor. Works with any async runtime. Needs the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn convert_async(self) -> impl std::future::Future<Output = anyhow::Result<ConversionReport>> + Send {
+        let task = background::spawn(move || self.convert());
+        async move {
+            match task?.await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("The conversion panicked")),
+            }
+        }
+    }
+
+    /// Runs the conversions listed in the JSON manifest at `path` with these settings, so a
+    /// batch can be kept under version control and repeated. The manifest is an array of jobs,
+    /// or an object listing them under `jobs`, each like `{"input": "a.png", "output": "a.jpg",
+    /// "options": {"jpeg.quality": 80}}`. Relative paths are relative to the manifest, the types
+    /// come from the extensions unless a job names them with `from` and `to`, and `options` are
+    /// [`Self::raw_options`] added to those of the settings. The input and output of the
+    /// settings are ignored. Failed jobs are listed in [`BatchReport::failed`] and the others
+    /// still run.
+    pub fn from_manifest(mut self, path: impl AsRef<Path>) -> anyhow::Result<BatchReport> {
+        let jobs = manifest::read(path.as_ref())?;
+        let raw_options = self.raw_options.clone();
+        let registry = self.prepare_registry()?;
+        let options = self.options.clone();
+
+        let mut report = BatchReport::default();
+        for job in &jobs {
+            let mut raw = raw_options.clone();
+            raw.extend(job.raw_options.clone());
+            self.options = options.clone();
+            self.to = (job.to, Some(job.output.clone()));
+
+            let result = options::apply_raw_options(&mut self.options, job.to, &raw)
+                .and_then(|()| self.convert_file(&registry, job.from, &job.input, &job.output));
+            match result {
+                Ok(converted) => report.converted.push(converted),
+                Err(err) => report.failed.push((job.input.clone(), err.to_string())),
+            }
+        }
+        report.print_failures(jobs.len());
+        Ok(report)
+    }
+
+    /// Watches `dir` on a background thread and converts every file of the source type that
+    /// appears or changes in it to the target type, next to the file. The source type can be
+    /// left unset to convert any known type. Files already in the directory are left alone and
+    /// files are only picked up once they stopped changing for [`watch::DEBOUNCE`]. Failures are
+    /// printed and the watch goes on until the returned [`watch::Watcher`] is stopped.
+    #[cfg(feature = "watch")]
+    pub fn watch(mut self, dir: PathBuf) -> anyhow::Result<watch::Watcher> {
+        if self.to.0 == FileType::Unknown {
+            return Err(anyhow::anyhow!("Target file type not specified"));
+        }
+
+        let registry = self.prepare_registry()?;
+        watch::start(self, registry, dir)
+    }
+
+    /// Converts the source to the target image type and returns the result as a base64 data
+    /// URI, like `data:image/jpeg;base64,...`, for embedding in HTML or CSS. The output path is
+    /// ignored, nothing is left on disk.
+    pub fn to_data_uri(mut self) -> anyhow::Result<String> {
+        let FileType::Image(to) = self.to.0 else {
+            return Err(anyhow::anyhow!("Data URIs need an image target type, not {:?}", self.to.0));
+        };
+
+        let temp = std::env::temp_dir().join(format!(
+            "phase_change_data_uri_{}_{}.{}",
+            std::process::id(),
+            DATA_URI_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            get_extension_for_type(&self.to.0)
+        ));
+        self.to.1 = Some(temp.clone());
+
+        let bytes = self.convert().and_then(|_| std::fs::read(&temp).with_path("Cannot read", &temp));
+        let _ = std::fs::remove_file(&temp);
+        Ok(format!("data:{};base64,{}", to.mime_type(), base64::encode(&bytes?)))
+    }
+
+    /// Takes the registry out of the builder with the custom converters registered, and applies
+    /// the [`Self::raw_options`] meant for the target type.
+    pub(crate) fn prepare_registry(&mut self) -> anyhow::Result<Arc<ConverterRegistry>> {
+        options::apply_raw_options(&mut self.options, self.to.0, &std::mem::take(&mut self.raw_options))?;
+
+        let mut registry = self.registry.take().ok_or_else(|| anyhow::anyhow!("No converter registry available"))?;
+        
+        if !self.custom_converters.is_empty() || !self.custom_category_converters.is_empty() {
+            // Snapshots a registry shared with other builders.
+            let registry = Arc::make_mut(&mut registry);
+            for converter in std::mem::take(&mut self.custom_category_converters) {
+                registry.register_category(converter);
+            }
+            for converter in std::mem::take(&mut self.custom_converters) {
+                registry.register(converter);
+            }
+        }
+        
+        Ok(registry)
+    }
+
+    /// Converts `input` of type `from` to the target type using the builder's settings.
+    pub(crate) fn convert_file(&self, registry: &ConverterRegistry, from: FileType, input: &Path, output_path: &Path) -> anyhow::Result<ConversionReport> {
+        let from = self.check_source_type(from, input)?;
+        if self.overwrite == Some(false) && output_path.exists() {
+            return Err(ConvertError::OutputExists { path: output_path.to_path_buf() }.into());
+        }
+        self.ensure_output_dir(output_path)?;
+        self.progress.reset();
+
+        let mut intermediates = Vec::new();
+        let tiled = self.tiled && !self.best_effort && !self.split_channels && self.via.is_none()
+            && converters::image::tiled::convert(from, input, self.to.0, output_path, &self.options)?;
+        let result = if tiled {
+            ConversionReport::new(input, output_path, vec![from, self.to.0])
+        } else if self.best_effort {
+            self.convert_best_effort(registry, from, input, output_path, &mut intermediates)
+        } else if self.split_channels {
+            self.convert_split(registry, from, input, output_path, &mut intermediates)
+        } else {
+            self.convert_to(registry, from, input, self.to.0, output_path, &self.options, &mut intermediates)
+                .and_then(|steps| ConversionReport::new(input, output_path, steps))
+        };
+        if result.is_err() && !intermediates.is_empty() {
+            let kept: Vec<_> = intermediates.iter().map(|path| path.display().to_string()).collect();
+            println!("Kept the intermediate files of the failed conversion: {}", kept.join(", "));
+        }
+        let mut report = result?;
+        report.intermediates = intermediates;
+
+        if self.verify_output {
+            self.verify(&report)?;
+        }
+
+        if let Some(min_psnr) = self.min_psnr {
+            report.psnr = self.measure_psnr(&report)?;
+            if let Some(psnr) = report.psnr && psnr < min_psnr {
+                std::fs::remove_file(&report.output).with_path("Cannot delete", &report.output)?;
+                return Err(ConvertError::QualityTooLow { output: report.output, psnr, min_psnr }.into());
+            }
+        }
+
+        if let Some(comment) = &self.comment {
+            let written = match report.steps.last() {
+                Some(FileType::Image(format)) => converters::image::metadata::write_comment(&report.output, *format, comment)?,
+                _ => false,
+            };
+            if !written {
+                println!("Comments can only be embedded in JPEG and WebP files, {} is written without it", report.output.display());
+            }
+        }
+
+        if self.deterministic && let Some(FileType::Image(format)) = report.steps.last() {
+            converters::image::metadata::strip_timestamps(&report.output, *format)?;
+        }
+
+        // The metadata changes above may have changed the size.
+        if self.comment.is_some() || self.deterministic {
+            report.output_size = std::fs::metadata(&report.output).with_path("Cannot access", &report.output)?.len();
+        }
+
+        if self.write_sidecar {
+            let timestamp = (!self.deterministic).then(std::time::SystemTime::now);
+            report.write_sidecars(&self.options, timestamp)?;
+        }
+
+        if self.preserve_mtime && report.output != report.input {
+            let modified = std::fs::metadata(input).and_then(|metadata| metadata.modified()).with_path("Cannot access", input)?;
+            std::fs::File::options().write(true).open(&report.output)
+                .and_then(|file| file.set_modified(modified))
+                .with_path("Cannot set the modification time of", &report.output)?;
+        }
+
+        self.progress.report(1.0);
+        Ok(report)
     }
-}
 
-#[derive(Default)]
-pub struct FileConvertBuilder {
-    from: (FileType, PathBuf),
-    to: (FileType, Option<PathBuf>),
-    registry: Option<ConverterRegistry>,
-    custom_converters: Vec<Box<dyn Converter>>,
-}
+    /// Compares the declared type of `input` with the one detected from its contents, returning
+    /// the type to convert it as according to [`Self::on_type_mismatch`].
+    fn check_source_type(&self, declared: FileType, input: &Path) -> anyhow::Result<FileType> {
+        let mut magic = [0u8; 32];
+        let read = std::io::Read::read(&mut std::fs::File::open(input).with_path("Cannot read", input)?, &mut magic).with_path("Cannot read", input)?;
+        let Some(sniffed) = FileType::from_magic(&magic[..read]).filter(|sniffed| *sniffed != declared) else {
+            return Ok(declared);
+        };
 
-impl FileConvertBuilder {
-    pub fn new() -> Self {
-        Self {
-            registry: Some(ConverterRegistry::new()),
-            ..Default::default()
+        match self.on_type_mismatch {
+            TypeMismatchPolicy::TrustDeclared => {
+                println!("{} looks like {:?}, converting it as the declared {:?}", input.display(), sniffed, declared);
+                Ok(declared)
+            }
+            TypeMismatchPolicy::TrustSniffed => {
+                println!("{} was declared as {:?}, converting it as {:?} like its contents say", input.display(), declared, sniffed);
+                Ok(sniffed)
+            }
+            TypeMismatchPolicy::Error => Err(ConvertError::TypeMismatch { path: input.to_path_buf(), declared, sniffed }.into()),
         }
     }
 
-    pub fn from_file(&mut self, file_type: FileType, file_location: PathBuf) -> &mut Self {
-        self.from = (file_type, file_location);
-        self
+    /// Creates the parent directory of `output_path` if it is missing and [`Self::create_dirs`]
+    /// is enabled, errors otherwise.
+    fn ensure_output_dir(&self, output_path: &Path) -> anyhow::Result<()> {
+        let Some(parent) = output_path.parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+            return Ok(());
+        };
+        if parent.is_dir() {
+            return Ok(());
+        }
+        if !self.create_dirs {
+            return Err(anyhow::anyhow!("Output directory {} does not exist, use create_dirs to create it", parent.display()));
+        }
+
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.dir_mode {
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode);
+        }
+        builder.create(parent)
+            .map_err(|error| anyhow::anyhow!("Cannot create output directory {}: {}", parent.display(), error))
     }
 
-    pub fn to_file(&mut self, file_type: FileType, file_location: Option<PathBuf>) -> &mut Self {
-        self.to = (file_type, file_location);
-        self
+    /// Converts `input` to `to`, returning the path of types that was taken.
+    #[allow(clippy::too_many_arguments)]
+    fn convert_to(&self, registry: &ConverterRegistry, from: FileType, input: &Path, to: FileType, output_path: &Path, options: &ConvertOptions, intermediates: &mut Vec<PathBuf>) -> anyhow::Result<Vec<FileType>> {
+        if let Some(via) = &self.via {
+            return self.convert_via(registry, from, input, via, to, output_path, options, intermediates);
+        }
+
+        let max_hops = self.max_hops.unwrap_or(DEFAULT_MAX_HOPS);
+        let mut excluded = HashSet::new();
+        let mut last_error = None;
+        let mut too_long = None;
+        
+        for _ in 0..MAX_PATH_ATTEMPTS {
+            let path = if self.lossless_only {
+                registry.find_path_where(from, to, |key, converter| {
+                    converter.is_available() && !converter.is_lossy() && !excluded.contains(key)
+                })
+            } else {
+                registry.find_conversion_path_excluding(from, to, &excluded)
+            };
+            let Some(path) = path else {
+                break;
+            };
+            // Paths are the shortest ones, any other path would be at least as long.
+            if path.len() - 1 > max_hops {
+                too_long = Some(path.len() - 1);
+                break;
+            }
+            
+            match self.run_path(registry, input, &path, output_path, options, intermediates) {
+                Ok(()) => return Ok(path),
+                Err((step, error)) if self.try_alternate_paths => {
+                    println!("Conversion step {:?} -> {:?} failed, looking for another path: {}", step.0, step.1, error);
+                    excluded.insert(step);
+                    last_error = Some(error);
+                }
+                Err((_, error)) => return Err(error),
+            }
+        }
+        
+        if let Some(error) = last_error {
+            return Err(error);
+        }
+        
+        if let Some(steps) = too_long {
+            return Err(ConvertError::TooManySteps { from, to, steps, max_hops }.into());
+        }
+        
+        if self.lossless_only && let Some(path) = registry.find_conversion_path_excluding(from, to, &excluded) {
+            return Err(ConvertError::WouldLoseData { from, to, lossy_steps: registry.lossy_steps(&path) }.into());
+        }
+        
+        let missing = registry.missing_converters(from, to);
+        if !missing.is_empty() {
+            return Err(ConvertError::Unavailable { from, to, missing }.into());
+        }
+        
+        if registry.blocked_by_allowlist(from, to) {
+            return Err(ConvertError::NotAllowed { from, to }.into());
+        }
+        
+        Err(ConvertError::NoConversionPath { from, to }.into())
     }
 
-    pub fn with_converter<C>(&mut self, converter: C) -> &mut Self 
-    where
-        C: Converter + 'static,
-    {
-        self.custom_converters.push(Box::new(converter));
-        self
+    /// Checks the outputs of `report` as described in [`Self::verify_output`], deleting or
+    /// renaming them if one of them fails.
+    fn verify(&self, report: &ConversionReport) -> anyhow::Result<()> {
+        let Some(&to) = report.steps.last() else {
+            return Ok(());
+        };
+        let outputs = if report.channel_outputs.is_empty() { std::slice::from_ref(&report.output) } else { &report.channel_outputs[..] };
+
+        let check = |output: &Path| -> anyhow::Result<()> {
+            let bytes = std::fs::read(output).with_path("Cannot read", output)?;
+            if bytes.is_empty() {
+                return Err(anyhow::anyhow!("the file is empty"));
+            }
+            // Types that can't be detected from their contents, like TGA, are only decoded.
+            if let Some(sniffed) = FileType::from_magic(&bytes) && sniffed != to {
+                return Err(anyhow::anyhow!("its contents are {:?}, expected {:?}", sniffed, to));
+            }
+            match to {
+                FileType::Image(_) => converters::image::open_image(output, &self.options).map(|_| ()),
+                FileType::Audio(audio_file_type @ (AudioFileType::WAV | AudioFileType::AIFF)) => audio_file_type.decode_with_format(output).map(|_| ()),
+                FileType::Audio(AudioFileType::MP3 | AudioFileType::FLAC) | FileType::Text(_) | FileType::Document(_) | FileType::Video(_) | FileType::Unknown => Ok(()),
+            }
+        };
+
+        let Some((failed, error)) = outputs.iter().find_map(|output| check(output).err().map(|error| (output, error))) else {
+            return Ok(());
+        };
+        let mut corrupt = None;
+        for output in outputs {
+            match self.on_verify_failure {
+                VerifyFailurePolicy::DeleteOutput => std::fs::remove_file(output).with_path("Cannot delete", output)?,
+                VerifyFailurePolicy::KeepAsCorrupt => {
+                    let mut renamed = output.clone().into_os_string();
+                    renamed.push(".corrupt");
+                    std::fs::rename(output, &renamed).with_path("Cannot rename", output)?;
+                    if output == failed {
+                        corrupt = Some(PathBuf::from(renamed));
+                    }
+                }
+            }
+        }
+        Err(ConvertError::VerificationFailed { output: failed.clone(), reason: error.to_string(), corrupt }.into())
     }
 
-    pub fn with_converters(&mut self, converters: Vec<Box<dyn Converter>>) -> &mut Self {
-        self.custom_converters.extend(converters);
-        self
+    /// PSNR of the output of an image conversion against its source with the image
+    /// transformations applied, `None` for other conversions and outputs that can't be decoded.
+    fn measure_psnr(&self, report: &ConversionReport) -> anyhow::Result<Option<f64>> {
+        if !matches!((report.steps.first(), report.steps.last()), (Some(FileType::Image(_)), Some(FileType::Image(_)))) {
+            return Ok(None);
+        }
+
+        let mut reference = converters::image::open_image(&report.input, &self.options)?;
+        converters::image::apply_transforms(&mut reference, &self.options);
+        match converters::image::open_image(&report.output, &self.options) {
+            Ok(output) => Ok(Some(converters::image::psnr(&reference, &output))),
+            Err(error) => {
+                println!("Cannot check the quality of {}, it can't be decoded: {}", report.output.display(), error);
+                Ok(None)
+            }
+        }
     }
 
-    pub fn convert(mut self) -> anyhow::Result<()> {
-        let mut registry = self.registry.take().ok_or_else(|| anyhow::anyhow!("No converter registry available"))?;
-        
-        for converter in self.custom_converters {
-            registry.register(converter);
+    /// Writes every channel of an audio source to a file of its own, named after `output_path`
+    /// with a `_L`/`_R` suffix for stereo sources and the channel number otherwise.
+    fn convert_split(&self, registry: &ConverterRegistry, from: FileType, input: &Path, output_path: &Path, intermediates: &mut Vec<PathBuf>) -> anyhow::Result<ConversionReport> {
+        let FileType::Audio(audio_file_type) = from else {
+            return Err(anyhow::anyhow!("Splitting channels needs an audio source, got {:?}", from));
+        };
+
+        let (mut buffer, format) = audio_file_type.decode_with_format(input)?;
+        converters::audio::apply_transforms(&mut buffer, &self.options)?;
+
+        let stem = output_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let extension = get_extension_for_type(&self.to.0);
+        // The channels are already remixed, the conversions of the mono files must not do it again.
+        let options = self.options.without_audio_transforms();
+
+        let mut outputs = Vec::new();
+        let mut steps = Vec::new();
+        for channel in 0..buffer.channels {
+            let suffix = match (buffer.channels, channel) {
+                (2, 0) => "L".to_string(),
+                (2, _) => "R".to_string(),
+                _ => (channel + 1).to_string(),
+            };
+            let output = output_path.with_file_name(format!("{}_{}.{}", stem, suffix, extension));
+            let temp = self.intermediate_path(output_path, &format!("split_{}", suffix), &FileType::Audio(AudioFileType::WAV));
+
+            converters::audio::wav::encode(&buffer.channel(channel), format, Endian::Little, &temp)?;
+            let result = self.convert_to(registry, FileType::Audio(AudioFileType::WAV), &temp, self.to.0, &output, &options, intermediates);
+            if self.keep_intermediates {
+                intermediates.push(temp);
+            } else {
+                let _ = std::fs::remove_file(&temp);
+            }
+
+            steps = result?;
+            outputs.push(output);
         }
 
-        if self.from.0 == FileType::Unknown {
-            return Err(anyhow::anyhow!("Source file type not specified"));
+        let mut report = ConversionReport::new(input, &outputs[0], std::iter::once(from).chain(steps.into_iter().skip(1)).collect())?;
+        report.output_size = outputs.iter()
+            .map(|output| std::fs::metadata(output).map(|metadata| metadata.len()).with_path("Cannot access", output))
+            .sum::<anyhow::Result<u64>>()?;
+        report.channel_outputs = outputs;
+        Ok(report)
+    }
+
+    fn convert_best_effort(&self, registry: &ConverterRegistry, from: FileType, input: &Path, output_path: &Path, intermediates: &mut Vec<PathBuf>) -> anyhow::Result<ConversionReport> {
+        if !matches!(from, FileType::Image(_)) {
+            return Err(anyhow::anyhow!("Best effort conversion needs an image source, got {:?}", from));
         }
         
-        if self.to.0 == FileType::Unknown {
-            return Err(anyhow::anyhow!("Target file type not specified"));
-        }
+        let min_psnr = self.best_effort_min_psnr.unwrap_or(DEFAULT_BEST_EFFORT_MIN_PSNR);
+        let mut reference = converters::image::open_image(input, &self.options)?;
+        converters::image::apply_transforms(&mut reference, &self.options);
         
-        let output_path = match self.to.1 {
-            Some(path) => path,
-            None => {
-                let mut output = self.from.1.clone();
-                output.set_extension(get_extension_for_type(&self.to.0));
-                output
+        let mut best: Option<(u64, ImageFileType, PathBuf, Vec<FileType>)> = None;
+        for candidate in BEST_EFFORT_CANDIDATES {
+            let temp = self.temp_path(output_path, "best_effort", &FileType::Image(candidate));
+            
+            let steps = match self.convert_to(registry, from, input, FileType::Image(candidate), &temp, &self.options, intermediates) {
+                Ok(steps) => steps,
+                Err(error) => {
+                    println!("Skipping best effort candidate {:?}: {}", candidate, error);
+                    continue;
+                }
+            };
+            
+            // Only outputs that can be decoded again can be checked against the quality threshold.
+            let quality = match image::open(&temp) {
+                Ok(decoded) => converters::image::psnr(&reference, &decoded),
+                Err(error) => {
+                    println!("Skipping best effort candidate {:?}, its output can't be verified: {}", candidate, error);
+                    std::fs::remove_file(&temp).with_path("Cannot delete", &temp)?;
+                    continue;
+                }
+            };
+            
+            let size = std::fs::metadata(&temp).with_path("Cannot access", &temp)?.len();
+            if quality < min_psnr || best.as_ref().is_some_and(|(best_size, ..)| *best_size <= size) {
+                std::fs::remove_file(&temp).with_path("Cannot delete", &temp)?;
+                continue;
+            }
+            
+            if let Some((_, _, previous, _)) = best.replace((size, candidate, temp, steps)) {
+                std::fs::remove_file(&previous).with_path("Cannot delete", &previous)?;
             }
+        }
+        
+        let Some((_, format, temp, steps)) = best else {
+            return Err(anyhow::anyhow!("No best effort candidate reached a PSNR of {} dB", min_psnr));
         };
         
-        if registry.can_convert(self.from.0, self.to.0) {
-            return registry.convert(&self.from.0, &self.to.0, &self.from.1, &output_path);
-        }
+        let output_path = output_path.with_extension(get_extension_for_type(&FileType::Image(format)));
+        std::fs::rename(&temp, &output_path).with_path(&format!("Cannot rename {} to", temp.display()), &output_path)?;
         
-        if let Some(path) = registry.find_conversion_path(self.from.0, self.to.0) {
+        let mut report = ConversionReport::new(input, &output_path, steps)?;
+        report.chosen_format = Some(format);
+        Ok(report)
+    }
+
+    /// Runs every step of `path`, returning the step that failed alongside its error. The
+    /// intermediate files are deleted afterwards, or added to `intermediates` when
+    /// [`Self::keep_intermediates`] is enabled.
+    fn run_path(&self, registry: &ConverterRegistry, input: &Path, path: &[FileType], output_path: &Path, options: &ConvertOptions, intermediates: &mut Vec<PathBuf>) -> Result<(), ((FileType, FileType), anyhow::Error)> {
+        if path.len() > 2 {
             println!("Multi-step conversion path: {:?}", path);
-            
-            let mut current_input = self.from.1.clone();
-            
-            for window in path.windows(2) {
-                if let [from_type, to_type] = window {
-                    let temp_output = if to_type == &self.to.0 {
-                        output_path.clone()
-                    } else {
-                        let mut temp = current_input.clone();
-                        temp.set_extension(get_extension_for_type(to_type));
-                        temp.set_file_name(format!("temp_{}", temp.file_name().unwrap().to_string_lossy()));
-                        temp
+        }
+        
+        if path.len() == 1 {
+            return copy_unchanged(input, output_path).map_err(|error| ((path[0], path[0]), error));
+        }
+        
+        let mut current_input = input.to_path_buf();
+        let mut options = options.clone();
+        let mut temps = Vec::new();
+        
+        for (index, window) in path.windows(2).enumerate() {
+            if let [from_type, to_type] = window {
+                let temp_output = if index == path.len() - 2 {
+                    output_path.to_path_buf()
+                } else {
+                    let temp = self.intermediate_path(output_path, &format!("step{}", index + 1), to_type);
+                    temps.push(temp.clone());
+                    temp
+                };
+                
+                if let Err(error) = self.run_step(registry, *from_type, *to_type, &current_input, &temp_output, &options) {
+                    self.finish_temps(temps, intermediates);
+                    return Err(((*from_type, *to_type), error));
+                }
+                current_input = temp_output;
+                // The last step is reported once the output is complete.
+                if index + 2 < path.len() && !self.best_effort && !self.split_channels {
+                    self.progress.report((index + 1) as f32 / (path.len() - 1) as f32);
+                }
+                
+                // Transforms only run on the first step that decodes an image or audio, otherwise
+                // they would be applied once per intermediate format. Steps producing an image
+                // from audio, like a spectrogram, have applied the image transforms as well.
+                for file_type in [from_type, to_type] {
+                    options = match file_type {
+                        FileType::Image(_) => options.without_image_transforms(),
+                        FileType::Audio(_) => options.without_audio_transforms(),
+                        FileType::Text(_) | FileType::Document(_) | FileType::Video(_) | FileType::Unknown => options,
                     };
-                    
-                    registry.convert(from_type, to_type, &current_input, &temp_output)?;
-                    current_input = temp_output;
                 }
             }
-            
-            return Ok(());
         }
         
-        Err(anyhow::anyhow!("No conversion path available from {:?} to {:?}", self.from.0, self.to.0))
+        self.finish_temps(temps, intermediates);
+        Ok(())
+    }
+
+    /// Deletes the intermediate files in `temps`, or moves the ones that were written to
+    /// `intermediates` if they should be kept.
+    fn finish_temps(&self, temps: Vec<PathBuf>, intermediates: &mut Vec<PathBuf>) {
+        if self.keep_intermediates {
+            intermediates.extend(temps.into_iter().filter(|temp| temp.exists()));
+        } else {
+            for temp in temps {
+                let _ = std::fs::remove_file(temp);
+            }
+        }
+    }
+
+    /// A [`temp_path`] starting with the prefix set by [`Self::temp_prefix`].
+    fn temp_path(&self, next_to: &Path, label: &str, file_type: &FileType) -> PathBuf {
+        temp_path(next_to, self.temp_prefix.as_deref().unwrap_or("temp"), label, file_type)
+    }
+
+    /// Like [`Self::temp_path`], in [`Self::temp_dir`] if one is set.
+    fn intermediate_path(&self, next_to: &Path, label: &str, file_type: &FileType) -> PathBuf {
+        let path = self.temp_path(next_to, label, file_type);
+        match (&self.temp_dir, path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => path,
+        }
+    }
+
+    /// Runs a single converter, retrying it as configured by [`FileConvertBuilder::retries`].
+    fn run_step(&self, registry: &ConverterRegistry, from: FileType, to: FileType, input: &Path, output: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        let mut delay = RETRY_BASE_DELAY;
+        let mut attempt = 0;
+        
+        loop {
+            match registry.convert(&from, &to, input, output, options) {
+                // A ConvertError means the conversion can't work at all, retrying won't help.
+                Err(error) if attempt < self.retries && error.downcast_ref::<ConvertError>().is_none() => {
+                    attempt += 1;
+                    println!("Converter {:?} -> {:?} failed, retrying ({}/{}): {}", from, to, attempt, self.retries, error);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+static TEMP_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// A path for an intermediate file of type `file_type`, in the directory of `next_to`. Names
+/// look like `{prefix}_{label}_{stem}_{pid}_{n}.{ext}` so that they can't clash with each other
+/// or with the inputs, whatever dots the name of `next_to` contains or lacks.
+pub(crate) fn temp_path(next_to: &Path, prefix: &str, label: &str, file_type: &FileType) -> PathBuf {
+    let stem = next_to.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "output".to_string());
+
+    next_to.with_file_name(format!(
+        "{}_{}_{}_{}_{}.{}",
+        prefix,
+        label,
+        stem,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        get_extension_for_type(file_type)
+    ))
+}
+
+/// Copies a file that is already in the requested format, doing nothing if the output
+/// is the input itself.
+pub(crate) fn copy_unchanged(input: &Path, output: &Path) -> anyhow::Result<()> {
+    if output.exists() && std::fs::canonicalize(input).with_path("Cannot resolve", input)? == std::fs::canonicalize(output).with_path("Cannot resolve", output)? {
+        return Ok(());
     }
+    
+    std::fs::copy(input, output).with_path(&format!("Cannot copy {} to", input.display()), output)?;
+    Ok(())
 }
 
-fn get_extension_for_type(file_type: &FileType) -> &'static str {
+pub(crate) fn get_extension_for_type(file_type: &FileType) -> &'static str {
     match file_type {
         FileType::Unknown => "unknown",
         FileType::Image(image_file_type) => {
             match image_file_type {
                 ImageFileType::PNG => "png",
                 ImageFileType::JPEG => "jpg",
+                ImageFileType::ICO => "ico",
+                ImageFileType::WEBP => "webp",
+                ImageFileType::AVIF => "avif",
+                ImageFileType::GIF => "gif",
+                ImageFileType::PSD => "psd",
+                ImageFileType::TGA => "tga",
+                ImageFileType::QOI => "qoi",
+                ImageFileType::HDR => "hdr",
+                ImageFileType::EXR => "exr",
+                ImageFileType::PNM => "pnm",
             }
         },
         FileType::Audio(audio_file_type) => {
             match audio_file_type {
                 AudioFileType::MP3 => "mp3",
                 AudioFileType::WAV => "wav",
+                AudioFileType::AIFF => "aiff",
+                AudioFileType::FLAC => "flac",
             }
         },
+        FileType::Text(TextFileType::Ascii) => "txt",
+        FileType::Document(DocumentFileType::PDF) => "pdf",
+        FileType::Video(VideoFileType::MP4) => "mp4",
     }
 }
 
 pub trait Converter: Send + Sync {
-    fn convert(&self, input_path: &PathBuf, output_path: &PathBuf) -> anyhow::Result<()>;
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()>;
+    #[allow(clippy::wrong_self_convention)]
     fn from_type(&self) -> FileType;
     fn to_type(&self) -> FileType;
+
+    /// Whether the backend this converter relies on (e.g. a native library) can be used at
+    /// runtime. Unavailable converters are skipped when looking for a conversion path.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Whether this conversion discards information, e.g. by using a lossy codec.
+    fn is_lossy(&self) -> bool {
+        false
+    }
 }
 
+/// A converter handling every pair of types of one category, like a backend that decodes and
+/// encodes all image formats. Registered with [`ConverterRegistry::register_category`], it is
+/// used for the pairs no [`Converter`] is registered for.
+pub trait CategoryConverter: Send + Sync {
+    fn category(&self) -> FileCategory;
+    fn convert(&self, from: FileType, to: FileType, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()>;
+
+    /// Whether the converter can turn `from` into `to`, two types of its category. It isn't
+    /// registered for the other pairs.
+    fn supports(&self, _from: FileType, _to: FileType) -> bool {
+        true
+    }
+
+    /// Like [`Converter::is_available`].
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Like [`Converter::is_lossy`], for the conversion from `from` to `to`.
+    fn is_lossy(&self, _from: FileType, _to: FileType) -> bool {
+        false
+    }
+}
+
+/// A [`CategoryConverter`] bound to one of its pairs of types.
+struct CategoryStep {
+    converter: Arc<dyn CategoryConverter>,
+    from: FileType,
+    to: FileType,
+}
+
+impl Converter for CategoryStep {
+    fn convert(&self, input_path: &Path, output_path: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        self.converter.convert(self.from, self.to, input_path, output_path, options)
+    }
+
+    fn from_type(&self) -> FileType {
+        self.from
+    }
+
+    fn to_type(&self) -> FileType {
+        self.to
+    }
+
+    fn is_available(&self) -> bool {
+        self.converter.is_available()
+    }
+
+    fn is_lossy(&self) -> bool {
+        self.converter.is_lossy(self.from, self.to)
+    }
+}
+
+/// The converters every [`ConverterRegistry`] starts with. Making a new converter available
+/// by default only takes adding it here.
+pub const DEFAULT_CONVERTERS: &[fn() -> Box<dyn Converter>] = &[
+    || Box::new(PngToJpeg),
+    || Box::new(PngToPng),
+    || Box::new(IcoToPng::default()),
+    || Box::new(JpegToPng),
+    || Box::new(JpegToJpeg),
+    || Box::new(PngToWebp),
+    || Box::new(JpegToWebp),
+    || Box::new(PngToAvif),
+    || Box::new(WebpToPng),
+    || Box::new(AvifToPng),
+    || Box::new(PngToGif),
+    || Box::new(WebpToGif),
+    || Box::new(GifToWebp),
+    #[cfg(feature = "psd")]
+    || Box::new(converters::image::psd::PsdToPng),
+    #[cfg(feature = "exr")]
+    || Box::new(converters::image::exr::ExrToPng),
+    #[cfg(feature = "exr")]
+    || Box::new(converters::image::exr::PngToExr),
+    #[cfg(feature = "exr")]
+    || Box::new(converters::image::exr::HdrToExr),
+    #[cfg(feature = "exr")]
+    || Box::new(converters::image::exr::ExrToHdr),
+    || Box::new(TgaToPng),
+    || Box::new(PngToTga),
+    || Box::new(PnmToPng),
+    || Box::new(PngToPnm),
+    || Box::new(QoiToPng),
+    || Box::new(PngToQoi),
+    || Box::new(HdrToPng),
+    || Box::new(PngToHdr),
+    || Box::new(ImageToAscii::default()),
+    || Box::new(Mp3ToPng),
+    || Box::new(Mp3ToMp3),
+    || Box::new(WavToWav),
+    || Box::new(WavToPng),
+    #[cfg(feature = "video")]
+    || Box::new(converters::video::mp4::Mp4ToWebp),
+    #[cfg(feature = "video")]
+    || Box::new(converters::video::mp4::Mp4ToGif),
+    || Box::new(WavToAiff),
+    || Box::new(AiffToWav),
+    || Box::new(FlacToMp3),
+    #[cfg(feature = "pdf")]
+    || Box::new(converters::document::pdf::PngToPdf),
+];
+
+/// The converters available to a conversion, keyed by their pair of types.
+///
+/// A registry is only read while converting, so once it is set up it can be shared behind an
+/// [`Arc`] by any number of threads converting at the same time, see
+/// [`FileConvertBuilder::with_shared_registry`] and [`Self::convert`].
+#[derive(Clone)]
 pub struct ConverterRegistry {
-    converters: HashMap<(FileType, FileType), Box<dyn Converter>>,
+    /// Shared with the registry's snapshots, which don't copy the converters themselves.
+    converters: HashMap<(FileType, FileType), Arc<dyn Converter>>,
+    /// The pairs of the registered category converters, used where `converters` has none.
+    category_steps: HashMap<(FileType, FileType), Arc<dyn Converter>>,
+    /// Pairs still handled by the converter [`Self::from_defaults`] registered for them.
+    defaults: HashSet<(FileType, FileType)>,
+    /// Pairs registered more than once, not counting replaced defaults.
+    conflicts: Vec<(FileType, FileType)>,
+    /// The only pairs converters may be used for, see [`Self::set_allowlist`].
+    allowlist: Option<HashSet<(FileType, FileType)>>,
+}
+
+// Converters are `Send + Sync`, so is a registry of them.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ConverterRegistry>();
+};
+
+impl Default for ConverterRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConverterRegistry {
+    /// A registry with every converter in [`DEFAULT_CONVERTERS`].
     pub fn new() -> Self {
-        let mut registry = Self {
+        Self::from_defaults()
+    }
+
+    /// A registry without any converters.
+    pub fn empty() -> Self {
+        Self {
             converters: HashMap::new(),
-        };
+            category_steps: HashMap::new(),
+            defaults: HashSet::new(),
+            conflicts: Vec::new(),
+            allowlist: None,
+        }
+    }
+
+    /// A registry with every converter in [`DEFAULT_CONVERTERS`].
+    pub fn from_defaults() -> Self {
+        let mut registry = Self::empty();
         
-        registry.register(Box::new(PngToJpeg));
+        for converter in DEFAULT_CONVERTERS {
+            registry.register(converter());
+        }
+        registry.defaults = registry.converters.keys().copied().collect();
         
         registry
     }
 
+    /// Every pair of types with a registered converter, in no particular order.
+    pub fn supported_conversions(&self) -> Vec<(FileType, FileType)> {
+        self.entries().map(|(key, _)| *key).collect()
+    }
+
+    /// Registers `converter` for its pair of types, replacing any converter registered for
+    /// them before.
     pub fn register(&mut self, converter: Box<dyn Converter>) {
+        self.register_shared(Arc::from(converter));
+    }
+
+    /// Like [`Self::register`], for a converter that other registries may use as well.
+    pub fn register_shared(&mut self, converter: Arc<dyn Converter>) {
         let key = (converter.from_type(), converter.to_type());
-        self.converters.insert(key, converter);
+        if self.converters.insert(key, converter).is_some() && !self.defaults.remove(&key) {
+            self.conflicts.push(key);
+        }
+    }
+
+    /// Registers `converter` for every pair of types of its category it supports, replacing
+    /// the category converter registered for them before. Converters registered for a pair
+    /// with [`Self::register`] still take precedence.
+    pub fn register_category(&mut self, converter: Box<dyn CategoryConverter>) {
+        let converter: Arc<dyn CategoryConverter> = Arc::from(converter);
+        let category = converter.category();
+        self.category_steps.retain(|(from, _), _| from.category() != Some(category));
+
+        let types = category.types();
+        for &from in &types {
+            for &to in &types {
+                if converter.supports(from, to) {
+                    self.category_steps.insert((from, to), Arc::new(CategoryStep { converter: converter.clone(), from, to }));
+                }
+            }
+        }
+    }
+
+    /// The converter used from `from` to `to`: the one registered for the pair, else the
+    /// category converter handling it.
+    fn lookup(&self, from: FileType, to: FileType) -> Option<&Arc<dyn Converter>> {
+        self.converters.get(&(from, to)).or_else(|| self.category_steps.get(&(from, to)))
+    }
+
+    /// Every pair with the converter [`Self::lookup`] returns for it.
+    fn entries(&self) -> impl Iterator<Item = (&(FileType, FileType), &Arc<dyn Converter>)> {
+        self.converters.iter().chain(self.category_steps.iter().filter(|(key, _)| !self.converters.contains_key(key)))
+    }
+
+    /// A copy of the registry sharing its converters, for a server to adjust per request, e.g.
+    /// with its own allowlist or extra converters, without changing the registry every other
+    /// request uses. Only the map of converters is copied, which is cheap.
+    pub fn snapshot(&self) -> Self {
+        self.clone()
+    }
+
+    /// Restricts the registry to converters between the given pairs of types, for hosted
+    /// services that only want to offer some conversions. Other converters stay registered but
+    /// are never used, neither directly nor as a step of a longer path, and converting with
+    /// them fails with [`ConvertError::NotAllowed`].
+    pub fn set_allowlist(&mut self, pairs: HashSet<(FileType, FileType)>) {
+        self.allowlist = Some(pairs);
+    }
+
+    /// Whether the allowlist, if any, permits converting from `from` to `to` in one step.
+    pub fn is_allowed(&self, from: FileType, to: FileType) -> bool {
+        self.allowlist.as_ref().is_none_or(|allowlist| allowlist.contains(&(from, to)))
+    }
+
+    /// Whether `from` can only not be converted to `to` because of the allowlist.
+    pub(crate) fn blocked_by_allowlist(&self, from: FileType, to: FileType) -> bool {
+        self.allowlist.is_some() && self.search(from, to, |_, _| true).is_some() && self.find_path_where(from, to, |_, _| true).is_none()
+    }
+
+    /// Looks for registrations that are likely mistakes: pairs of types that were given several
+    /// converters of which only the last is used, and cycles containing a lossy converter, which
+    /// let data degrade a little more every time a file goes around them. Replacing a default
+    /// converter isn't reported. The default converters already form such cycles, PNG to JPEG
+    /// and back for one.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems: Vec<String> = self.conflicts.iter()
+            .map(|(from, to)| format!("{:?} -> {:?} has conflicting converters, only the last one registered is used", from, to))
+            .collect();
+
+        let mut cycles: Vec<Vec<FileType>> = Vec::new();
+        for (&(from, to), converter) in self.entries() {
+            if !converter.is_lossy() {
+                continue;
+            }
+
+            // A lossy step is part of a cycle if its source can be reached again from its target.
+            let mut cycle = if from == to {
+                vec![from]
+            } else {
+                match self.find_path_where(to, from, |_, _| true) {
+                    Some(back) => std::iter::once(from).chain(back[..back.len() - 1].iter().copied()).collect(),
+                    None => continue,
+                }
+            };
+
+            // Rotate the cycle to a canonical start so each is only reported once.
+            let start = (0..cycle.len()).min_by_key(|&i| format!("{:?}", cycle[i])).unwrap_or(0);
+            cycle.rotate_left(start);
+            if !cycles.contains(&cycle) {
+                cycles.push(cycle);
+            }
+        }
+
+        for mut cycle in cycles {
+            cycle.push(cycle[0]);
+            let steps = cycle.iter().map(|file_type| format!("{:?}", file_type)).collect::<Vec<_>>();
+            problems.push(format!("Lossy conversion cycle: {}", steps.join(" -> ")));
+        }
+
+        if problems.is_empty() { Ok(()) } else { Err(problems) }
+    }
+
+    /// The converter registered from `from` to `to`, or the category converter handling the
+    /// pair, whether or not it is available.
+    pub fn get(&self, from: FileType, to: FileType) -> Option<&dyn Converter> {
+        self.lookup(from, to).map(|converter| converter.as_ref())
     }
 
+    /// Whether a converter is registered from `from` to `to`, directly or for their category,
+    /// whether or not it is available or allowed.
+    pub fn is_registered(&self, from: FileType, to: FileType) -> bool {
+        self.lookup(from, to).is_some()
+    }
+
+    /// Whether a single converter can turn `from` into `to`: one is registered for the pair,
+    /// it is available and the allowlist permits it. [`Self::convert`] needs this.
+    pub fn has_direct(&self, from: FileType, to: FileType) -> bool {
+        self.is_allowed(from, to) && self.lookup(from, to).is_some_and(|converter| converter.is_available())
+    }
+
+    /// Whether `from` can be turned into `to` at all, directly or through intermediate formats
+    /// as the builder would. Unlike [`Self::has_direct`], this holds for pairs like JPEG to GIF
+    /// that go through PNG.
     pub fn can_convert(&self, from: FileType, to: FileType) -> bool {
-        self.converters.contains_key(&(from.clone(), to.clone()))
+        self.find_conversion_path(from, to).is_some()
     }
     
-    pub fn convert(&self, from: &FileType, to: &FileType, input: &PathBuf, output: &PathBuf) -> anyhow::Result<()> {
-        let key = (from.clone(), to.clone());
-        match self.converters.get(&key) {
-            Some(converter) => converter.convert(input, output),
+    /// Converts `input` with the converter registered for `from` and `to`, without looking
+    /// for a longer path. Only borrows the registry, so threads sharing it can convert at the
+    /// same time.
+    pub fn convert(&self, from: &FileType, to: &FileType, input: &Path, output: &Path, options: &ConvertOptions) -> anyhow::Result<()> {
+        if !self.is_allowed(*from, *to) {
+            return Err(ConvertError::NotAllowed { from: *from, to: *to }.into());
+        }
+
+        match self.lookup(*from, *to) {
+            Some(converter) if converter.is_available() => converter.convert(input, output, options),
+            Some(_) => Err(ConvertError::Unavailable { from: *from, to: *to, missing: vec![(*from, *to)] }.into()),
             None => Err(anyhow::anyhow!("No converter available from {:?} to {:?}", from, to)),
         }
     }
 
     pub fn find_conversion_path(&self, from: FileType, to: FileType) -> Option<Vec<FileType>> {
+        self.find_path_where(from, to, |_, converter| converter.is_available())
+    }
+
+    /// Like [`Self::find_conversion_path`], but only returns paths of at most `max_hops`
+    /// converters.
+    pub fn find_conversion_path_bounded(&self, from: FileType, to: FileType, max_hops: usize) -> Option<Vec<FileType>> {
+        // The search finds the shortest path, so no other path fits if it doesn't.
+        self.find_conversion_path(from, to).filter(|path| path.len() - 1 <= max_hops)
+    }
+
+    /// Like [`Self::find_conversion_path`], but never uses the converters for the `excluded` pairs.
+    pub fn find_conversion_path_excluding(&self, from: FileType, to: FileType, excluded: &HashSet<(FileType, FileType)>) -> Option<Vec<FileType>> {
+        self.find_path_where(from, to, |key, converter| converter.is_available() && !excluded.contains(key))
+    }
+
+    /// Lists the unavailable converters on the path that would be used from `from` to `to`
+    /// if every registered converter was available. Empty if no such path exists.
+    pub fn missing_converters(&self, from: FileType, to: FileType) -> Vec<(FileType, FileType)> {
+        let Some(path) = self.find_path_where(from, to, |_, _| true) else {
+            return Vec::new();
+        };
+
+        path.windows(2)
+            .map(|step| (step[0], step[1]))
+            .filter(|(from, to)| self.lookup(*from, *to).is_some_and(|converter| !converter.is_available()))
+            .collect()
+    }
+
+    /// Lists the steps of `path` whose converters are lossy.
+    pub fn lossy_steps(&self, path: &[FileType]) -> Vec<(FileType, FileType)> {
+        path.windows(2)
+            .map(|step| (step[0], step[1]))
+            .filter(|(from, to)| self.lookup(*from, *to).is_some_and(|converter| converter.is_lossy()))
+            .collect()
+    }
+
+    /// Breadth-first search for the shortest path only using the converters `usable` accepts,
+    /// and the allowlist permits.
+    pub(crate) fn find_path_where<F>(&self, from: FileType, to: FileType, usable: F) -> Option<Vec<FileType>>
+    where
+        F: Fn(&(FileType, FileType), &dyn Converter) -> bool,
+    {
+        self.search(from, to, |key, converter| self.is_allowed(key.0, key.1) && usable(key, converter))
+    }
+
+    fn search<F>(&self, from: FileType, to: FileType, usable: F) -> Option<Vec<FileType>>
+    where
+        F: Fn(&(FileType, FileType), &dyn Converter) -> bool,
+    {
+        // A same type conversion uses a converter registered for it, e.g. to re-encode with
+        // different options, and otherwise is a single step path the builder copies.
         if from == to {
-            return Some(vec![from.clone()]);
+            let direct = self.lookup(from, to)
+                .is_some_and(|converter| usable(&(from, to), converter.as_ref()));
+            return Some(if direct { vec![from, to] } else { vec![from] });
         }
         
         let mut queue = VecDeque::new();
         let mut visited = HashSet::new();
         let mut parent: HashMap<FileType, FileType> = HashMap::new();
         
-        queue.push_back(from.clone());
-        visited.insert(from.clone());
+        queue.push_back(from);
+        visited.insert(from);
         
         while let Some(current) = queue.pop_front() {
             if current == to {
-                let mut path = vec![current.clone()];
+                let mut path = vec![current];
                 let mut node = current;
                 
                 while let Some(p) = parent.get(&node) {
-                    path.push(p.clone());
-                    node = p.clone();
+                    path.push(*p);
+                    node = *p;
                 }
                 
                 path.reverse();
                 return Some(path);
             }
             
-            for ((from_type, to_type), _) in &self.converters {
-                if *from_type == current && !visited.contains(to_type) {
-                    visited.insert(to_type.clone());
-                    parent.insert(to_type.clone(), current.clone());
-                    queue.push_back(to_type.clone());
+            for (key, converter) in self.entries() {
+                let (from_type, to_type) = key;
+                if *from_type == current && !visited.contains(to_type) && usable(key, converter.as_ref()) {
+                    visited.insert(*to_type);
+                    parent.insert(*to_type, current);
+                    queue.push_back(*to_type);
                 }
             }
         }
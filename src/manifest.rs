@@ -0,0 +1,78 @@
+//! Conversion jobs listed in a manifest file, see [`crate::FileConvertBuilder::from_manifest`].
+
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+use crate::{error::PathContext, json::JsonValue, FileType};
+
+/// One conversion of a manifest.
+pub(crate) struct ManifestJob {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub from: FileType,
+    pub to: FileType,
+    /// Raw options of this job, on top of those of the settings.
+    pub raw_options: HashMap<String, String>,
+}
+
+/// The type named by `field` of a job, or the one of `path`'s extension if it has none.
+fn job_type(job: &JsonValue, field: &str, path: &Path, index: usize) -> anyhow::Result<FileType> {
+    let file_type = match job.get(field) {
+        Some(name) => {
+            let name = name.as_str().ok_or_else(|| anyhow::anyhow!("Job {} of the manifest has a {} that isn't a string", index, field))?;
+            FileType::from_extension(name).or_else(|| FileType::from_mime(name)).unwrap_or(FileType::Unknown)
+        }
+        None => FileType::from_path(path).unwrap_or(FileType::Unknown),
+    };
+    if file_type == FileType::Unknown {
+        return Err(anyhow::anyhow!("Cannot tell the file type of {} in job {} of the manifest", path.display(), index));
+    }
+    Ok(file_type)
+}
+
+/// Reads the jobs of a JSON manifest: an array of jobs, or an object listing them under
+/// `jobs`. Every job has an `input` and `output` path, relative ones being relative to the
+/// manifest, and may name the `from` and `to` types and map raw `options` to their values.
+pub(crate) fn read(path: &Path) -> anyhow::Result<Vec<ManifestJob>> {
+    let manifest = JsonValue::parse(&std::fs::read_to_string(path).with_path("Cannot read", path)?)
+        .map_err(|err| anyhow::anyhow!("Invalid manifest {}: {}", path.display(), err))?;
+    let jobs = match manifest.get("jobs").unwrap_or(&manifest) {
+        JsonValue::Array(jobs) => jobs,
+        _ => return Err(anyhow::anyhow!("Manifest {} doesn't list any jobs", path.display())),
+    };
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    jobs.iter().enumerate()
+        .map(|(index, job)| {
+            let path_of = |field: &str| {
+                job.get(field).and_then(JsonValue::as_str)
+                    .map(|value| base.join(value))
+                    .ok_or_else(|| anyhow::anyhow!("Job {} of the manifest has no {} path", index, field))
+            };
+            let (input, output) = (path_of("input")?, path_of("output")?);
+
+            let mut raw_options = HashMap::new();
+            match job.get("options") {
+                Some(JsonValue::Object(options)) => {
+                    for (key, value) in options {
+                        let value = match value {
+                            JsonValue::String(value) => value.clone(),
+                            JsonValue::Number(_) | JsonValue::Bool(_) => value.to_string(),
+                            _ => return Err(anyhow::anyhow!("Option {} of job {} of the manifest isn't a string, number or boolean", key, index)),
+                        };
+                        raw_options.insert(key.clone(), value);
+                    }
+                }
+                None => {}
+                Some(_) => return Err(anyhow::anyhow!("The options of job {} of the manifest aren't an object", index)),
+            }
+
+            Ok(ManifestJob {
+                from: job_type(job, "from", &input, index)?,
+                to: job_type(job, "to", &output, index)?,
+                input,
+                output,
+                raw_options,
+            })
+        })
+        .collect()
+}
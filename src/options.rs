@@ -0,0 +1,506 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use image::{imageops::FilterType, DynamicImage};
+
+use crate::{converters::{audio::{mp3::BITRATES_V1_L3, wav::SampleFormat, AudioFileType}, document::DocumentFileType, image::ImageFileType}, json::JsonValue, FileType};
+
+/// A user supplied transformation applied to a decoded image before it is encoded again.
+pub type ImageFilter = Arc<dyn Fn(&mut DynamicImage) + Send + Sync>;
+
+/// Default value for [`ConvertOptions::max_pixels`], enough for a 16384x16384 image.
+pub const DEFAULT_MAX_PIXELS: u64 = 16384 * 16384;
+
+/// Resolution of images placed on PDF pages that don't declare one, one pixel per point.
+pub const DEFAULT_PDF_DPI: f32 = 72.0;
+
+/// Frames per second kept from videos turned into animations, smooth enough for previews.
+pub const DEFAULT_VIDEO_FPS: f32 = 10.0;
+
+/// How often animated GIFs play.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GifLoop {
+    #[default]
+    Forever,
+    /// Plays once, then repeats this many more times.
+    Repeat(u16),
+    /// Plays once and stops on the last frame.
+    Once,
+}
+
+/// Which palettes the frames of animated GIFs use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GifPalette {
+    /// Every frame gets its 256 colours of its own, the best quality for frames that differ.
+    #[default]
+    PerFrame,
+    /// Every frame shares one palette computed over all of them, which takes less space and
+    /// keeps colours from flickering between the frames of videos.
+    Global,
+}
+
+/// How the linear light of HDR images, which can be brighter than white, is brought into the
+/// range of 8-bit outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Clips everything brighter than white, keeping darker values exactly.
+    Clamp,
+    /// Reinhard's operator on the luminance, compressing highlights smoothly without shifting
+    /// hues.
+    #[default]
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, with more contrast than Reinhard.
+    Aces,
+}
+
+/// Settings of the WebP encoder. The default encodes losslessly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WebpEncodeOptions {
+    /// Near-lossless level from 0 (smallest) to 100 (lossless), see [`Self::near_lossless`].
+    pub near_lossless: Option<u8>,
+}
+
+impl WebpEncodeOptions {
+    /// Encodes losslessly after snapping the pixels to fewer distinct values, which compresses
+    /// much better at the cost of small per-pixel changes. Like libwebp, lower levels allow
+    /// larger changes: every 20 levels below 100 drop one more bit of precision per channel.
+    pub fn near_lossless(level: u8) -> Self {
+        Self { near_lossless: Some(level.min(100)) }
+    }
+}
+
+/// Sample formats [`WavEncodeOptions`] can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    Int16,
+    /// 24-bit signed integers, packed in three bytes.
+    Int24,
+    /// 32-bit IEEE floating point, which can't clip.
+    Float32,
+}
+
+impl From<PcmFormat> for SampleFormat {
+    fn from(format: PcmFormat) -> Self {
+        match format {
+            PcmFormat::Int16 => SampleFormat::I16,
+            PcmFormat::Int24 => SampleFormat::I24,
+            PcmFormat::Float32 => SampleFormat::F32,
+        }
+    }
+}
+
+/// Byte order of the fields and samples of a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Settings of the WAV encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavEncodeOptions {
+    pub format: PcmFormat,
+}
+
+/// Colours spectrogram magnitudes are mapped to, from the quietest to the loudest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Colormap {
+    Grayscale,
+    /// Dark blue through green to yellow, perceptually uniform and readable in grayscale.
+    #[default]
+    Viridis,
+    /// Black through purple and orange to pale yellow.
+    Magma,
+}
+
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84], [71, 44, 122], [59, 81, 139], [44, 113, 142], [33, 144, 141],
+    [39, 173, 129], [92, 200, 99], [170, 220, 50], [253, 231, 37],
+];
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4], [28, 16, 68], [79, 18, 123], [129, 37, 129], [181, 54, 122],
+    [229, 80, 100], [251, 135, 97], [254, 194, 135], [252, 253, 191],
+];
+
+/// Settings of the spectrograms WAV files are rendered as when converted to PNG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectrogramOptions {
+    /// Samples per transform, a power of two from 16 to 65536. The image is half as many pixels
+    /// high, larger sizes resolve frequencies more finely but blur quick changes.
+    pub fft_size: usize,
+    pub colormap: Colormap,
+}
+
+impl Default for SpectrogramOptions {
+    fn default() -> Self {
+        Self { fft_size: 1024, colormap: Colormap::default() }
+    }
+}
+
+impl Colormap {
+    /// The colour of `value` in `0.0..=1.0`, interpolated between the stops of the map.
+    pub(crate) fn color(self, value: f32) -> [u8; 3] {
+        let stops = match self {
+            Colormap::Grayscale => return [(value.clamp(0.0, 1.0) * 255.0).round() as u8; 3],
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+        };
+        let position = value.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let fraction = position - index as f32;
+        std::array::from_fn(|channel| {
+            let (low, high) = (stops[index][channel] as f32, stops[index + 1][channel] as f32);
+            (low + (high - low) * fraction).round() as u8
+        })
+    }
+}
+
+/// Options handed to every [`crate::Converter`] invocation.
+///
+/// Image transformations are applied in a fixed order once the source has been decoded and
+/// un-premultiplied if [`Self::premultiplied`] says so: black borders are cropped, the image
+/// is rotated, resized and scaled down to its maximum dimensions, then every image filter runs
+/// in the order it was added, and only then is the image encoded.
+/// Audio is remixed to the requested channels right after decoding, then normalized to the
+/// target loudness.
+#[derive(Clone)]
+pub struct ConvertOptions {
+    pub image_filters: Vec<ImageFilter>,
+    /// Whether the colour channels of image sources are premultiplied by their alpha, and so
+    /// divided by it after decoding. `None` follows the format, only EXRs are premultiplied.
+    pub premultiplied: Option<bool>,
+    /// Crops dark borders whose channels are all at most this value.
+    pub auto_crop_threshold: Option<u8>,
+    /// Number of clockwise quarter turns to rotate images by.
+    pub quarter_turns: u8,
+    /// Width and height to resize images to, ignoring their aspect ratio.
+    pub resize: Option<(u32, u32)>,
+    /// Width and height images are scaled down to fit in, keeping their aspect ratio. Smaller
+    /// images are left as they are.
+    pub max_dimensions: Option<(u32, u32)>,
+    /// Filter used by resizes that make an image larger, Catmull-Rom by default.
+    pub resize_up_filter: FilterType,
+    /// Filter used by resizes that make an image smaller, Lanczos by default.
+    pub resize_down_filter: FilterType,
+    /// Images declaring more pixels than this are rejected before being decoded.
+    pub max_pixels: u64,
+    /// Number of channels to remix audio to.
+    pub channels: Option<u16>,
+    /// Custom remix matrix, one row of input channel gains per output channel.
+    pub channel_matrix: Option<Vec<Vec<f32>>>,
+    /// Integrated loudness in LUFS to bring audio to, after remixing.
+    pub loudness_target: Option<f32>,
+    /// ID3v2 text frames to set on MP3 outputs by ID, like `TIT2` for the title, `None`
+    /// removing the frame.
+    pub id3_text: HashMap<String, Option<String>>,
+    /// Start and end MP3 outputs are trimmed to, at the nearest frame boundaries. `None` as
+    /// the end keeps the audio up to the end.
+    pub mp3_trim: Option<(Duration, Option<Duration>)>,
+    /// Whether to tag encoded images as sRGB. `None` does so for web formats only.
+    pub embed_srgb: Option<bool>,
+    pub tone_mapping: ToneMapping,
+    /// Stops HDR images are brightened by before tone mapping, 0 by default.
+    pub exposure: f32,
+    /// Output quality from 0 to 100 for lossy encoders. `None` keeps each codec's default.
+    pub quality: Option<u8>,
+    /// Whether to give encoded images an alpha channel: `Some(true)` adds an opaque one,
+    /// `Some(false)` composites translucent pixels over white and drops it, `None` keeps the
+    /// source's. Only formats that can store alpha are affected.
+    pub force_alpha: Option<bool>,
+    /// Whether to keep every frame of animated sources in outputs that would otherwise only
+    /// get the first one.
+    pub all_frames: bool,
+    /// Whether to dither images quantised to a 256 colour palette, on by default.
+    pub gif_dither: bool,
+    pub gif_loop: GifLoop,
+    pub gif_palette: GifPalette,
+    /// Whether to run-length encode TGA outputs, on by default.
+    pub tga_rle: bool,
+    /// Whether to write progressive JPEGs. `None` writes baseline ones, except when re-encoding
+    /// a JPEG, which keeps the mode of the source.
+    pub jpeg_progressive: Option<bool>,
+    /// Resolution images are placed on PDF pages at, which sets the size of the page. `None`
+    /// uses the one every image declares, or [`DEFAULT_PDF_DPI`] if it declares none.
+    pub pdf_dpi: Option<f32>,
+    /// Frames per second kept from videos turned into animations, [`DEFAULT_VIDEO_FPS`] if
+    /// `None`.
+    pub video_fps: Option<f32>,
+    /// How much of videos turned into animations is kept, from the start. `None` keeps all
+    /// of it.
+    pub video_max_duration: Option<Duration>,
+    pub webp: WebpEncodeOptions,
+    /// How WAV outputs are encoded. `None` keeps the sample format of the source.
+    pub wav: Option<WavEncodeOptions>,
+    /// Byte order of WAV outputs, little endian by default as the format specifies. Big endian
+    /// ones are RIFX files, which only some players read.
+    pub wav_endian: Endian,
+    /// Bitrate of MP3 outputs in kbit/s. `None` uses 192 kbit/s.
+    pub mp3_bitrate: Option<u32>,
+    /// How WAV files converted to images are rendered as spectrograms.
+    pub spectrogram: SpectrogramOptions,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            image_filters: Vec::new(),
+            premultiplied: None,
+            auto_crop_threshold: None,
+            quarter_turns: 0,
+            resize: None,
+            max_dimensions: None,
+            resize_up_filter: FilterType::CatmullRom,
+            resize_down_filter: FilterType::Lanczos3,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            channels: None,
+            channel_matrix: None,
+            loudness_target: None,
+            id3_text: HashMap::new(),
+            mp3_trim: None,
+            embed_srgb: None,
+            tone_mapping: ToneMapping::Reinhard,
+            exposure: 0.0,
+            quality: None,
+            force_alpha: None,
+            all_frames: false,
+            gif_dither: true,
+            gif_loop: GifLoop::Forever,
+            gif_palette: GifPalette::PerFrame,
+            tga_rle: true,
+            jpeg_progressive: None,
+            pdf_dpi: None,
+            video_fps: None,
+            video_max_duration: None,
+            webp: WebpEncodeOptions::default(),
+            wav: None,
+            wav_endian: Endian::Little,
+            mp3_bitrate: None,
+            spectrogram: SpectrogramOptions::default(),
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// Describes the values that would make conversions fail, for
+    /// [`FileConvertBuilder::validate`](crate::FileConvertBuilder::validate).
+    pub(crate) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, dimensions) in [("resize", self.resize), ("max_dimensions", self.max_dimensions)] {
+            if let Some((width, height)) = dimensions && (width == 0 || height == 0) {
+                problems.push(format!("Invalid {} of {}x{}, expected a width and height of at least 1", name, width, height));
+            }
+        }
+        if self.channels == Some(0) {
+            problems.push("Cannot remix audio to 0 channels".to_string());
+        }
+        if let Some(matrix) = &self.channel_matrix {
+            if matrix.is_empty() {
+                problems.push("The channel matrix has no rows".to_string());
+            } else if matrix.iter().any(|row| row.len() != matrix[0].len()) {
+                problems.push("The rows of the channel matrix have different numbers of gains".to_string());
+            }
+        }
+        if let Some((start, Some(end))) = self.mp3_trim && end <= start {
+            problems.push(format!("The MP3 trim ends at {:?}, not after its start at {:?}", end, start));
+        }
+        if !self.exposure.is_finite() {
+            problems.push(format!("Invalid exposure {}, expected a finite number of stops", self.exposure));
+        }
+        if let Some(dpi) = self.pdf_dpi && !(dpi > 0.0 && dpi.is_finite()) {
+            problems.push(format!("Invalid PDF resolution {} DPI, expected a positive number", dpi));
+        }
+        if let Some(fps) = self.video_fps && !(fps > 0.0 && fps.is_finite()) {
+            problems.push(format!("Invalid frame rate {}, expected a positive number", fps));
+        }
+        if let Some(bitrate) = self.mp3_bitrate && !BITRATES_V1_L3[1..].contains(&bitrate) {
+            problems.push(format!("Invalid MP3 bitrate {} kbit/s, expected one of {:?}", bitrate, &BITRATES_V1_L3[1..]));
+        }
+        let fft_size = self.spectrogram.fft_size;
+        if !fft_size.is_power_of_two() || !(16..=65536).contains(&fft_size) {
+            problems.push(format!("Invalid FFT size {}, expected a power of two from 16 to 65536", fft_size));
+        }
+        problems
+    }
+
+    /// Returns a copy of these options without any of the image transformations, which should
+    /// only happen once per conversion. Used for the later steps of a multi-step conversion, so
+    /// a filter isn't applied again every time the image is re-decoded.
+    pub fn without_image_transforms(&self) -> Self {
+        Self {
+            image_filters: Vec::new(),
+            // Intermediate files are written with the alpha their format expects.
+            premultiplied: None,
+            auto_crop_threshold: None,
+            quarter_turns: 0,
+            resize: None,
+            max_dimensions: None,
+            ..self.clone()
+        }
+    }
+
+    /// Whether any image transformation has been requested.
+    pub fn has_image_transforms(&self) -> bool {
+        !self.image_filters.is_empty() || self.premultiplied == Some(true) || self.auto_crop_threshold.is_some() || !self.quarter_turns.is_multiple_of(4) || self.resize.is_some() || self.max_dimensions.is_some()
+    }
+
+    /// Like [`Self::without_image_transforms`], for the audio transformations.
+    pub fn without_audio_transforms(&self) -> Self {
+        Self {
+            channels: None,
+            channel_matrix: None,
+            loudness_target: None,
+            ..self.clone()
+        }
+    }
+
+    /// Whether any audio transformation has been requested.
+    pub fn has_audio_transforms(&self) -> bool {
+        self.channels.is_some() || self.channel_matrix.is_some() || self.loudness_target.is_some()
+    }
+
+    /// Describes the options for sidecar files. Image filters are closures, only their number
+    /// is recorded.
+    pub(crate) fn to_json_value(&self) -> JsonValue {
+        let number = |value: u8| JsonValue::from(value as u64);
+        JsonValue::object([
+            ("image_filters", (self.image_filters.len() as u64).into()),
+            ("premultiplied", self.premultiplied.into()),
+            ("auto_crop_threshold", self.auto_crop_threshold.map(number).into()),
+            ("quarter_turns", number(self.quarter_turns)),
+            ("resize", self.resize.map(|(width, height)| JsonValue::Array(vec![(width as u64).into(), (height as u64).into()])).into()),
+            ("max_dimensions", self.max_dimensions.map(|(width, height)| JsonValue::Array(vec![(width as u64).into(), (height as u64).into()])).into()),
+            ("resize_up_filter", format!("{:?}", self.resize_up_filter).into()),
+            ("resize_down_filter", format!("{:?}", self.resize_down_filter).into()),
+            ("max_pixels", self.max_pixels.into()),
+            ("channels", self.channels.map(|channels| channels as u64).into()),
+            ("channel_matrix", self.channel_matrix.as_ref().map(|matrix| {
+                JsonValue::Array(matrix.iter().map(|row| JsonValue::Array(row.iter().map(|gain| (*gain as f64).into()).collect())).collect())
+            }).into()),
+            ("loudness_target", self.loudness_target.map(|target| target as f64).into()),
+            ("id3_text", JsonValue::object(self.id3_text.iter().map(|(id, text)| (id.clone(), text.clone().into())))),
+            ("mp3_trim", self.mp3_trim.map(|(start, end)| JsonValue::Array(vec![start.as_secs_f64().into(), end.map(|end| end.as_secs_f64()).into()])).into()),
+            ("embed_srgb", self.embed_srgb.into()),
+            ("tone_mapping", format!("{:?}", self.tone_mapping).into()),
+            ("exposure", (self.exposure as f64).into()),
+            ("quality", self.quality.map(number).into()),
+            ("force_alpha", self.force_alpha.into()),
+            ("all_frames", self.all_frames.into()),
+            ("gif_dither", self.gif_dither.into()),
+            ("gif_loop", format!("{:?}", self.gif_loop).into()),
+            ("gif_palette", format!("{:?}", self.gif_palette).into()),
+            ("tga_rle", self.tga_rle.into()),
+            ("jpeg_progressive", self.jpeg_progressive.into()),
+            ("pdf_dpi", self.pdf_dpi.map(|dpi| dpi as f64).into()),
+            ("video_fps", self.video_fps.map(|fps| fps as f64).into()),
+            ("video_max_duration", self.video_max_duration.map(|duration| duration.as_secs_f64()).into()),
+            ("webp_near_lossless", self.webp.near_lossless.map(number).into()),
+            ("wav_format", self.wav.map(|wav| format!("{:?}", wav.format)).into()),
+            ("wav_endian", format!("{:?}", self.wav_endian).into()),
+            ("mp3_bitrate", self.mp3_bitrate.map(|kbps| kbps as u64).into()),
+            ("spectrogram_fft_size", (self.spectrogram.fft_size as u64).into()),
+            ("spectrogram_colormap", format!("{:?}", self.spectrogram.colormap).into()),
+        ])
+    }
+}
+
+/// The options [`crate::FileConvertBuilder::raw_options`] understands, with the values they take.
+pub const RAW_OPTIONS: &[(&str, &str)] = &[
+    ("jpeg.quality", "0 to 100"),
+    ("jpeg.progressive", "true or false"),
+    ("avif.quality", "0 to 100"),
+    ("webp.lossless", "true"),
+    ("webp.near_lossless", "0 to 100"),
+    ("gif.dither", "true or false"),
+    ("gif.loop", "forever, once or a number of repeats"),
+    ("gif.palette", "per_frame or global"),
+    ("tga.rle", "true or false"),
+    ("pdf.dpi", "a positive number"),
+    ("wav.format", "int16, int24 or float32"),
+    ("wav.endian", "little or big"),
+    ("mp3.bitrate", "32 to 320 kbit/s"),
+];
+
+/// Options that exist in other tools but that the encoders here can't honour.
+const UNSUPPORTED_RAW_OPTIONS: &[&str] = &["webp.quality"];
+
+/// Applies the raw options whose format matches `target`.
+pub(crate) fn apply_raw_options(options: &mut ConvertOptions, target: FileType, raw: &HashMap<String, String>) -> anyhow::Result<()> {
+    let mut keys: Vec<_> = raw.keys().collect();
+    keys.sort();
+
+    for key in keys {
+        let value = raw[key].trim();
+        let Some((format, name)) = key.split_once('.') else {
+            println!("Ignoring raw option {}, expected a key like jpeg.quality", key);
+            continue;
+        };
+        let Some(format) = FileType::from_extension(format) else {
+            println!("Ignoring raw option {}, {} is not a known format", key, format);
+            continue;
+        };
+        if format != target {
+            continue;
+        }
+
+        let invalid = |expected: &str| anyhow::anyhow!("Invalid value {:?} for {}, expected {}", value, key, expected);
+        let percent = || value.parse::<u8>().ok().filter(|value| *value <= 100).ok_or_else(|| invalid("0 to 100"));
+        let boolean = || value.parse::<bool>().map_err(|_| invalid("true or false"));
+
+        match (format, name) {
+            (FileType::Image(ImageFileType::JPEG | ImageFileType::AVIF), "quality") => options.quality = Some(percent()?),
+            (FileType::Image(ImageFileType::JPEG), "progressive") => options.jpeg_progressive = Some(boolean()?),
+            (FileType::Image(ImageFileType::WEBP), "lossless") => {
+                if !boolean()? {
+                    return Err(anyhow::anyhow!("Raw option {}={} is not supported, WebPs can only be encoded losslessly", key, value));
+                }
+                options.webp = WebpEncodeOptions::default();
+            }
+            (FileType::Image(ImageFileType::WEBP), "near_lossless") => options.webp = WebpEncodeOptions::near_lossless(percent()?),
+            (FileType::Image(ImageFileType::GIF), "dither") => options.gif_dither = boolean()?,
+            (FileType::Image(ImageFileType::GIF), "loop") => {
+                options.gif_loop = match value.to_ascii_lowercase().as_str() {
+                    "forever" => GifLoop::Forever,
+                    "once" => GifLoop::Once,
+                    repeats => GifLoop::Repeat(repeats.parse().map_err(|_| invalid("forever, once or a number of repeats"))?),
+                };
+            }
+            (FileType::Image(ImageFileType::GIF), "palette") => {
+                options.gif_palette = match value.to_ascii_lowercase().as_str() {
+                    "per_frame" => GifPalette::PerFrame,
+                    "global" => GifPalette::Global,
+                    _ => return Err(invalid("per_frame or global")),
+                };
+            }
+            (FileType::Image(ImageFileType::TGA), "rle") => options.tga_rle = boolean()?,
+            (FileType::Document(DocumentFileType::PDF), "dpi") => {
+                options.pdf_dpi = Some(value.parse::<f32>().ok().filter(|dpi| *dpi > 0.0 && dpi.is_finite()).ok_or_else(|| invalid("a positive number"))?);
+            }
+            (FileType::Audio(AudioFileType::WAV), "format") => {
+                let format = match value.to_ascii_lowercase().as_str() {
+                    "int16" => PcmFormat::Int16,
+                    "int24" => PcmFormat::Int24,
+                    "float32" => PcmFormat::Float32,
+                    _ => return Err(invalid("int16, int24 or float32")),
+                };
+                options.wav = Some(WavEncodeOptions { format });
+            }
+            (FileType::Audio(AudioFileType::WAV), "endian") => {
+                options.wav_endian = match value.to_ascii_lowercase().as_str() {
+                    "little" => Endian::Little,
+                    "big" => Endian::Big,
+                    _ => return Err(invalid("little or big")),
+                };
+            }
+            _ if UNSUPPORTED_RAW_OPTIONS.contains(&key.as_str()) => println!("Ignoring raw option {}, it is not supported", key),
+            (FileType::Audio(AudioFileType::MP3), "bitrate") => {
+                options.mp3_bitrate = Some(value.parse().map_err(|_| invalid("32 to 320 kbit/s"))?);
+            }
+            _ if UNSUPPORTED_RAW_OPTIONS.contains(&key.as_str()) => {
+                return Err(anyhow::anyhow!("Raw option {} is not supported by the encoders here", key));
+            }
+            _ => println!("Ignoring unknown raw option {}", key),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Progress callbacks, see [`crate::FileConvertBuilder::on_progress`].
+
+use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+
+/// Called with the fraction of a conversion that is done, from 0.0 to 1.0.
+pub type ProgressFn = Arc<dyn Fn(f32) + Send + Sync>;
+
+/// Hands fractions to a [`ProgressFn`], leaving out those below one already reported so that a
+/// progress bar never goes backwards.
+#[derive(Default)]
+pub(crate) struct Progress {
+    pub callback: Option<ProgressFn>,
+    /// Bits of the highest fraction reported. Non-negative floats order like their bits.
+    reported: AtomicU32,
+}
+
+impl Progress {
+    pub fn new(callback: ProgressFn) -> Self {
+        Self { callback: Some(callback), reported: AtomicU32::new(0) }
+    }
+
+    pub fn report(&self, fraction: f32) {
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        let fraction = fraction.clamp(0.0, 1.0);
+        if fraction.to_bits() > self.reported.fetch_max(fraction.to_bits(), Ordering::Relaxed) {
+            callback(fraction);
+        }
+    }
+
+    /// Starts over, for the next conversion.
+    pub fn reset(&self) {
+        self.reported.store(0, Ordering::Relaxed);
+    }
+}
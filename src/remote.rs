@@ -0,0 +1,175 @@
+//! Fetching sources over the network, see [`crate::FileConvertBuilder::from_url`].
+
+use std::{io::{BufRead, BufReader, Read, Write}, net::TcpStream, path::PathBuf, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use crate::{error::PathContext, FileType};
+
+/// Default for [`crate::FileConvertBuilder::max_download_bytes`], 64 MiB.
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 64 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Responses with larger headers are rejected.
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+static DOWNLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A downloaded source in the temporary directory, deleted when dropped.
+pub(crate) struct Download {
+    pub path: PathBuf,
+    pub file_type: FileType,
+    /// The last segment of the URL path, for naming the output.
+    pub file_name: String,
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct Url<'a> {
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+fn parse_url(url: &str) -> anyhow::Result<Url<'_>> {
+    if url.starts_with("https://") {
+        return Err(anyhow::anyhow!("Cannot fetch {}: only plain HTTP URLs are supported", url));
+    }
+    let rest = url.strip_prefix("http://")
+        .ok_or_else(|| anyhow::anyhow!("Not an HTTP URL: {}", url))?;
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| anyhow::anyhow!("Invalid port in URL: {}", url))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(anyhow::anyhow!("URL has no host: {}", url));
+    }
+
+    Ok(Url { host, port, path })
+}
+
+/// Downloads `url` to a temporary file and detects its type from its contents. Redirects are
+/// followed, bodies larger than `max_bytes` are rejected.
+pub(crate) fn download(url: &str, max_bytes: u64) -> anyhow::Result<Download> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        match fetch(&url, max_bytes)? {
+            Response::Redirect(location) => {
+                url = if location.starts_with('/') {
+                    let parsed = parse_url(&url)?;
+                    format!("http://{}:{}{}", parsed.host, parsed.port, location)
+                } else {
+                    location
+                };
+            }
+            Response::Body(body) => {
+                let file_type = FileType::from_magic(&body)
+                    .ok_or_else(|| anyhow::anyhow!("Cannot tell the file type of {}", url))?;
+
+                let path = parse_url(&url)?.path;
+                let file_name = path.split(['?', '#']).next().unwrap_or_default()
+                    .rsplit('/').next().filter(|name| !name.is_empty())
+                    .unwrap_or("download")
+                    .to_string();
+
+                let temp = std::env::temp_dir().join(format!(
+                    "phase_change_download_{}_{}", std::process::id(), DOWNLOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::write(&temp, body).with_path("Cannot write", &temp)?;
+                return Ok(Download { path: temp, file_type, file_name });
+            }
+        }
+    }
+
+    Err(anyhow::anyhow!("Too many redirects fetching {}", url))
+}
+
+enum Response {
+    Redirect(String),
+    Body(Vec<u8>),
+}
+
+fn fetch(url: &str, max_bytes: u64) -> anyhow::Result<Response> {
+    let parsed = parse_url(url)?;
+    let mut stream = TcpStream::connect((parsed.host, parsed.port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: phase-change\r\nAccept-Encoding: identity\r\nConnection: close\r\n\r\n",
+        parsed.path, parsed.host
+    )?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status: u16 = status_line.split_whitespace().nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid HTTP response from {}", url))?;
+
+    let mut headers = Vec::new();
+    let mut header_bytes = status_line.len();
+    loop {
+        let mut line = String::new();
+        header_bytes += reader.read_line(&mut line)?;
+        if header_bytes > MAX_HEADER_BYTES {
+            return Err(anyhow::anyhow!("HTTP headers from {} are too large", url));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let header = |name: &str| headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+    if (300..400).contains(&status) && let Some(location) = header("location") {
+        return Ok(Response::Redirect(location.to_string()));
+    }
+    if !(200..300).contains(&status) {
+        return Err(anyhow::anyhow!("Fetching {} failed with HTTP status {}", url, status));
+    }
+
+    let too_large = || anyhow::anyhow!("{} is larger than the download limit of {} bytes", url, max_bytes);
+    if let Some(length) = header("content-length").and_then(|length| length.parse::<u64>().ok()) && length > max_bytes {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    if header("transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        loop {
+            let mut size_line = String::new();
+            reader.read_line(&mut size_line)?;
+            let size_hex = size_line.trim().split(';').next().unwrap_or_default();
+            let size = u64::from_str_radix(size_hex, 16)
+                .map_err(|_| anyhow::anyhow!("Invalid chunked response from {}", url))?;
+            if size == 0 {
+                break;
+            }
+            if body.len() as u64 + size > max_bytes {
+                return Err(too_large());
+            }
+
+            (&mut reader).take(size).read_to_end(&mut body)?;
+            // The CRLF after every chunk.
+            reader.read_line(&mut String::new())?;
+        }
+    } else {
+        // Read one byte more than allowed to tell a body of exactly the limit from a larger one.
+        reader.take(max_bytes + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > max_bytes {
+            return Err(too_large());
+        }
+    }
+
+    Ok(Response::Body(body))
+}
@@ -0,0 +1,147 @@
+use std::{path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+use crate::{converters::image::{animation, ImageFileType}, error::PathContext, json::JsonValue, sha256, ConvertOptions, FileType};
+
+/// The frames of a GIF or WebP file, still images have a single one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationInfo {
+    pub frames: usize,
+    /// The delays of every frame added up.
+    pub duration: Duration,
+}
+
+impl AnimationInfo {
+    fn read(path: &Path, file_type: Option<&FileType>) -> Option<Self> {
+        match file_type? {
+            FileType::Image(format @ (ImageFileType::GIF | ImageFileType::WEBP)) => animation::read_timing(path, *format).ok(),
+            _ => None,
+        }
+    }
+
+    fn to_json_value(self) -> JsonValue {
+        JsonValue::object([
+            ("frames", (self.frames as u64).into()),
+            ("duration_ms", (self.duration.as_millis() as u64).into()),
+        ])
+    }
+}
+
+/// Describes a finished conversion.
+#[derive(Debug, Clone)]
+pub struct ConversionReport {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Every type the file went through, starting with the source and ending with the target.
+    pub steps: Vec<FileType>,
+    pub input_size: u64,
+    pub output_size: u64,
+    /// The format picked by a [`crate::FileConvertBuilder::best_effort`] conversion.
+    pub chosen_format: Option<ImageFileType>,
+    /// The files written by a [`crate::FileConvertBuilder::split_channels`] conversion, in
+    /// channel order. `output` is the first of them and `output_size` their total size.
+    pub channel_outputs: Vec<PathBuf>,
+    /// The frames of a GIF or WebP source, to check against [`Self::output_animation`] that
+    /// none were dropped.
+    pub input_animation: Option<AnimationInfo>,
+    /// The frames of a GIF or WebP output.
+    pub output_animation: Option<AnimationInfo>,
+    /// The intermediate files of a [`crate::FileConvertBuilder::keep_intermediates`]
+    /// conversion, in the order they were written.
+    pub intermediates: Vec<PathBuf>,
+    /// PSNR of the output against the source in decibels, measured for
+    /// [`crate::FileConvertBuilder::min_psnr`].
+    pub psnr: Option<f64>,
+}
+
+impl ConversionReport {
+    pub(crate) fn new(input: &Path, output: &Path, steps: Vec<FileType>) -> anyhow::Result<Self> {
+        Ok(Self {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            input_size: std::fs::metadata(input).with_path("Cannot access", input)?.len(),
+            output_size: std::fs::metadata(output).with_path("Cannot access", output)?.len(),
+            chosen_format: None,
+            channel_outputs: Vec::new(),
+            input_animation: AnimationInfo::read(input, steps.first()),
+            output_animation: AnimationInfo::read(output, steps.last()),
+            intermediates: Vec::new(),
+            psnr: None,
+            steps,
+        })
+    }
+
+    /// Output size divided by input size, below 1.0 when the output got smaller.
+    pub fn compression_ratio(&self) -> f64 {
+        self.output_size as f64 / self.input_size.max(1) as f64
+    }
+
+    /// The format of the output file.
+    pub fn codec(&self) -> Option<FileType> {
+        self.steps.last().copied()
+    }
+
+    /// Serializes the report as a JSON object, e.g. so CI jobs can record conversion stats.
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+
+    pub(crate) fn to_json_value(&self) -> JsonValue {
+        JsonValue::object([
+            ("input", self.input.display().to_string().into()),
+            ("output", self.output.display().to_string().into()),
+            ("input_size", self.input_size.into()),
+            ("output_size", self.output_size.into()),
+            ("compression_ratio", self.compression_ratio().into()),
+            ("steps", JsonValue::Array(self.steps.iter().map(|step| format!("{:?}", step).into()).collect())),
+            ("codec", self.codec().map(|codec| format!("{:?}", codec)).into()),
+            ("chosen_format", self.chosen_format.map(|format| format!("{:?}", format)).into()),
+            ("channel_outputs", JsonValue::Array(self.channel_outputs.iter().map(|output| output.display().to_string().into()).collect())),
+            ("input_animation", self.input_animation.map(AnimationInfo::to_json_value).into()),
+            ("output_animation", self.output_animation.map(AnimationInfo::to_json_value).into()),
+            ("intermediates", JsonValue::Array(self.intermediates.iter().map(|path| path.display().to_string().into()).collect())),
+        ])
+    }
+
+    /// Writes a `<output>.json` sidecar next to every output, recording where it came from and
+    /// how: the source path and its SHA-256, the conversion steps, the options and when it
+    /// happened. Raw options are recorded through their effect on `options`. `timestamp` is
+    /// left out for deterministic conversions.
+    pub(crate) fn write_sidecars(&self, options: &ConvertOptions, timestamp: Option<SystemTime>) -> anyhow::Result<()> {
+        let sidecar = JsonValue::object([
+            ("source", self.input.display().to_string().into()),
+            ("source_sha256", sha256::hash_file(&self.input)?.into()),
+            ("steps", JsonValue::Array(self.steps.iter().map(|step| format!("{:?}", step).into()).collect())),
+            ("options", options.to_json_value()),
+            ("timestamp", timestamp.map(utc_timestamp).into()),
+        ]);
+
+        let outputs = if self.channel_outputs.is_empty() { std::slice::from_ref(&self.output) } else { self.channel_outputs.as_slice() };
+        for output in outputs {
+            let mut path = output.clone().into_os_string();
+            path.push(".json");
+            let JsonValue::Object(mut fields) = sidecar.clone() else { unreachable!() };
+            fields.insert(1, ("output".to_string(), output.display().to_string().into()));
+            std::fs::write(&path, JsonValue::Object(fields).to_string()).with_path("Cannot write", Path::new(&path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats `time` as an ISO 8601 UTC timestamp with second precision.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (days, second_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Converts days since 1970-01-01 to a civil date, counting 400 year eras from 0000-03-01.
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60)
+}
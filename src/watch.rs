@@ -0,0 +1,123 @@
+//! Converting files as they appear in a directory, see [`crate::FileConvertBuilder::watch`].
+
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::JoinHandle, time::{Duration, Instant, SystemTime}};
+
+use crate::{get_extension_for_type, glob, ConverterRegistry, FileConvertBuilder, FileType};
+
+/// How often the watched directory is scanned.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long a file has to stay unchanged before it is converted, so a file that is still being
+/// written isn't picked up halfway.
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// A directory being watched on a background thread. Dropping it stops the watch.
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    /// Stops watching, waiting for a conversion in progress to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// The last seen state of a file in the watched directory.
+struct Seen {
+    modified: SystemTime,
+    len: u64,
+    /// When the file was last seen changing, `None` once it has been handled.
+    changed_at: Option<Instant>,
+}
+
+pub(crate) fn start(settings: FileConvertBuilder, registry: Arc<ConverterRegistry>, dir: PathBuf) -> anyhow::Result<Watcher> {
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!("Cannot watch {}, it is not a directory", dir.display()));
+    }
+
+    // Files already there when the watch starts are left alone.
+    let mut seen = scan(&dir)?.into_iter()
+        .map(|(path, (modified, len))| (path, Seen { modified, len, changed_at: None }))
+        .collect::<HashMap<_, _>>();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_thread = stop.clone();
+    let handle = std::thread::spawn(move || {
+        let mut outputs = HashSet::new();
+        while !stop_thread.load(Ordering::Relaxed) {
+            std::thread::sleep(POLL_INTERVAL);
+            match scan(&dir) {
+                Ok(files) => poll(&settings, &registry, &mut seen, &mut outputs, files),
+                Err(err) => println!("Failed to scan {}: {}", dir.display(), err),
+            }
+        }
+    });
+
+    Ok(Watcher { stop, handle: Some(handle) })
+}
+
+fn scan(dir: &Path) -> anyhow::Result<HashMap<PathBuf, (SystemTime, u64)>> {
+    let mut files = HashMap::new();
+    for relative in glob::walk(dir, 1)? {
+        let path = dir.join(relative);
+        // The file may have been removed since it was listed.
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            files.insert(path, (metadata.modified()?, metadata.len()));
+        }
+    }
+    Ok(files)
+}
+
+fn poll(settings: &FileConvertBuilder, registry: &ConverterRegistry, seen: &mut HashMap<PathBuf, Seen>, outputs: &mut HashSet<PathBuf>, files: HashMap<PathBuf, (SystemTime, u64)>) {
+    seen.retain(|path, _| files.contains_key(path));
+
+    for (path, (modified, len)) in files {
+        let entry = seen.entry(path.clone()).or_insert(Seen { modified, len, changed_at: Some(Instant::now()) });
+        if (entry.modified, entry.len) != (modified, len) {
+            *entry = Seen { modified, len, changed_at: Some(Instant::now()) };
+            continue;
+        }
+        if entry.changed_at.is_none_or(|changed_at| changed_at.elapsed() < DEBOUNCE) {
+            continue;
+        }
+        entry.changed_at = None;
+
+        // Our own outputs and the intermediate files of multi-step conversions, which may be kept
+        // with `keep_intermediates`. Those carry the temp prefix or sit in the temp directory.
+        let prefix = format!("{}_", settings.temp_prefix.as_deref().unwrap_or("temp"));
+        let is_temp = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&prefix))
+            || settings.temp_dir.as_ref().is_some_and(|dir| path.starts_with(dir));
+        if outputs.contains(&path) || is_temp {
+            continue;
+        }
+        let from = match settings.from.0 {
+            FileType::Unknown => FileType::from_path(&path).unwrap_or_default(),
+            file_type if FileType::from_path(&path) == Some(file_type) => file_type,
+            _ => continue,
+        };
+        if from == FileType::Unknown || from == settings.to.0 {
+            continue;
+        }
+
+        let output = path.with_extension(get_extension_for_type(&settings.to.0));
+        match settings.convert_file(registry, from, &path, &output) {
+            Ok(_) => println!("Converted {} to {}", path.display(), output.display()),
+            Err(err) => println!("Failed to convert {}: {}", path.display(), err),
+        }
+        outputs.insert(output);
+    }
+}